@@ -0,0 +1,135 @@
+use wasm_bindgen::JsValue;
+
+use crate::utils::fetch::FetchError;
+
+/// Structured error kinds surfaced to JavaScript callers
+///
+/// Internally errors still travel as `anyhow::Error`; an `LlmError` anywhere
+/// in the chain determines the `kind` reported at the WASM boundary.
+#[derive(Debug, thiserror::Error)]
+pub enum LlmError {
+    #[error("Model not loaded. Call load() first.")]
+    NotLoaded,
+    #[error("Fetch failed ({}): {message}", status.map(|s| s.to_string()).unwrap_or_else(|| "network".to_string()))]
+    Fetch { status: Option<u16>, message: String },
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("Tokenization error: {0}")]
+    Tokenize(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Blocked by moderation: {0}")]
+    Blocked(String),
+    #[error("Embedding worker error: {0}")]
+    Worker(String),
+}
+
+impl LlmError {
+    /// Stable identifier for the error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LlmError::NotLoaded => "NotLoaded",
+            LlmError::Fetch { .. } => "Fetch",
+            LlmError::Parse(_) => "Parse",
+            LlmError::Tokenize(_) => "Tokenize",
+            LlmError::Config(_) => "Config",
+            LlmError::Blocked(_) => "Blocked",
+            LlmError::Worker(_) => "Worker",
+        }
+    }
+}
+
+impl From<FetchError> for LlmError {
+    fn from(err: FetchError) -> Self {
+        let status = match err {
+            FetchError::Http(status) => Some(status),
+            _ => None,
+        };
+        LlmError::Fetch {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Kind of an error chain (`"Internal"` when no `LlmError` is present)
+pub fn error_kind(err: &anyhow::Error) -> &'static str {
+    find_llm_error(err).map(LlmError::kind).unwrap_or("Internal")
+}
+
+/// Convert an error into a `{ kind, message, status? }` JavaScript object
+pub fn to_js_error(err: &anyhow::Error) -> JsValue {
+    let object = js_sys::Object::new();
+    let set = |key: &str, value: JsValue| {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from_str(key), &value);
+    };
+
+    set("kind", JsValue::from_str(error_kind(err)));
+    set("message", JsValue::from_str(&format!("{:#}", err)));
+    if let Some(LlmError::Fetch {
+        status: Some(status),
+        ..
+    }) = find_llm_error(err)
+    {
+        set("status", JsValue::from(*status));
+    }
+
+    object.into()
+}
+
+fn find_llm_error(err: &anyhow::Error) -> Option<&LlmError> {
+    err.chain().find_map(|cause| cause.downcast_ref::<LlmError>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{GenerationConfig, ModelConfig, PhiModel, TokenizerWrapper};
+    use anyhow::Context;
+
+    #[tokio::test]
+    async fn test_not_loaded_kind() {
+        let model = PhiModel::new(ModelConfig::default());
+        let err = model
+            .generate("hi", &GenerationConfig::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error_kind(&err), "NotLoaded");
+
+        let tokenizer = TokenizerWrapper::new(String::new());
+        assert_eq!(error_kind(&tokenizer.encode("hi").unwrap_err()), "NotLoaded");
+    }
+
+    #[tokio::test]
+    async fn test_config_kind() {
+        let mut model = PhiModel::new(ModelConfig::new(String::new(), String::new()));
+        let err = model.load().await.unwrap_err();
+        assert_eq!(error_kind(&err), "Config");
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        let err = TokenizerWrapper::from_bytes(b"not json")
+            .err()
+            .unwrap();
+        assert_eq!(error_kind(&err), "Parse");
+    }
+
+    #[test]
+    fn test_fetch_and_tokenize_kinds() {
+        let err = anyhow::Error::from(LlmError::from(FetchError::Http(503)))
+            .context("Failed to fetch model bytes");
+        assert_eq!(error_kind(&err), "Fetch");
+        assert!(matches!(
+            find_llm_error(&err),
+            Some(LlmError::Fetch { status: Some(503), .. })
+        ));
+
+        let err: anyhow::Error = Err::<(), _>(LlmError::Tokenize("bad input".into()))
+            .context("Generation failed")
+            .unwrap_err();
+        assert_eq!(error_kind(&err), "Tokenize");
+
+        assert_eq!(error_kind(&anyhow::anyhow!("something else")), "Internal");
+    }
+}
@@ -3,9 +3,16 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Context;
 use wasm_bindgen::prelude::*;
 
+use error::to_js_error;
+
 // Module declarations
+pub mod error;
 pub mod llm;
 pub mod rag;
 pub mod storage;
@@ -17,9 +24,16 @@ pub mod utils;
 // pub mod test_candle;
 
 // Re-exports for easy access
-pub use llm::{ModelConfig, PhiModel, GenerationConfig};
+pub use error::LlmError;
+pub use llm::{ChatMessage, ModelConfig, PhiModel, GenerationConfig};
+use llm::{PatternRedactor, RedactionFilter};
 pub use rag::{RagPipeline, Document, Chunk};
+use rag::{
+    ChunkingStrategy, DocumentMetadata, EmbeddingModel, MetadataFilter, RagSource, SearchResult,
+    VectorDatabase,
+};
 pub use storage::{IndexedDbStorage, MemoryCache};
+use utils::{FileParser, Utf8Decoding};
 
 /// Initialize the WASM module
 /// This sets up panic hooks and logging for better debugging
@@ -45,10 +59,29 @@ pub fn version() -> String {
 // LLM WASM Bindings
 // ============================================================================
 
+/// Parse a JavaScript generation config (`undefined`/`null` for defaults)
+fn parse_generation_config(config: JsValue) -> Result<GenerationConfig, JsValue> {
+    if config.is_undefined() || config.is_null() {
+        return Ok(GenerationConfig::default());
+    }
+    serde_wasm_bindgen::from_value(config)
+        .map_err(|e| to_js_error(&LlmError::Config(e.to_string()).into()))
+}
+
 /// WASM wrapper for PhiModel
 #[wasm_bindgen]
 pub struct WasmPhiModel {
-    inner: PhiModel,
+    /// Shared with open `ReadableStream`s returned by `generate_readable_stream`
+    inner: Rc<PhiModel>,
+}
+
+impl WasmPhiModel {
+    /// Mutable access to the model, unavailable while a stream is open
+    fn inner_mut(&mut self) -> Result<&mut PhiModel, JsValue> {
+        Rc::get_mut(&mut self.inner).ok_or_else(|| {
+            to_js_error(&LlmError::Config("Model is in use by an open stream".to_string()).into())
+        })
+    }
 }
 
 #[wasm_bindgen]
@@ -58,7 +91,7 @@ impl WasmPhiModel {
     pub fn new() -> Self {
         let config = ModelConfig::default();
         Self {
-            inner: PhiModel::new(config),
+            inner: Rc::new(PhiModel::new(config)),
         }
     }
 
@@ -67,37 +100,145 @@ impl WasmPhiModel {
     pub fn with_config(model_url: String, tokenizer_url: String) -> Self {
         let config = ModelConfig::new(model_url, tokenizer_url);
         Self {
-            inner: PhiModel::new(config),
+            inner: Rc::new(PhiModel::new(config)),
         }
     }
 
-    /// Load the model from configured URLs
+    /// Create a model from a HuggingFace repo (`owner/name`) and weights file
+    #[wasm_bindgen]
+    pub fn from_hf_repo(
+        repo: String,
+        filename: String,
+        revision: Option<String>,
+    ) -> Result<WasmPhiModel, JsValue> {
+        let revision = revision.as_deref().unwrap_or("main");
+        let config = ModelConfig::from_hf_repo_at_revision(&repo, &filename, revision)
+            .map_err(|e| to_js_error(&LlmError::Config(e).into()))?;
+        Ok(Self {
+            inner: Rc::new(PhiModel::new(config)),
+        })
+    }
+
+    /// Send `Authorization: Bearer <token>` when fetching model files
+    ///
+    /// Required for gated or private HuggingFace repos. Call before `load()`.
+    #[wasm_bindgen]
+    pub fn set_auth_token(&mut self, token: String) -> Result<(), JsValue> {
+        self.inner_mut()?.config_mut().set_auth_token(&token);
+        Ok(())
+    }
+
+    /// Replace the model configuration (as returned by `get_config`)
+    ///
+    /// Returns `true` when the change requires calling `load()` again.
+    /// Headers set with `set_auth_token` are kept unless the new config
+    /// provides its own.
+    #[wasm_bindgen]
+    pub fn update_config(&mut self, config: JsValue) -> Result<bool, JsValue> {
+        let mut config: ModelConfig = serde_wasm_bindgen::from_value(config)
+            .map_err(|e| to_js_error(&LlmError::Config(e.to_string()).into()))?;
+
+        let inner = self.inner_mut()?;
+        if config.headers.is_empty() {
+            config.headers = inner.config().headers.clone();
+        }
+
+        inner
+            .set_config(config)
+            .context("Invalid model configuration")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Redact email addresses and phone numbers from generated text
+    #[wasm_bindgen]
+    pub fn set_pii_redaction(&mut self, enabled: bool) -> Result<(), JsValue> {
+        let filter: Option<Box<dyn RedactionFilter>> = if enabled {
+            Some(Box::new(PatternRedactor::pii()))
+        } else {
+            None
+        };
+        self.inner_mut()?.set_redaction_filter(filter);
+        Ok(())
+    }
+
+    /// Load the model from configured URLs (tokenizer and weights are
+    /// downloaded concurrently)
     #[wasm_bindgen]
     pub async fn load(&mut self) -> Result<(), JsValue> {
-        self.inner
+        self.inner_mut()?
             .load()
             .await
-            .map_err(|e| JsValue::from_str(&format!("Failed to load model: {}", e)))
+            .context("Failed to load model")
+            .map_err(|e| to_js_error(&e))
     }
 
     /// Generate text from a prompt
     #[wasm_bindgen]
     pub async fn generate(&self, prompt: String, config: JsValue) -> Result<String, JsValue> {
         // Parse generation config from JavaScript
-        let gen_config: GenerationConfig = if config.is_undefined() || config.is_null() {
-            GenerationConfig::default()
-        } else {
-            serde_wasm_bindgen::from_value(config)
-                .map_err(|e| JsValue::from_str(&format!("Invalid config: {}", e)))?
-        };
+        let gen_config = parse_generation_config(config)?;
 
         self.inner
             .generate(&prompt, &gen_config)
             .await
-            .map_err(|e| JsValue::from_str(&format!("Generation failed: {}", e)))
+            .context("Generation failed")
+            .map_err(|e| to_js_error(&e))
     }
 
-    /// Generate text with streaming (calls callback for each token)
+    /// Generate text and return
+    /// `{ text, finish_reason, tokens_generated, metrics }`
+    #[wasm_bindgen]
+    pub async fn generate_with_details(&self, prompt: String, config: JsValue) -> Result<JsValue, JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let output = self
+            .inner
+            .generate_with_details(&prompt, &gen_config)
+            .await
+            .context("Generation failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&output)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize output: {}", e)))
+    }
+
+    /// Generate `n` independent completions, returned as an array of
+    /// `{ text, finish_reason, tokens_generated, metrics }`
+    #[wasm_bindgen]
+    pub async fn generate_n(&self, prompt: String, n: usize, config: JsValue) -> Result<JsValue, JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let outputs = self
+            .inner
+            .generate_n(&prompt, &gen_config, n)
+            .await
+            .context("Generation failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&outputs)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize output: {}", e)))
+    }
+
+    /// Generate a reply to `[{ role, content }, ...]` messages using the
+    /// configured chat template
+    #[wasm_bindgen]
+    pub async fn chat(&self, messages: JsValue, config: JsValue) -> Result<String, JsValue> {
+        let messages: Vec<ChatMessage> = serde_wasm_bindgen::from_value(messages)
+            .map_err(|e| to_js_error(&LlmError::Config(e.to_string()).into()))?;
+
+        let gen_config = parse_generation_config(config)?;
+
+        self.inner
+            .chat(&messages, &gen_config)
+            .await
+            .context("Chat generation failed")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Generate text with streaming
+    ///
+    /// The callback is called as `callback(delta, accumulated)` for each
+    /// token; callbacks taking only the delta keep working unchanged.
     #[wasm_bindgen]
     pub async fn generate_stream(
         &self,
@@ -106,29 +247,186 @@ impl WasmPhiModel {
         config: JsValue,
     ) -> Result<(), JsValue> {
         // Parse generation config
-        let gen_config: GenerationConfig = if config.is_undefined() || config.is_null() {
-            GenerationConfig::default()
-        } else {
-            serde_wasm_bindgen::from_value(config)
-                .map_err(|e| JsValue::from_str(&format!("Invalid config: {}", e)))?
-        };
+        let gen_config = parse_generation_config(config)?;
 
         // Create Rust closure that calls the JavaScript callback
-        let js_callback = move |token: String| -> anyhow::Result<()> {
+        let js_callback = move |token: &str, accumulated: &str| -> anyhow::Result<()> {
             let this = JsValue::null();
-            let token_js = JsValue::from_str(&token);
+            let token_js = JsValue::from_str(token);
+            let accumulated_js = JsValue::from_str(accumulated);
 
             callback
-                .call1(&this, &token_js)
+                .call2(&this, &token_js, &accumulated_js)
                 .map_err(|e| anyhow::anyhow!("Callback error: {:?}", e))?;
 
             Ok(())
         };
 
         self.inner
-            .generate_stream(&prompt, &gen_config, js_callback)
+            .generate_stream_accumulated(&prompt, &gen_config, js_callback)
             .await
-            .map_err(|e| JsValue::from_str(&format!("Streaming generation failed: {}", e)))
+            .map(|_| ())
+            .context("Streaming generation failed")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Stream generated text and report live throughput
+    ///
+    /// Like `generate_stream`, plus `on_metrics(tokens_generated, elapsed_ms,
+    /// tokens_per_sec)` every `config.metrics_every` tokens.
+    #[wasm_bindgen]
+    pub async fn generate_stream_with_metrics(
+        &self,
+        prompt: String,
+        callback: js_sys::Function,
+        on_metrics: js_sys::Function,
+        config: JsValue,
+    ) -> Result<(), JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let mut accumulated = String::new();
+        let js_callback = |token: String| -> anyhow::Result<()> {
+            accumulated.push_str(&token);
+            callback
+                .call2(
+                    &JsValue::null(),
+                    &JsValue::from_str(&token),
+                    &JsValue::from_str(&accumulated),
+                )
+                .map_err(|e| anyhow::anyhow!("Callback error: {:?}", e))?;
+            Ok(())
+        };
+
+        let mut js_on_metrics = |tokens: usize, elapsed_ms: f64, tokens_per_sec: f64| {
+            on_metrics
+                .call3(
+                    &JsValue::null(),
+                    &JsValue::from(tokens as u32),
+                    &JsValue::from(elapsed_ms),
+                    &JsValue::from(tokens_per_sec),
+                )
+                .map_err(|e| anyhow::anyhow!("Metrics callback error: {:?}", e))?;
+            Ok(())
+        };
+
+        self.inner
+            .generate_stream_with_metrics(&prompt, &gen_config, js_callback, &mut js_on_metrics)
+            .await
+            .map(|_| ())
+            .context("Streaming generation failed")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Stream generated text as a `ReadableStream` of string chunks
+    ///
+    /// Each pull decodes until new text is available, so generation runs at
+    /// the pace the consumer reads (e.g. `for await (const chunk of stream)`).
+    #[wasm_bindgen]
+    pub fn generate_readable_stream(
+        &self,
+        prompt: String,
+        config: JsValue,
+    ) -> Result<web_sys::ReadableStream, JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let stream = self
+            .inner
+            .token_stream(&prompt, &gen_config)
+            .context("Streaming generation failed")
+            .map_err(|e| to_js_error(&e))?;
+        // Dropped once the stream ends, fails or is cancelled, releasing its
+        // reference to the model (the JS closures themselves are never freed)
+        let stream = Rc::new(RefCell::new(Some(stream)));
+
+        type PullFn = dyn FnMut(web_sys::ReadableStreamDefaultController) -> js_sys::Promise;
+        let pull_stream = Rc::clone(&stream);
+        let pull = Closure::<PullFn>::new(
+            move |controller: web_sys::ReadableStreamDefaultController| {
+                let stream = Rc::clone(&pull_stream);
+                wasm_bindgen_futures::future_to_promise(async move {
+                    let (next, should_yield) = {
+                        let mut slot = stream.borrow_mut();
+                        let Some(active) = slot.as_mut() else {
+                            controller.close()?;
+                            return Ok(JsValue::UNDEFINED);
+                        };
+                        let next = active.next_delta();
+                        let should_yield = active.config().should_yield(active.tokens_generated());
+                        if !matches!(next, Ok(Some(_))) {
+                            *slot = None;
+                        }
+                        (next, should_yield)
+                    };
+
+                    match next.context("Streaming generation failed") {
+                        Ok(Some(delta)) => {
+                            controller.enqueue_with_chunk(&JsValue::from_str(&delta))?
+                        }
+                        Ok(None) => controller.close()?,
+                        Err(e) => return Err(to_js_error(&e)),
+                    }
+
+                    // Let the browser render between tokens
+                    if should_yield {
+                        utils::yield_now().await;
+                    }
+                    Ok(JsValue::UNDEFINED)
+                })
+            },
+        );
+
+        let cancel = Closure::<dyn FnMut(JsValue)>::new(move |_reason: JsValue| {
+            stream.borrow_mut().take();
+        });
+
+        let source = web_sys::UnderlyingSource::new();
+        source.set_pull(pull.into_js_value().unchecked_ref());
+        source.set_cancel(cancel.into_js_value().unchecked_ref());
+        web_sys::ReadableStream::new_with_underlying_source(&source)
+    }
+
+    /// Get the top-n next-token candidates as `[token, probability]` pairs
+    #[wasm_bindgen]
+    pub fn next_token_distribution(&self, prompt: String, n: usize) -> Result<JsValue, JsValue> {
+        let distribution = self
+            .inner
+            .next_token_distribution(&prompt, n)
+            .context("Failed to compute distribution")
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&distribution)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize distribution: {}", e)))
+    }
+
+    /// Load only the tokenizer so `count_tokens` works before `load()`
+    #[wasm_bindgen]
+    pub async fn load_tokenizer_only(&mut self) -> Result<(), JsValue> {
+        self.inner_mut()?
+            .load_tokenizer_only()
+            .await
+            .context("Failed to load tokenizer")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Number of tokens in `text` (needs only the tokenizer)
+    #[wasm_bindgen]
+    pub fn count_tokens(&self, text: String) -> Result<usize, JsValue> {
+        self.inner
+            .count_tokens(&text)
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Tokens of `text` as `{ id, text, start, end }` objects covering the
+    /// whole input (needs only the tokenizer)
+    #[wasm_bindgen]
+    pub fn tokenize_preview(&self, text: String) -> Result<JsValue, JsValue> {
+        let pieces = self
+            .inner
+            .tokenize_preview(&text)
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&pieces)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize tokens: {}", e)))
     }
 
     /// Check if the model is loaded
@@ -137,11 +435,267 @@ impl WasmPhiModel {
         self.inner.is_loaded()
     }
 
+    /// Check if the tokenizer is loaded
+    #[wasm_bindgen]
+    pub fn is_tokenizer_loaded(&self) -> bool {
+        self.inner.is_tokenizer_loaded()
+    }
+
     /// Get model configuration as JSON
     #[wasm_bindgen]
     pub fn get_config(&self) -> Result<JsValue, JsValue> {
         serde_wasm_bindgen::to_value(self.inner.config())
-            .map_err(|e| JsValue::from_str(&format!("Failed to serialize config: {}", e)))
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize config: {}", e)))
+    }
+}
+
+// ============================================================================
+// RAG WASM Bindings
+// ============================================================================
+
+/// Serialize results as `RagSource`s, as previews when `snippet_chars` is set
+fn sources_to_js(
+    results: &[SearchResult],
+    snippet_chars: Option<usize>,
+) -> Result<JsValue, JsValue> {
+    let sources: Vec<RagSource> = match snippet_chars {
+        Some(max_chars) => results.iter().map(|r| RagSource::snippet(r, max_chars)).collect(),
+        None => results.iter().map(RagSource::from).collect(),
+    };
+    serde_wasm_bindgen::to_value(&sources)
+        .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize results: {}", e)))
+}
+
+/// WASM wrapper for RagPipeline
+#[wasm_bindgen]
+pub struct WasmRagPipeline {
+    inner: RagPipeline,
+}
+
+#[wasm_bindgen]
+impl WasmRagPipeline {
+    /// Create a RAG pipeline with default chunking and embedding model
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            inner: RagPipeline::new(
+                ChunkingStrategy::default(),
+                EmbeddingModel::new("all-MiniLM-L6-v2".to_string()),
+                VectorDatabase::new(),
+            ),
+        }
+    }
+
+    /// Index plain text under a document name (the name is the document ID)
+    #[wasm_bindgen]
+    pub async fn index_text(&mut self, name: String, content: String) -> Result<usize, JsValue> {
+        self.index(name, content, "txt".to_string()).await
+    }
+
+    /// Parse an uploaded file (txt, md, html, ...) and index it under its
+    /// file name; returns the number of chunks
+    ///
+    /// Invalid UTF-8 is replaced with `�` unless `strict_utf8` is true, in
+    /// which case it fails the upload.
+    #[wasm_bindgen]
+    pub async fn index_document(
+        &mut self,
+        name: String,
+        bytes: Vec<u8>,
+        strict_utf8: Option<bool>,
+    ) -> Result<usize, JsValue> {
+        let decoding = if strict_utf8.unwrap_or(false) {
+            Utf8Decoding::Strict
+        } else {
+            Utf8Decoding::Lossy
+        };
+        let content = FileParser::parse(&name, &bytes, decoding)
+            .await
+            .context("Failed to parse document")
+            .map_err(|e| to_js_error(&e))?;
+        let file_type = FileParser::resolve_type(&name, &bytes);
+
+        self.index(name, content, file_type).await
+    }
+
+    /// Retrieve the top-k chunks for a question as an array of
+    /// `{ chunk_id, document_id, document_name, content, score, truncated }`
+    ///
+    /// With `snippet_chars`, `content` is a preview of at most that many
+    /// characters; fetch the full text with `get_chunk(chunk_id)`.
+    #[wasm_bindgen]
+    pub async fn query(
+        &self,
+        question: String,
+        top_k: usize,
+        snippet_chars: Option<usize>,
+    ) -> Result<JsValue, JsValue> {
+        let results = self
+            .inner
+            .retrieve(&question, top_k)
+            .await
+            .context("Retrieval failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        sources_to_js(&results, snippet_chars)
+    }
+
+    /// Like `query`, restricted to chunks matching `filter`:
+    /// `{ document_ids?: string[], extra?: { [key]: value } }`
+    #[wasm_bindgen]
+    pub async fn query_with_filter(
+        &self,
+        question: String,
+        top_k: usize,
+        filter: JsValue,
+        snippet_chars: Option<usize>,
+    ) -> Result<JsValue, JsValue> {
+        let filter: MetadataFilter = serde_wasm_bindgen::from_value(filter)
+            .map_err(|e| to_js_error(&LlmError::Config(e.to_string()).into()))?;
+        let results = self
+            .inner
+            .query_with_filter(&question, top_k, &filter)
+            .await
+            .context("Retrieval failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        sources_to_js(&results, snippet_chars)
+    }
+
+    /// Full content of an indexed chunk (`undefined` if there is none)
+    #[wasm_bindgen]
+    pub fn get_chunk(&self, chunk_id: String) -> Option<String> {
+        self.inner
+            .vector_db()
+            .get_chunk(&chunk_id)
+            .map(|chunk| chunk.content)
+    }
+
+    /// Remove a document's chunks; returns the number deleted
+    #[wasm_bindgen]
+    pub async fn delete_document(&mut self, document_id: String) -> Result<usize, JsValue> {
+        self.inner
+            .delete_document(&document_id)
+            .await
+            .context("Delete failed")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Get `{ total_chunks, total_documents }`
+    #[wasm_bindgen]
+    pub fn stats(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.inner.stats())
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize stats: {}", e)))
+    }
+
+    /// Remove all indexed documents
+    #[wasm_bindgen]
+    pub async fn clear(&mut self) -> Result<(), JsValue> {
+        self.inner
+            .clear()
+            .await
+            .context("Clear failed")
+            .map_err(|e| to_js_error(&e))
+    }
+
+    /// Answer a question with retrieved context; returns `{ answer, sources }`
+    #[wasm_bindgen]
+    pub async fn answer(
+        &self,
+        model: &WasmPhiModel,
+        question: String,
+        top_k: usize,
+        config: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let answer = self
+            .inner
+            .answer(&model.inner, &question, top_k, &gen_config)
+            .await
+            .context("RAG answer failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&answer)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize answer: {}", e)))
+    }
+
+    /// Answer a question, calling `on_source(source)` for each retrieved
+    /// source before streaming the answer through `on_token(delta)`;
+    /// resolves to `{ answer, sources }`
+    #[wasm_bindgen]
+    pub async fn answer_stream(
+        &self,
+        model: &WasmPhiModel,
+        question: String,
+        top_k: usize,
+        on_source: js_sys::Function,
+        on_token: js_sys::Function,
+        config: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let gen_config = parse_generation_config(config)?;
+
+        let js_on_source = |source: &RagSource| -> anyhow::Result<()> {
+            let source_js = serde_wasm_bindgen::to_value(source)
+                .map_err(|e| anyhow::anyhow!("Failed to serialize source: {}", e))?;
+            on_source
+                .call1(&JsValue::null(), &source_js)
+                .map_err(|e| anyhow::anyhow!("Callback error: {:?}", e))?;
+            Ok(())
+        };
+        let js_on_token = |token: String| -> anyhow::Result<()> {
+            on_token
+                .call1(&JsValue::null(), &JsValue::from_str(&token))
+                .map_err(|e| anyhow::anyhow!("Callback error: {:?}", e))?;
+            Ok(())
+        };
+
+        let answer = self
+            .inner
+            .answer_stream(&model.inner, &question, top_k, &gen_config, js_on_source, js_on_token)
+            .await
+            .context("RAG answer failed")
+            .map_err(|e| to_js_error(&e))?;
+
+        serde_wasm_bindgen::to_value(&answer)
+            .map_err(|e| to_js_error(&anyhow::anyhow!("Failed to serialize answer: {}", e)))
+    }
+}
+
+impl WasmRagPipeline {
+    /// Sanitize and index text as a document named (and identified by) `name`
+    async fn index(
+        &mut self,
+        name: String,
+        content: String,
+        file_type: String,
+    ) -> Result<usize, JsValue> {
+        let mut document = Document {
+            id: name.clone(),
+            name,
+            metadata: DocumentMetadata {
+                file_type,
+                size_bytes: content.len(),
+                char_count: content.chars().count(),
+                uploaded_at: utils::current_timestamp(),
+                num_chunks: 0,
+                extra: Default::default(),
+            },
+            content,
+        };
+        document.sanitize();
+
+        self.inner
+            .index_document(document)
+            .await
+            .context("Indexing failed")
+            .map_err(|e| to_js_error(&e))
+    }
+}
+
+impl Default for WasmRagPipeline {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -174,3 +728,190 @@ pub fn create_generation_config(
 
     serde_wasm_bindgen::to_value(&config).unwrap_or(JsValue::NULL)
 }
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+    use crate::llm::MockBackend;
+    use wasm_bindgen_futures::JsFuture;
+
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_readable_stream_matches_generate() {
+        let model = WasmPhiModel {
+            inner: Rc::new(PhiModel::with_backend(
+                ModelConfig::default(),
+                word_level_tokenizer(&["to", "be", "or", "not"]),
+                Box::new(MockBackend::new(vec![0.0, 0.0, 3.0, 2.5, 1.0, 0.5])),
+            )),
+        };
+        let config = GenerationConfig {
+            max_tokens: 6,
+            temperature: 0.0,
+            repetition_penalty: 2.0,
+            ..GenerationConfig::default()
+        };
+        let js_config = serde_wasm_bindgen::to_value(&config).unwrap();
+
+        let stream = model.generate_readable_stream("to".to_string(), js_config).unwrap();
+        let reader = web_sys::ReadableStreamDefaultReader::new(&stream).unwrap();
+        let mut chunks = String::new();
+        loop {
+            let result = JsFuture::from(reader.read()).await.unwrap();
+            let done = js_sys::Reflect::get(&result, &"done".into()).unwrap();
+            if done.as_bool() == Some(true) {
+                break;
+            }
+            let value = js_sys::Reflect::get(&result, &"value".into()).unwrap();
+            chunks.push_str(&value.as_string().unwrap());
+        }
+
+        let expected = model.inner.generate("to", &config).await.unwrap();
+        assert_eq!(chunks, expected);
+    }
+
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_finished_readable_stream_releases_model() {
+        let mut model = WasmPhiModel {
+            inner: Rc::new(PhiModel::with_backend(
+                ModelConfig::default(),
+                word_level_tokenizer(&["to", "be"]),
+                Box::new(MockBackend::new(vec![0.0, 0.0, 3.0, 2.5])),
+            )),
+        };
+        let config = GenerationConfig {
+            max_tokens: 2,
+            temperature: 0.0,
+            ..GenerationConfig::default()
+        };
+        let js_config = serde_wasm_bindgen::to_value(&config).unwrap();
+
+        let stream = model.generate_readable_stream("to".to_string(), js_config).unwrap();
+        let reader = web_sys::ReadableStreamDefaultReader::new(&stream).unwrap();
+        loop {
+            let result = JsFuture::from(reader.read()).await.unwrap();
+            let done = js_sys::Reflect::get(&result, &"done".into()).unwrap();
+            if done.as_bool() == Some(true) {
+                break;
+            }
+        }
+
+        let js_model_config = model.get_config().unwrap();
+        assert!(model.update_config(js_model_config).is_ok());
+
+        // Cancelling an unfinished stream releases the model too
+        let js_config = serde_wasm_bindgen::to_value(&config).unwrap();
+        let stream = model.generate_readable_stream("to".to_string(), js_config).unwrap();
+        JsFuture::from(stream.cancel()).await.unwrap();
+        assert!(model.set_auth_token("token".to_string()).is_ok());
+    }
+
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_rag_pipeline_index_and_query() {
+        let mut pipeline = WasmRagPipeline::new();
+        let bytes = b"Rust compiles to WebAssembly.\r\nThe borrow checker prevents races.".to_vec();
+
+        let num_chunks = pipeline
+            .index_document("notes.txt".to_string(), bytes, None)
+            .await
+            .unwrap();
+        assert!(num_chunks > 0);
+
+        let question = "What prevents data races?".to_string();
+        let results = pipeline.query(question, 3, None).await.unwrap();
+        let sources: js_sys::Array = results.into();
+        assert_eq!(sources.length() as usize, num_chunks.min(3));
+        let first = sources.get(0);
+        let document_id = js_sys::Reflect::get(&first, &"document_id".into()).unwrap();
+        assert_eq!(document_id.as_string().as_deref(), Some("notes.txt"));
+
+        let stats = pipeline.stats().unwrap();
+        let total_documents = js_sys::Reflect::get(&stats, &"total_documents".into()).unwrap();
+        assert_eq!(total_documents.as_f64(), Some(1.0));
+
+        let deleted = pipeline.delete_document("notes.txt".to_string()).await.unwrap();
+        assert_eq!(deleted, num_chunks);
+        pipeline.clear().await.unwrap();
+    }
+
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_query_snippets_respect_char_limit() {
+        let mut pipeline = WasmRagPipeline::new();
+        let sentence = "Ownership rules let Rust free memory without a garbage collector. ";
+        let content = sentence.repeat(8);
+        pipeline.index_text("rust.txt".to_string(), content).await.unwrap();
+
+        let results = pipeline
+            .query("memory".to_string(), 3, Some(24))
+            .await
+            .unwrap();
+        let sources: js_sys::Array = results.into();
+        assert!(sources.length() > 0);
+        for source in sources.iter() {
+            let get = |key: &str| js_sys::Reflect::get(&source, &key.into()).unwrap();
+            let snippet = get("content").as_string().unwrap();
+            assert!(snippet.chars().count() <= 24);
+            assert_eq!(get("truncated").as_bool(), Some(true));
+
+            let chunk_id = get("chunk_id").as_string().unwrap();
+            let full = pipeline.get_chunk(chunk_id).unwrap();
+            assert!(full.contains(snippet.trim_end_matches('…')));
+            assert!(full.chars().count() > 24);
+        }
+        assert_eq!(pipeline.get_chunk("missing".to_string()), None);
+    }
+
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_answer_stream_emits_sources_before_tokens() {
+        use wasm_bindgen::closure::Closure;
+
+        let mut pipeline = WasmRagPipeline::new();
+        pipeline
+            .index_text("notes".to_string(), "Rust compiles to WebAssembly.".to_string())
+            .await
+            .unwrap();
+        let model = WasmPhiModel {
+            inner: Rc::new(PhiModel::with_backend(
+                ModelConfig::default(),
+                word_level_tokenizer(&["Rust", "WebAssembly"]),
+                Box::new(MockBackend::new(vec![0.0, 0.0, 4.0, 5.0])),
+            )),
+        };
+        let config = GenerationConfig {
+            max_tokens: 3,
+            temperature: 0.0,
+            repetition_penalty: 2.0,
+            ..GenerationConfig::default()
+        };
+
+        let events = Rc::new(RefCell::new(Vec::<String>::new()));
+        let source_events = events.clone();
+        let on_source = Closure::<dyn FnMut(JsValue)>::new(move |_source: JsValue| {
+            source_events.borrow_mut().push("source".to_string());
+        });
+        let token_events = events.clone();
+        let on_token = Closure::<dyn FnMut(JsValue)>::new(move |token: JsValue| {
+            token_events.borrow_mut().push(token.as_string().unwrap());
+        });
+
+        let answer = pipeline
+            .answer_stream(
+                &model,
+                "What does Rust compile to?".to_string(),
+                2,
+                on_source.as_ref().unchecked_ref::<js_sys::Function>().clone(),
+                on_token.as_ref().unchecked_ref::<js_sys::Function>().clone(),
+                serde_wasm_bindgen::to_value(&config).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let events = events.borrow();
+        assert_eq!(events.first().map(String::as_str), Some("source"));
+        let first_token = events.iter().position(|e| e != "source").unwrap();
+        assert!(events[first_token..].iter().all(|e| e != "source"));
+
+        let text = js_sys::Reflect::get(&answer, &"answer".into()).unwrap();
+        assert_eq!(events[first_token..].concat(), text.as_string().unwrap());
+    }
+}
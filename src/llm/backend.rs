@@ -0,0 +1,156 @@
+use std::cell::Cell;
+
+use anyhow::Result;
+
+use super::device::Device;
+use crate::utils::now_ms;
+
+/// Inference backend that produces next-token logits for a token context
+///
+/// The Candle model will implement this once WASM support is complete.
+/// Until then `MockBackend` provides deterministic logits for testing.
+pub trait InferenceBackend {
+    /// Run a forward pass over the context and return logits for the next token
+    fn forward(&self, tokens: &[u32]) -> Result<Vec<f32>>;
+
+    /// Vocabulary size of the model
+    fn vocab_size(&self) -> usize;
+
+    /// Last-layer hidden state for each token of the context
+    ///
+    /// Used to derive embeddings from the language model itself. Backends
+    /// that cannot expose hidden states return an error.
+    fn hidden_states(&self, _tokens: &[u32]) -> Result<Vec<Vec<f32>>> {
+        anyhow::bail!("This backend does not expose hidden states")
+    }
+
+    /// Run the following forward passes on `device`
+    ///
+    /// The loaded weights keep their primary placement; the Candle backend
+    /// will move the request's tensors with `.to_device`.
+    fn select_device(&self, _device: Device) -> Result<()> {
+        Ok(())
+    }
+
+    /// Device the last request ran on, if the backend tracks it
+    fn active_device(&self) -> Option<Device> {
+        None
+    }
+}
+
+/// Hidden size reported by `MockBackend::hidden_states`
+pub const MOCK_HIDDEN_SIZE: usize = 64;
+
+/// Mock backend returning the same logits at every step
+pub struct MockBackend {
+    logits: Vec<f32>,
+    delay_ms: u64,
+    /// Recorded by `select_device`
+    device: Cell<Option<Device>>,
+}
+
+impl MockBackend {
+    /// Create a mock backend with fixed logits
+    pub fn new(logits: Vec<f32>) -> Self {
+        Self {
+            logits,
+            delay_ms: 0,
+            device: Cell::new(None),
+        }
+    }
+
+    /// Simulate a slow model by blocking for `delay_ms` on each forward pass
+    pub fn with_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.delay_ms = delay_ms;
+        self
+    }
+}
+
+impl InferenceBackend for MockBackend {
+    fn forward(&self, _tokens: &[u32]) -> Result<Vec<f32>> {
+        if self.delay_ms > 0 {
+            let start = now_ms();
+            while now_ms() - start < self.delay_ms as f64 {
+                std::hint::spin_loop();
+            }
+        }
+        Ok(self.logits.clone())
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.logits.len()
+    }
+
+    fn select_device(&self, device: Device) -> Result<()> {
+        self.device.set(Some(device));
+        Ok(())
+    }
+
+    fn active_device(&self) -> Option<Device> {
+        self.device.get()
+    }
+
+    /// Deterministic hidden states: a one-hot vector per token ID, so
+    /// mean-pooled embeddings behave like bag-of-words vectors
+    fn hidden_states(&self, tokens: &[u32]) -> Result<Vec<Vec<f32>>> {
+        Ok(tokens
+            .iter()
+            .map(|&token| {
+                let mut state = vec![0.0; MOCK_HIDDEN_SIZE];
+                state[token as usize % MOCK_HIDDEN_SIZE] = 1.0;
+                state
+            })
+            .collect())
+    }
+}
+
+/// Backend that deterministically produces a fixed token sequence
+///
+/// Each forward pass puts all probability on the next scripted token
+/// (counted from the context length), then on `eos_token_id`. Drives the
+/// mock responses through the regular sampling and stopping logic.
+pub(crate) struct ScriptedBackend {
+    prompt_len: usize,
+    tokens: Vec<u32>,
+    eos_token_id: Option<u32>,
+    vocab_size: usize,
+}
+
+impl ScriptedBackend {
+    pub(crate) fn new(
+        prompt_len: usize,
+        tokens: Vec<u32>,
+        eos_token_id: Option<u32>,
+        vocab_size: usize,
+    ) -> Self {
+        Self {
+            prompt_len,
+            tokens,
+            eos_token_id,
+            vocab_size,
+        }
+    }
+
+    /// Number of scripted tokens
+    pub(crate) fn len(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+impl InferenceBackend for ScriptedBackend {
+    fn forward(&self, tokens: &[u32]) -> Result<Vec<f32>> {
+        let step = tokens.len().saturating_sub(self.prompt_len);
+        let next = self.tokens.get(step).copied().or(self.eos_token_id);
+
+        let mut logits = vec![f32::NEG_INFINITY; self.vocab_size];
+        match next.and_then(|id| logits.get_mut(id as usize)) {
+            Some(logit) => *logit = 0.0,
+            None => anyhow::bail!("Scripted backend has no token for step {}", step),
+        }
+        Ok(logits)
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+/// Role of a chat message author
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl ChatRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+        }
+    }
+}
+
+/// A single chat message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    /// Create a system message
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: ChatRole::System,
+            content: content.into(),
+        }
+    }
+
+    /// Create a user message
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: ChatRole::User,
+            content: content.into(),
+        }
+    }
+
+    /// Create an assistant message
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: ChatRole::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// Chat prompt formats, keyed by template ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChatTemplate {
+    /// Phi-3: `<|user|>\n...<|end|>\n<|assistant|>\n`
+    #[default]
+    Phi3,
+    /// ChatML (Qwen, OpenHermes, ...): `<|im_start|>user\n...<|im_end|>\n`
+    ChatMl,
+    /// Llama 2 chat: `<s>[INST] <<SYS>>...<</SYS>> ... [/INST]`
+    Llama2,
+    /// Zephyr / TinyLlama chat: `<|user|>\n...</s>\n<|assistant|>\n`
+    Zephyr,
+}
+
+impl ChatTemplate {
+    /// All registered templates
+    pub const ALL: [ChatTemplate; 4] = [
+        ChatTemplate::Phi3,
+        ChatTemplate::ChatMl,
+        ChatTemplate::Llama2,
+        ChatTemplate::Zephyr,
+    ];
+
+    /// Look up a template by ID
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.id() == id)
+    }
+
+    /// Template ID (as used in `ModelConfig::chat_template`)
+    pub fn id(&self) -> &'static str {
+        match self {
+            ChatTemplate::Phi3 => "phi3",
+            ChatTemplate::ChatMl => "chatml",
+            ChatTemplate::Llama2 => "llama2",
+            ChatTemplate::Zephyr => "zephyr",
+        }
+    }
+
+    /// Render messages into a prompt, ending with the assistant generation prefix
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        match self {
+            ChatTemplate::Phi3 => Self::render_tagged(messages, "<|end|>"),
+            ChatTemplate::Zephyr => Self::render_tagged(messages, "</s>"),
+            ChatTemplate::ChatMl => Self::render_chatml(messages),
+            ChatTemplate::Llama2 => Self::render_llama2(messages),
+        }
+    }
+
+    /// `<|role|>\n{content}{end}\n` per message, then `<|assistant|>\n`
+    fn render_tagged(messages: &[ChatMessage], end: &str) -> String {
+        let mut prompt = String::new();
+        for message in messages {
+            prompt.push_str(&format!(
+                "<|{}|>\n{}{}\n",
+                message.role.as_str(),
+                message.content,
+                end
+            ));
+        }
+        prompt.push_str("<|assistant|>\n");
+        prompt
+    }
+
+    fn render_chatml(messages: &[ChatMessage]) -> String {
+        let mut prompt = String::new();
+        for message in messages {
+            prompt.push_str(&format!(
+                "<|im_start|>{}\n{}<|im_end|>\n",
+                message.role.as_str(),
+                message.content
+            ));
+        }
+        prompt.push_str("<|im_start|>assistant\n");
+        prompt
+    }
+
+    /// Llama 2 folds the system prompt into the first user turn; the
+    /// generation prompt is the trailing `[/INST]`
+    fn render_llama2(messages: &[ChatMessage]) -> String {
+        let mut prompt = String::new();
+        let mut system: Option<&str> = None;
+
+        for message in messages {
+            match message.role {
+                ChatRole::System => system = Some(&message.content),
+                ChatRole::User => {
+                    prompt.push_str("<s>[INST] ");
+                    if let Some(system) = system.take() {
+                        prompt.push_str(&format!("<<SYS>>\n{}\n<</SYS>>\n\n", system));
+                    }
+                    prompt.push_str(&format!("{} [/INST]", message.content));
+                }
+                ChatRole::Assistant => {
+                    prompt.push_str(&format!(" {} </s>", message.content));
+                }
+            }
+        }
+
+        prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("Be brief."),
+            ChatMessage::user("Hi"),
+        ]
+    }
+
+    #[test]
+    fn test_phi3_and_chatml_layouts() {
+        let messages = conversation();
+
+        assert_eq!(
+            ChatTemplate::Phi3.render(&messages),
+            "<|system|>\nBe brief.<|end|>\n<|user|>\nHi<|end|>\n<|assistant|>\n"
+        );
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_llama2_layout() {
+        let mut messages = conversation();
+        messages.push(ChatMessage::assistant("Hello!"));
+        messages.push(ChatMessage::user("Bye"));
+
+        assert_eq!(
+            ChatTemplate::Llama2.render(&messages),
+            "<s>[INST] <<SYS>>\nBe brief.\n<</SYS>>\n\nHi [/INST] Hello! </s><s>[INST] Bye [/INST]"
+        );
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        for template in ChatTemplate::ALL {
+            assert_eq!(ChatTemplate::from_id(template.id()), Some(template));
+        }
+        assert_eq!(ChatTemplate::from_id("unknown"), None);
+    }
+}
@@ -1,4 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::chat_template::ChatTemplate;
+use super::device::DevicePreference;
+use crate::utils::fetch::{FetchCredentials, FetchMode, FetchOptions, RetryPolicy};
 
 /// Model configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,13 +14,43 @@ pub struct ModelConfig {
     pub tokenizer_url: String,
     /// Model ID for identification
     pub model_id: String,
-    /// Whether to use WebGPU (fallback to CPU if unavailable)
+    /// Whether WebGPU may be used; `false` forces the CPU regardless of
+    /// `device`
+    #[deprecated(note = "use `device` (`DevicePreference`) instead")]
     pub use_webgpu: bool,
+    /// Device selection policy applied by `PhiModel::load`
+    #[serde(default)]
+    pub device: DevicePreference,
     /// Quantization type (Q4, Q8, etc.)
     pub quantization: String,
+    /// Retries for transient network errors when fetching model files
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Initial retry delay in milliseconds (doubled on each retry)
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u32,
+    /// Chat template ID (`phi3`, `chatml`, `llama2`, `zephyr`)
+    #[serde(default = "default_chat_template")]
+    pub chat_template: String,
+    /// Maximum number of tokens (prompt plus generated) the model attends to;
+    /// generation is rejected up front when a request would exceed it
+    #[serde(default = "default_context_length")]
+    pub context_length: usize,
+    /// Extra headers sent when fetching model files (never serialized, as
+    /// they usually carry credentials)
+    #[serde(default, skip_serializing)]
+    pub headers: HashMap<String, String>,
+    /// Request mode for model files; `same-origin` for self-hosted models
+    #[serde(default)]
+    pub fetch_mode: FetchMode,
+    /// Whether cookies are sent with model file requests; `include` for
+    /// deployments behind cookie-based auth
+    #[serde(default)]
+    pub credentials: FetchCredentials,
 }
 
 impl Default for ModelConfig {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
             model_url: String::from(
@@ -26,11 +61,35 @@ impl Default for ModelConfig {
             ),
             model_id: String::from("Phi-3-mini-4k-instruct-q4"),
             use_webgpu: true,
+            device: DevicePreference::default(),
             quantization: String::from("Q4"),
+            max_retries: default_max_retries(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            chat_template: default_chat_template(),
+            context_length: default_context_length(),
+            headers: HashMap::new(),
+            fetch_mode: FetchMode::default(),
+            credentials: FetchCredentials::default(),
         }
     }
 }
 
+fn default_context_length() -> usize {
+    4096
+}
+
+fn default_chat_template() -> String {
+    ChatTemplate::Phi3.id().to_string()
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u32 {
+    500
+}
+
 impl ModelConfig {
     /// Create a new model configuration
     pub fn new(model_url: String, tokenizer_url: String) -> Self {
@@ -41,6 +100,106 @@ impl ModelConfig {
         }
     }
 
+    /// Configuration for a model hosted on the HuggingFace Hub
+    ///
+    /// `repo` is `owner/name`; `filename` is the weights file in the repo.
+    /// The tokenizer is expected at `tokenizer.json` in the same repo.
+    pub fn from_hf_repo(repo: &str, filename: &str) -> Result<Self, String> {
+        Self::from_hf_repo_at_revision(repo, filename, "main")
+    }
+
+    /// Like `from_hf_repo`, pinned to a branch, tag or commit
+    pub fn from_hf_repo_at_revision(
+        repo: &str,
+        filename: &str,
+        revision: &str,
+    ) -> Result<Self, String> {
+        validate_hf_repo(repo)?;
+        if filename.is_empty() || filename.starts_with('/') {
+            return Err(format!("Invalid model filename: '{}'", filename));
+        }
+        if revision.is_empty() || revision.contains(char::is_whitespace) {
+            return Err(format!("Invalid revision: '{}'", revision));
+        }
+
+        let base = format!("https://huggingface.co/{}/resolve/{}", repo, revision);
+        let name = filename.rsplit('/').next().unwrap_or(filename);
+        let model_id = name.rsplit_once('.').map_or(name, |(stem, _)| stem).to_string();
+
+        Ok(Self {
+            model_url: format!("{}/{}", base, filename),
+            tokenizer_url: format!("{}/tokenizer.json", base),
+            model_id,
+            ..Default::default()
+        })
+    }
+
+    /// Select the device policy, keeping `use_webgpu` in sync
+    #[allow(deprecated)]
+    pub fn with_device(mut self, device: DevicePreference) -> Self {
+        self.use_webgpu = device != DevicePreference::Cpu;
+        self.device = device;
+        self
+    }
+
+    /// Effective device policy (`use_webgpu: false` means `Cpu`)
+    #[allow(deprecated)]
+    pub fn device_preference(&self) -> DevicePreference {
+        if self.use_webgpu {
+            self.device
+        } else {
+            DevicePreference::Cpu
+        }
+    }
+
+    /// Retry policy for fetching model and tokenizer files
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            backoff_ms: self.retry_backoff_ms,
+        }
+    }
+
+    /// Send `Authorization: Bearer <token>` (for gated HuggingFace repos)
+    pub fn with_auth_token(mut self, token: &str) -> Self {
+        self.set_auth_token(token);
+        self
+    }
+
+    /// Set the bearer token used when fetching model files
+    pub fn set_auth_token(&mut self, token: &str) {
+        self.headers
+            .insert("Authorization".to_string(), format!("Bearer {}", token));
+    }
+
+    /// Fetch options (retry policy, headers, mode and credentials) for
+    /// model files
+    pub fn fetch_options(&self) -> FetchOptions {
+        FetchOptions {
+            retry: self.retry_policy(),
+            headers: self.headers.clone(),
+            mode: self.fetch_mode,
+            credentials: self.credentials,
+        }
+    }
+
+    /// Resolve the configured chat template
+    pub fn chat_template(&self) -> Result<ChatTemplate, String> {
+        ChatTemplate::from_id(&self.chat_template)
+            .ok_or_else(|| format!("Unknown chat template: {}", self.chat_template))
+    }
+
+    /// Whether switching from `self` to `other` requires reloading the model
+    ///
+    /// Weights and tokenizer depend on the URLs, quantization and device;
+    /// other settings (headers, retries, chat template) apply in place.
+    pub fn requires_reload(&self, other: &ModelConfig) -> bool {
+        self.model_url != other.model_url
+            || self.tokenizer_url != other.tokenizer_url
+            || self.quantization != other.quantization
+            || self.device_preference() != other.device_preference()
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), String> {
         if self.model_url.is_empty() {
@@ -49,6 +208,109 @@ impl ModelConfig {
         if self.tokenizer_url.is_empty() {
             return Err("Tokenizer URL cannot be empty".to_string());
         }
+        self.chat_template()?;
         Ok(())
     }
 }
+
+/// Check that a HuggingFace repo ID has the form `owner/name`
+fn validate_hf_repo(repo: &str) -> Result<(), String> {
+    let valid_part = |part: &str| {
+        !part.is_empty()
+            && !part.starts_with(['.', '-'])
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+
+    match repo.split_once('/') {
+        Some((owner, name)) if valid_part(owner) && valid_part(name) => Ok(()),
+        _ => Err(format!("Invalid HuggingFace repo '{}', expected 'owner/name'", repo)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_hf_repo_urls() {
+        let config = ModelConfig::from_hf_repo(
+            "microsoft/Phi-3-mini-4k-instruct-gguf",
+            "Phi-3-mini-4k-instruct-q4.gguf",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.model_url,
+            "https://huggingface.co/microsoft/Phi-3-mini-4k-instruct-gguf/resolve/main/Phi-3-mini-4k-instruct-q4.gguf"
+        );
+        assert_eq!(
+            config.tokenizer_url,
+            "https://huggingface.co/microsoft/Phi-3-mini-4k-instruct-gguf/resolve/main/tokenizer.json"
+        );
+        assert_eq!(config.model_id, "Phi-3-mini-4k-instruct-q4");
+        assert!(config.validate().is_ok());
+
+        let pinned =
+            ModelConfig::from_hf_repo_at_revision("owner/model", "model.gguf", "v1.0").unwrap();
+        assert_eq!(
+            pinned.model_url,
+            "https://huggingface.co/owner/model/resolve/v1.0/model.gguf"
+        );
+
+        let dotted = ModelConfig::from_hf_repo(
+            "microsoft/Phi-3.5-mini-instruct-gguf",
+            "quantized/Phi-3.5-mini-instruct-q4.gguf",
+        )
+        .unwrap();
+        assert_eq!(dotted.model_id, "Phi-3.5-mini-instruct-q4");
+    }
+
+    #[test]
+    fn test_from_hf_repo_rejects_invalid_repos() {
+        for repo in ["", "no-slash", "a/b/c", "/name", "owner/", "own er/name", "../name"] {
+            assert!(
+                ModelConfig::from_hf_repo(repo, "model.gguf").is_err(),
+                "accepted '{}'",
+                repo
+            );
+        }
+        assert!(ModelConfig::from_hf_repo("owner/model", "").is_err());
+        assert!(ModelConfig::from_hf_repo_at_revision("owner/model", "m.gguf", "").is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_use_webgpu_mirrors_device() {
+        let config = ModelConfig::default();
+        assert_eq!(config.device_preference(), DevicePreference::PreferWebGpu);
+
+        let cpu = ModelConfig::default().with_device(DevicePreference::Cpu);
+        assert!(!cpu.use_webgpu);
+        assert_eq!(cpu.device_preference(), DevicePreference::Cpu);
+
+        // Legacy configs that only set `use_webgpu: false` stay on the CPU
+        let legacy = ModelConfig {
+            use_webgpu: false,
+            ..Default::default()
+        };
+        assert_eq!(legacy.device_preference(), DevicePreference::Cpu);
+        assert!(config.requires_reload(&legacy));
+    }
+
+    #[test]
+    fn test_baseline_config_deserializes_with_defaults() {
+        let config: ModelConfig = serde_json::from_value(serde_json::json!({
+            "model_url": "/models/phi.gguf",
+            "tokenizer_url": "/models/tokenizer.json",
+            "model_id": "phi",
+            "use_webgpu": true,
+            "quantization": "Q4"
+        }))
+        .unwrap();
+
+        assert_eq!(config.retry_policy(), ModelConfig::default().retry_policy());
+        assert_eq!(config.chat_template().unwrap(), ChatTemplate::Phi3);
+    }
+}
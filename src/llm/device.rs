@@ -0,0 +1,88 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::LlmError;
+
+/// Device inference runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Cpu,
+    WebGpu,
+}
+
+/// Which device `PhiModel::load` selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DevicePreference {
+    /// Always use the CPU; WebGPU is never probed
+    #[serde(rename = "cpu")]
+    Cpu,
+    /// Use WebGPU and fail to load if it is unavailable
+    #[serde(rename = "webgpu")]
+    WebGpu,
+    /// Use WebGPU when available, otherwise fall back to the CPU
+    #[default]
+    #[serde(rename = "prefer_webgpu")]
+    PreferWebGpu,
+}
+
+impl DevicePreference {
+    /// Choose a device, calling `webgpu_available` only when it matters
+    pub fn resolve(self, webgpu_available: impl FnOnce() -> bool) -> Result<Device> {
+        if self == DevicePreference::Cpu {
+            return Ok(Device::Cpu);
+        }
+        if webgpu_available() {
+            return Ok(Device::WebGpu);
+        }
+
+        if self == DevicePreference::WebGpu {
+            return Err(LlmError::Config(
+                "WebGPU is required but not available in this browser".to_string(),
+            )
+            .into());
+        }
+        log::info!("WebGPU not available, falling back to CPU");
+        Ok(Device::Cpu)
+    }
+}
+
+/// Whether the browser exposes WebGPU (`navigator.gpu`; always false natively)
+pub fn webgpu_available() -> bool {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let global = js_sys::global();
+        js_sys::Reflect::get(&global, &"navigator".into())
+            .and_then(|navigator| js_sys::Reflect::get(&navigator, &"gpu".into()))
+            .map(|gpu| !gpu.is_undefined() && !gpu.is_null())
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_resolve_device_preferences() {
+        let probed = Cell::new(false);
+        let unavailable = || {
+            probed.set(true);
+            false
+        };
+        assert_eq!(DevicePreference::Cpu.resolve(unavailable).unwrap(), Device::Cpu);
+        assert!(!probed.get());
+
+        assert_eq!(DevicePreference::WebGpu.resolve(|| true).unwrap(), Device::WebGpu);
+        let err = DevicePreference::WebGpu.resolve(|| false).unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Config");
+
+        assert_eq!(DevicePreference::PreferWebGpu.resolve(|| true).unwrap(), Device::WebGpu);
+        assert_eq!(DevicePreference::PreferWebGpu.resolve(|| false).unwrap(), Device::Cpu);
+    }
+}
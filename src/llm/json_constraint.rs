@@ -0,0 +1,257 @@
+// Incremental JSON validation for constrained generation
+
+/// Incremental checker that text is a prefix of a JSON object
+///
+/// Fed token by token during generation; `accepts` tells whether a
+/// candidate token keeps the output a valid prefix. Only an object is
+/// accepted at the top level, so the output is always something
+/// `JSON.parse` can turn into a record once it is complete.
+#[derive(Debug, Clone, Default)]
+pub struct JsonValidator {
+    /// Open containers, `{` or `[`
+    stack: Vec<char>,
+    state: Scan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Scan {
+    /// Before the top-level object
+    #[default]
+    Start,
+    /// Any value
+    Value,
+    /// Value or `]` right after `[`
+    FirstElement,
+    /// Key or `}` right after `{`
+    FirstKey,
+    /// Key after `,` in an object
+    Key,
+    Colon,
+    /// `,` or the closing bracket of the enclosing container
+    AfterValue,
+    String { key: bool, escape: bool, hex_left: u8 },
+    Number(NumberPart),
+    /// Rest of `true`, `false` or `null`
+    Literal(&'static str),
+    /// The top-level object is closed
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberPart {
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpDigits,
+}
+
+impl NumberPart {
+    /// Whether a number may end here
+    fn is_complete(self) -> bool {
+        matches!(self, Self::Zero | Self::Int | Self::Frac | Self::ExpDigits)
+    }
+
+    fn next(self, c: char) -> Option<Self> {
+        use NumberPart::*;
+        match (self, c) {
+            (Minus, '0') => Some(Zero),
+            (Minus | Int, '0'..='9') => Some(Int),
+            (Zero | Int, '.') => Some(Dot),
+            (Zero | Int | Frac, 'e' | 'E') => Some(Exp),
+            (Dot | Frac, '0'..='9') => Some(Frac),
+            (Exp, '+' | '-') => Some(ExpSign),
+            (Exp | ExpSign | ExpDigits, '0'..='9') => Some(ExpDigits),
+            _ => None,
+        }
+    }
+}
+
+impl JsonValidator {
+    /// Validator expecting a JSON object
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the top-level object has been closed
+    pub fn is_done(&self) -> bool {
+        self.state == Scan::Done
+    }
+
+    /// Whether appending `text` keeps the output a valid prefix
+    pub fn accepts(&self, text: &str) -> bool {
+        self.clone().push(text)
+    }
+
+    /// Append `text`; returns false (leaving the validator in an
+    /// unspecified state) if it makes the output invalid
+    pub fn push(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.push_char(c))
+    }
+
+    fn push_char(&mut self, c: char) -> bool {
+        match self.state {
+            Scan::Start => match c {
+                '{' => self.open(c),
+                _ => c.is_whitespace(),
+            },
+            Scan::Value => self.start_value(c),
+            Scan::FirstElement if c == ']' => self.close(),
+            Scan::FirstElement => self.start_value(c),
+            Scan::FirstKey if c == '}' => self.close(),
+            Scan::FirstKey | Scan::Key => match c {
+                '"' => self.begin_string(true),
+                _ => c.is_whitespace(),
+            },
+            Scan::Colon => match c {
+                ':' => {
+                    self.state = Scan::Value;
+                    true
+                }
+                _ => c.is_whitespace(),
+            },
+            Scan::AfterValue => match (c, self.stack.last()) {
+                (',', Some('{')) => {
+                    self.state = Scan::Key;
+                    true
+                }
+                (',', Some('[')) => {
+                    self.state = Scan::Value;
+                    true
+                }
+                ('}', Some('{')) | (']', Some('[')) => self.close(),
+                _ => c.is_whitespace(),
+            },
+            Scan::String { key, escape, hex_left } => self.string_char(c, key, escape, hex_left),
+            Scan::Number(part) => match part.next(c) {
+                Some(next) => {
+                    self.state = Scan::Number(next);
+                    true
+                }
+                // The number ended; `c` must follow a complete value
+                None if part.is_complete() => {
+                    self.end_value();
+                    self.push_char(c)
+                }
+                None => false,
+            },
+            Scan::Literal(rest) => match rest.strip_prefix(c) {
+                Some("") => {
+                    self.end_value();
+                    true
+                }
+                Some(rest) => {
+                    self.state = Scan::Literal(rest);
+                    true
+                }
+                None => false,
+            },
+            Scan::Done => c.is_whitespace(),
+        }
+    }
+
+    fn start_value(&mut self, c: char) -> bool {
+        self.state = match c {
+            '{' | '[' => return self.open(c),
+            '"' => return self.begin_string(false),
+            '-' => Scan::Number(NumberPart::Minus),
+            '0' => Scan::Number(NumberPart::Zero),
+            '1'..='9' => Scan::Number(NumberPart::Int),
+            't' => Scan::Literal("rue"),
+            'f' => Scan::Literal("alse"),
+            'n' => Scan::Literal("ull"),
+            _ => return c.is_whitespace(),
+        };
+        true
+    }
+
+    fn string_char(&mut self, c: char, key: bool, escape: bool, hex_left: u8) -> bool {
+        let (escape, hex_left) = if hex_left > 0 {
+            if !c.is_ascii_hexdigit() {
+                return false;
+            }
+            (false, hex_left - 1)
+        } else if escape {
+            match c {
+                'u' => (false, 4),
+                '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' => (false, 0),
+                _ => return false,
+            }
+        } else {
+            match c {
+                '"' => {
+                    if key {
+                        self.state = Scan::Colon;
+                    } else {
+                        self.end_value();
+                    }
+                    return true;
+                }
+                '\\' => (true, 0),
+                c if (c as u32) < 0x20 => return false,
+                _ => (false, 0),
+            }
+        };
+        self.state = Scan::String { key, escape, hex_left };
+        true
+    }
+
+    fn begin_string(&mut self, key: bool) -> bool {
+        self.state = Scan::String {
+            key,
+            escape: false,
+            hex_left: 0,
+        };
+        true
+    }
+
+    fn open(&mut self, c: char) -> bool {
+        self.stack.push(c);
+        self.state = if c == '{' { Scan::FirstKey } else { Scan::FirstElement };
+        true
+    }
+
+    fn close(&mut self) -> bool {
+        self.stack.pop();
+        self.end_value();
+        true
+    }
+
+    fn end_value(&mut self) {
+        self.state = if self.stack.is_empty() {
+            Scan::Done
+        } else {
+            Scan::AfterValue
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_prefixes() {
+        let text = r#"{"a": [1, -2.5e+3, true, null], "b": {"c": "x\"é"}, "d": []}"#;
+        let mut validator = JsonValidator::new();
+        for c in text.chars() {
+            assert!(!validator.is_done());
+            assert!(validator.push(&c.to_string()), "rejected {:?}", c);
+        }
+        assert!(validator.is_done());
+
+        let validator = JsonValidator::new();
+        assert!(validator.accepts("  {\"key\": 0"));
+        assert!(!validator.accepts("```json"));
+        assert!(!validator.accepts("[1]"));
+        assert!(!validator.accepts("{\"a\": 01"));
+        assert!(!validator.accepts("{\"a\" 1"));
+        assert!(!validator.accepts("{\"a\": tru}"));
+        assert!(!validator.accepts("{\"a\": 1,}"));
+        assert!(!validator.accepts("{\"a\": [1}"));
+        assert!(!validator.accepts("{\"a\": 1}}"));
+    }
+}
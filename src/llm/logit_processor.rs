@@ -0,0 +1,187 @@
+// Logit post-processors run by the sampler before temperature and filtering
+
+use std::collections::HashMap;
+
+use super::GenerationConfig;
+
+/// Adjusts next-token logits before sampling
+///
+/// `Sampler` runs its processors in order on every step. Processors that
+/// depend on history see each committed token through `observe`.
+pub trait LogitProcessor {
+    /// Adjust `logits` in place for generation step `step`
+    fn process(&mut self, logits: &mut [f32], step: usize);
+
+    /// Record a token committed to the output
+    fn observe(&mut self, _token_id: u32) {}
+
+    /// Undo one `observe` of `token_id` (the token was removed again)
+    fn unobserve(&mut self, _token_id: u32) {}
+
+    /// Forget all recorded tokens
+    fn reset(&mut self) {}
+}
+
+/// Penalizes tokens by how often they were already generated
+///
+/// Each occurrence divides a positive logit (or multiplies a negative one)
+/// by `penalty`.
+pub struct RepetitionPenalty {
+    penalty: f64,
+    counts: HashMap<u32, usize>,
+}
+
+impl RepetitionPenalty {
+    pub fn new(penalty: f64) -> Self {
+        Self {
+            penalty,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Change the penalty, keeping the recorded counts
+    pub(crate) fn set_penalty(&mut self, penalty: f64) {
+        self.penalty = penalty;
+    }
+}
+
+impl LogitProcessor for RepetitionPenalty {
+    fn process(&mut self, logits: &mut [f32], _step: usize) {
+        if self.penalty == 1.0 {
+            return;
+        }
+
+        for (&token_id, &count) in &self.counts {
+            if let Some(logit) = logits.get_mut(token_id as usize) {
+                let total_penalty = self.penalty.powi(count as i32) as f32;
+                if *logit > 0.0 {
+                    *logit /= total_penalty;
+                } else {
+                    *logit *= total_penalty;
+                }
+            }
+        }
+    }
+
+    fn observe(&mut self, token_id: u32) {
+        *self.counts.entry(token_id).or_insert(0) += 1;
+    }
+
+    fn unobserve(&mut self, token_id: u32) {
+        if let Some(count) = self.counts.get_mut(&token_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&token_id);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.counts.clear();
+    }
+}
+
+/// Adds a fixed bias to the logits of chosen tokens
+pub struct LogitBias {
+    bias: Vec<(u32, f32)>,
+}
+
+impl LogitBias {
+    /// Bias from `(token_id, bias)` pairs; a token may appear more than once
+    pub fn new(bias: Vec<(u32, f32)>) -> Self {
+        Self { bias }
+    }
+}
+
+impl LogitProcessor for LogitBias {
+    fn process(&mut self, logits: &mut [f32], _step: usize) {
+        for &(token_id, bias) in &self.bias {
+            if let Some(logit) = logits.get_mut(token_id as usize) {
+                *logit += bias;
+            }
+        }
+    }
+}
+
+/// Processors for the token-independent options in `config`, in order
+///
+/// Options that need the tokenizer (such as `token_bias`) are added by the
+/// caller with `Sampler::with_processor`.
+pub fn processors_from_config(config: &GenerationConfig) -> Vec<Box<dyn LogitProcessor>> {
+    let mut processors: Vec<Box<dyn LogitProcessor>> = Vec::new();
+    if config.repetition_penalty != 1.0 {
+        processors.push(Box::new(RepetitionPenalty::new(config.repetition_penalty)));
+    }
+    processors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::Sampler;
+
+    #[test]
+    fn test_repetition_penalty() {
+        let mut penalty = RepetitionPenalty::new(2.0);
+        let mut logits = vec![4.0, -4.0, 4.0];
+        penalty.process(&mut logits, 0);
+        assert_eq!(logits, vec![4.0, -4.0, 4.0]);
+
+        penalty.observe(0);
+        penalty.observe(0);
+        penalty.observe(1);
+        penalty.process(&mut logits, 3);
+        assert_eq!(logits, vec![1.0, -8.0, 4.0]);
+
+        penalty.reset();
+        let mut logits = vec![4.0, -4.0, 4.0];
+        penalty.process(&mut logits, 0);
+        assert_eq!(logits, vec![4.0, -4.0, 4.0]);
+    }
+
+    #[test]
+    fn test_logit_bias() {
+        let mut bias = LogitBias::new(vec![(1, -3.0), (1, 1.0), (2, 0.5), (9, 100.0)]);
+        let mut logits = vec![1.0, 1.0, 1.0];
+        bias.process(&mut logits, 0);
+        assert_eq!(logits, vec![1.0, -1.0, 1.5]);
+    }
+
+    #[test]
+    fn test_processor_chain() {
+        let config = GenerationConfig {
+            temperature: 0.0,
+            repetition_penalty: 4.0,
+            ..GenerationConfig::default()
+        };
+        let logits = [2.0, 1.9, 0.0];
+        // Bias lifts token 2 above token 1 but not token 0
+        let mut sampler =
+            Sampler::from_config(&config).with_processor(LogitBias::new(vec![(2, 1.95)]));
+
+        // Token 0 wins, then the penalty drops it to 0.5 below both others
+        assert_eq!(sampler.sample(&logits, &config).unwrap(), 0);
+        assert_eq!(sampler.sample(&logits, &config).unwrap(), 2);
+        // Token 2 is penalized before its bias is added: 0.0 + 1.95 > 1.9
+        assert_eq!(sampler.sample(&logits, &config).unwrap(), 2);
+
+        sampler.reset();
+        assert_eq!(sampler.sample(&logits, &config).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_repetition_penalty_unobserve() {
+        let mut penalty = RepetitionPenalty::new(2.0);
+        penalty.observe(0);
+        penalty.observe(0);
+        penalty.unobserve(0);
+        penalty.unobserve(1);
+
+        let mut logits = vec![4.0, 4.0];
+        penalty.process(&mut logits, 1);
+        assert_eq!(logits, vec![2.0, 4.0]);
+
+        penalty.unobserve(0);
+        assert!(penalty.counts.is_empty());
+    }
+}
@@ -1,13 +1,37 @@
 // LLM module for Phi-3 model loading and inference
 
+use std::collections::HashMap;
+
+pub mod backend;
+pub mod chat_template;
 pub mod config;
+pub mod device;
+pub mod json_constraint;
+pub mod logit_processor;
+pub mod moderation;
 pub mod phi_model;
+pub mod prompt_cache;
+pub mod prompt_template;
+pub mod redaction;
 pub mod sampler;
+pub mod stream_buffer;
+pub mod throughput;
 pub mod tokenizer_wrapper;
 
+pub use backend::{InferenceBackend, MockBackend, MOCK_HIDDEN_SIZE};
+pub use chat_template::{ChatMessage, ChatRole, ChatTemplate};
 pub use config::ModelConfig;
+pub use device::{Device, DevicePreference};
+pub use json_constraint::JsonValidator;
+pub use logit_processor::{LogitBias, LogitProcessor, RepetitionPenalty};
+pub use moderation::{ModerationResult, BLOCKED_RESPONSE};
 pub use phi_model::PhiModel;
-pub use sampler::Sampler;
+pub use prompt_cache::PromptCache;
+pub use prompt_template::MissingVariable;
+pub use redaction::{PatternRedactor, RedactionFilter};
+pub use sampler::{SampleInfo, Sampler};
+pub use stream_buffer::StreamGranularity;
+pub use throughput::MetricsCallback;
 pub use tokenizer_wrapper::TokenizerWrapper;
 
 /// Model loading status
@@ -20,23 +44,325 @@ pub enum ModelStatus {
 }
 
 /// Generation parameters
+///
+/// Every field has a serde default, so JavaScript callers can pass partial
+/// configs such as `{ temperature: 0.2 }`.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct GenerationConfig {
+    #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
+    #[serde(default = "default_temperature")]
     pub temperature: f64,
+    /// `(token_index, temperature)` points, linearly interpolated by
+    /// generation step; overrides `temperature` when set
+    #[serde(default)]
+    pub temperature_schedule: Option<Vec<(usize, f64)>>,
+    #[serde(default = "default_top_p")]
     pub top_p: f64,
+    /// Tolerance when comparing the cumulative probability against `top_p`,
+    /// absorbing floating-point error over large vocabularies
+    #[serde(default = "default_top_p_epsilon")]
+    pub top_p_epsilon: f32,
+    #[serde(default = "default_top_k")]
     pub top_k: usize,
+    #[serde(default = "default_repetition_penalty")]
     pub repetition_penalty: f64,
+    /// Back up over the last prompt token and constrain the first generated
+    /// token to continue it (improves completions of partial words)
+    #[serde(default)]
+    pub token_healing: bool,
+    /// Wall-clock limit for a single generation in milliseconds
+    #[serde(default)]
+    pub max_duration_ms: Option<u64>,
+    /// Report streaming throughput every N tokens (0 disables)
+    #[serde(default)]
+    pub metrics_every: usize,
+    /// Yield to the browser event loop every N tokens while streaming so
+    /// the UI stays responsive (0 never yields)
+    #[serde(default = "default_yield_every")]
+    pub yield_every: usize,
+    /// Seed for reproducible sampling (None uses the platform RNG)
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Stop generating when any of these strings appears in the output;
+    /// the stop sequence itself is not returned
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    /// Limit generation to this fraction of the context left after the
+    /// prompt (`max_tokens` still applies as an upper bound)
+    #[serde(default)]
+    pub max_new_tokens_ratio: Option<f64>,
+    /// Added to the logit of every token whose text contains the key
+    /// (negative values suppress those tokens)
+    #[serde(default)]
+    pub token_bias: HashMap<String, f32>,
+    /// Only sample tokens that keep the output a valid JSON object, and
+    /// stop once the object is closed
+    #[serde(default)]
+    pub json_constraint: bool,
+    /// Run this request on another device than the model's (the weights
+    /// stay where `load` placed them)
+    #[serde(default)]
+    pub device: Option<DevicePreference>,
+    /// Stop when the output ends in whitespace holding more than this many
+    /// newlines (guards against models looping on blank lines)
+    #[serde(default)]
+    pub max_consecutive_newlines: Option<usize>,
+    /// How much text `generate_stream` buffers before each callback
+    #[serde(default)]
+    pub stream_granularity: StreamGranularity,
+    /// Log every sampled token with its probability and record them in
+    /// `GenerationOutput::sampling_trace`
+    #[serde(default)]
+    pub debug_sampling: bool,
+    /// Return (and stream first) the decoded prompt before the completion;
+    /// with `debug_sampling`, prompt tokens are traced too
+    #[serde(default)]
+    pub echo: bool,
+}
+
+fn default_max_tokens() -> usize {
+    512
+}
+
+fn default_temperature() -> f64 {
+    0.7
+}
+
+fn default_top_p() -> f64 {
+    0.9
+}
+
+fn default_top_p_epsilon() -> f32 {
+    1e-6
+}
+
+fn default_top_k() -> usize {
+    40
+}
+
+fn default_repetition_penalty() -> f64 {
+    1.1
+}
+
+fn default_yield_every() -> usize {
+    4
 }
 
 impl Default for GenerationConfig {
     fn default() -> Self {
         Self {
-            max_tokens: 512,
-            temperature: 0.7,
-            top_p: 0.9,
-            top_k: 40,
-            repetition_penalty: 1.1,
+            max_tokens: default_max_tokens(),
+            temperature: default_temperature(),
+            temperature_schedule: None,
+            top_p: default_top_p(),
+            top_p_epsilon: default_top_p_epsilon(),
+            top_k: default_top_k(),
+            repetition_penalty: default_repetition_penalty(),
+            token_healing: false,
+            max_duration_ms: None,
+            metrics_every: 0,
+            yield_every: default_yield_every(),
+            seed: None,
+            stop_sequences: Vec::new(),
+            max_new_tokens_ratio: None,
+            token_bias: HashMap::new(),
+            json_constraint: false,
+            device: None,
+            max_consecutive_newlines: None,
+            stream_granularity: StreamGranularity::Token,
+            debug_sampling: false,
+            echo: false,
+        }
+    }
+}
+
+impl GenerationConfig {
+    /// Preset for generating a single parseable JSON object
+    ///
+    /// Low temperature, output constrained to a JSON object (ending when
+    /// it closes) and code fences suppressed.
+    pub fn json_mode() -> Self {
+        Self {
+            temperature: 0.1,
+            token_bias: HashMap::from([("```".to_string(), -100.0)]),
+            json_constraint: true,
+            ..Self::default()
+        }
+    }
+
+    /// Temperature for the token at generation step `step`
+    ///
+    /// Interpolates linearly between schedule points and holds the first and
+    /// last temperatures outside them.
+    pub fn temperature_at(&self, step: usize) -> f64 {
+        let Some(schedule) = self.temperature_schedule.as_deref().filter(|s| !s.is_empty()) else {
+            return self.temperature;
+        };
+
+        let mut points = schedule.to_vec();
+        points.sort_by_key(|&(index, _)| index);
+
+        let after = points.partition_point(|&(index, _)| index <= step);
+        match (after.checked_sub(1).map(|i| points[i]), points.get(after)) {
+            (Some((_, temperature)), None) | (None, Some(&(_, temperature))) => temperature,
+            (Some((i0, t0)), Some(&(i1, t1))) => {
+                t0 + (t1 - t0) * (step - i0) as f64 / (i1 - i0) as f64
+            }
+            (None, None) => self.temperature,
+        }
+    }
+
+    /// Token budget for a prompt of `prompt_tokens` tokens in a model with
+    /// a `context_length`-token window
+    ///
+    /// `max_tokens`, further limited by `max_new_tokens_ratio` of the
+    /// remaining context when set.
+    pub fn effective_max_tokens(&self, context_length: usize, prompt_tokens: usize) -> usize {
+        match self.max_new_tokens_ratio {
+            Some(ratio) => {
+                let remaining = context_length.saturating_sub(prompt_tokens);
+                let budget = (ratio.clamp(0.0, 1.0) * remaining as f64).floor() as usize;
+                self.max_tokens.min(budget)
+            }
+            None => self.max_tokens,
         }
     }
+
+    /// Whether to yield to the event loop after `tokens_generated` tokens
+    pub fn should_yield(&self, tokens_generated: usize) -> bool {
+        self.yield_every > 0
+            && tokens_generated > 0
+            && tokens_generated.is_multiple_of(self.yield_every)
+    }
+
+    /// Whether `max_duration_ms` has elapsed since `start_ms`
+    pub fn is_timed_out(&self, start_ms: f64) -> bool {
+        self.max_duration_ms
+            .is_some_and(|limit| crate::utils::now_ms() - start_ms >= limit as f64)
+    }
+}
+
+/// Why generation stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Reached `max_tokens`
+    Length,
+    /// Model produced an end-of-sequence token or a stop sequence
+    Stop,
+    /// Exceeded `max_duration_ms`
+    Timeout,
+}
+
+/// Generated text with completion details
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GenerationOutput {
+    pub text: String,
+    pub finish_reason: FinishReason,
+    pub tokens_generated: usize,
+    /// Timing and throughput of the generation
+    pub metrics: crate::utils::MetricsSnapshot,
+    /// One record per sampled token when `debug_sampling` is on
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sampling_trace: Vec<SamplingRecord>,
+}
+
+/// A sampled token, recorded when `GenerationConfig::debug_sampling` is on
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SamplingRecord {
+    /// Generation step (0 for the first generated token), or the position
+    /// in the prompt for echoed prompt tokens
+    pub step: usize,
+    pub token_id: u32,
+    pub token: String,
+    /// Probability in the distribution the token was drawn from
+    pub probability: f32,
+    /// Picked by argmax rather than sampled
+    pub greedy: bool,
+    /// Echoed prompt token; `probability` is then the model's probability
+    /// of it given the preceding prompt tokens
+    pub prompt: bool,
+}
+
+/// One token of a `PhiModel::tokenize_preview`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TokenPiece {
+    pub id: u32,
+    /// Input text the token covers, including whitespace skipped before it
+    pub text: String,
+    /// Character span of `text` in the input
+    pub start: usize,
+    pub end: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yield_cadence() {
+        let config = GenerationConfig {
+            yield_every: 3,
+            ..GenerationConfig::default()
+        };
+        let yields: Vec<usize> = (0..=10).filter(|&n| config.should_yield(n)).collect();
+        assert_eq!(yields, vec![3, 6, 9]);
+
+        let never = GenerationConfig {
+            yield_every: 0,
+            ..GenerationConfig::default()
+        };
+        assert!((0..=100).all(|n| !never.should_yield(n)));
+    }
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: GenerationConfig = serde_json::from_str(r#"{ "temperature": 0.2 }"#).unwrap();
+        let defaults = GenerationConfig::default();
+
+        assert_eq!(config.temperature, 0.2);
+        assert_eq!(config.max_tokens, defaults.max_tokens);
+        assert_eq!(config.top_p, defaults.top_p);
+        assert_eq!(config.top_k, defaults.top_k);
+        assert_eq!(config.repetition_penalty, defaults.repetition_penalty);
+        assert_eq!(config.yield_every, defaults.yield_every);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_partial_js_config_uses_defaults() {
+        let js_config = js_sys::Object::new();
+        js_sys::Reflect::set(&js_config, &"max_tokens".into(), &10.into()).unwrap();
+
+        let config: GenerationConfig = serde_wasm_bindgen::from_value(js_config.into()).unwrap();
+        let defaults = GenerationConfig::default();
+
+        assert_eq!(config.max_tokens, 10);
+        assert_eq!(config.temperature, defaults.temperature);
+        assert_eq!(config.top_p, defaults.top_p);
+        assert_eq!(config.top_k, defaults.top_k);
+        assert_eq!(config.repetition_penalty, defaults.repetition_penalty);
+        assert!(!config.token_healing);
+        assert_eq!(config.max_duration_ms, None);
+    }
+
+    #[test]
+    fn test_max_new_tokens_ratio() {
+        let config = GenerationConfig {
+            max_tokens: 1000,
+            max_new_tokens_ratio: Some(0.5),
+            ..GenerationConfig::default()
+        };
+        assert_eq!(config.effective_max_tokens(4096, 96), 1000);
+        assert_eq!(config.effective_max_tokens(4096, 3096), 500);
+        assert_eq!(config.effective_max_tokens(4096, 4095), 0);
+        assert_eq!(config.effective_max_tokens(4096, 5000), 0);
+
+        let absolute = GenerationConfig {
+            max_tokens: 1000,
+            ..GenerationConfig::default()
+        };
+        assert_eq!(absolute.effective_max_tokens(4096, 3096), 1000);
+    }
 }
@@ -0,0 +1,20 @@
+/// Reply returned in place of a generation blocked by the post-check
+pub const BLOCKED_RESPONSE: &str = "I'm sorry, but I can't help with that.";
+
+/// Outcome of a moderation check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationResult {
+    Allow,
+    Block { reason: String },
+}
+
+/// Moderation check over a prompt or a generated response
+pub type ModerationFn = Box<dyn Fn(&str) -> ModerationResult>;
+
+/// Checks run before and after generation
+pub struct Moderation {
+    /// Runs on the prompt; a block fails generation
+    pub pre: ModerationFn,
+    /// Runs on the generated text; a block replaces it with `BLOCKED_RESPONSE`
+    pub post: ModerationFn,
+}
@@ -1,10 +1,27 @@
+use std::future::Future;
+use std::rc::Rc;
+
 use anyhow::{Result, Context};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Request, RequestInit, RequestMode, Response};
-use js_sys::Uint8Array;
 
-use super::{config::ModelConfig, GenerationConfig};
+use crate::error::LlmError;
+use crate::utils::fetch::fetch_bytes;
+use crate::utils::{now_ms, yield_now, GenerationMetrics, MetricsSnapshot};
+
+use super::{config::ModelConfig, FinishReason, GenerationConfig, GenerationOutput};
+use super::{SamplingRecord, TokenPiece};
+use super::chat_template::ChatMessage;
+use super::device::{webgpu_available, Device};
+use super::json_constraint::JsonValidator;
+use super::logit_processor::LogitBias;
+use super::moderation::{Moderation, ModerationResult, BLOCKED_RESPONSE};
+use super::prompt_cache::PromptCache;
+use super::backend::{InferenceBackend, ScriptedBackend};
+use super::redaction::{RedactionFilter, StreamRedactor};
+use super::sampler::{derive_seed, softmax, Sampler};
+use super::stream_buffer::{StreamBuffer, StreamGranularity};
+use super::throughput::{MetricsCallback, ThroughputMeter};
 use super::tokenizer_wrapper::TokenizerWrapper;
 
 // Note: Candle's WASM support is still experimental
@@ -14,6 +31,18 @@ pub struct PhiModel {
     config: ModelConfig,
     tokenizer: Option<TokenizerWrapper>,
     model_loaded: bool,
+    /// Inference backend; when absent, generation uses the mock responses
+    backend: Option<Box<dyn InferenceBackend>>,
+    /// Applied to streamed and final generated text
+    redaction: Option<Box<dyn RedactionFilter>>,
+    /// Reuses tokenizations of previously seen prompt prefixes
+    prompt_cache: Option<PromptCache>,
+    /// Prompt and response checks
+    moderation: Option<Moderation>,
+    /// Device selected by the last `load`
+    device: Option<Device>,
+    /// Reports whether WebGPU can be used
+    webgpu_probe: Box<dyn Fn() -> bool>,
     // TODO: Add actual Candle model when WASM support is complete
     // For now, we'll implement a simpler approach or use mock data
     // model: Option<Box<dyn ModelInterface>>,
@@ -27,32 +56,162 @@ impl PhiModel {
             config,
             tokenizer: None,
             model_loaded: false,
+            backend: None,
+            redaction: None,
+            prompt_cache: None,
+            moderation: None,
+            device: None,
+            webgpu_probe: Box::new(webgpu_available),
+        }
+    }
+
+    /// Create a ready-to-use model from an already loaded tokenizer and backend
+    pub fn with_backend(
+        config: ModelConfig,
+        tokenizer: TokenizerWrapper,
+        backend: Box<dyn InferenceBackend>,
+    ) -> Self {
+        Self {
+            config,
+            tokenizer: Some(tokenizer),
+            model_loaded: true,
+            backend: Some(backend),
+            redaction: None,
+            prompt_cache: None,
+            moderation: None,
+            device: None,
+            webgpu_probe: Box::new(webgpu_available),
+        }
+    }
+
+    /// Moderate prompts before generation and responses after it
+    ///
+    /// A blocked prompt fails with `LlmError::Blocked`; a blocked response
+    /// is replaced by `BLOCKED_RESPONSE`. Streaming APIs only run `pre`,
+    /// since streamed text has already reached the caller.
+    pub fn with_moderation(
+        mut self,
+        pre: impl Fn(&str) -> ModerationResult + 'static,
+        post: impl Fn(&str) -> ModerationResult + 'static,
+    ) -> Self {
+        self.moderation = Some(Moderation {
+            pre: Box::new(pre),
+            post: Box::new(post),
+        });
+        self
+    }
+
+    /// Replace the WebGPU availability check used by `load`
+    pub fn with_webgpu_probe(mut self, probe: impl Fn() -> bool + 'static) -> Self {
+        self.webgpu_probe = Box::new(probe);
+        self
+    }
+
+    /// Device selected by the last `load` (`None` before loading)
+    pub fn device(&self) -> Option<Device> {
+        self.device
+    }
+
+    /// Device a request runs on: the override in `config`, checked against
+    /// WebGPU availability, or else the device chosen at load
+    fn run_device(&self, config: &GenerationConfig) -> Result<Device> {
+        match config.device {
+            Some(preference) => preference.resolve(|| (self.webgpu_probe)()),
+            None => Ok(self.device.unwrap_or(Device::Cpu)),
+        }
+    }
+
+    /// Fail if the moderation pre-check blocks `prompt`
+    fn check_prompt(&self, prompt: &str) -> Result<()> {
+        if let Some(moderation) = &self.moderation {
+            if let ModerationResult::Block { reason } = (moderation.pre)(prompt) {
+                return Err(LlmError::Blocked(reason).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Redact generated text (e.g. `PatternRedactor::pii()`)
+    pub fn with_redaction_filter(mut self, filter: Box<dyn RedactionFilter>) -> Self {
+        self.set_redaction_filter(Some(filter));
+        self
+    }
+
+    /// Set or remove the redaction filter
+    pub fn set_redaction_filter(&mut self, filter: Option<Box<dyn RedactionFilter>>) {
+        self.redaction = filter;
+    }
+
+    /// Cache prompt tokenizations so resubmitted prefixes are not re-encoded
+    pub fn with_prompt_cache(mut self, cache: PromptCache) -> Self {
+        self.prompt_cache = Some(cache);
+        self
+    }
+
+    /// Get the prompt cache, if enabled
+    pub fn prompt_cache(&self) -> Option<&PromptCache> {
+        self.prompt_cache.as_ref()
+    }
+
+    /// Tokenize a prompt, through the prompt cache when enabled
+    fn encode_prompt(&self, tokenizer: &TokenizerWrapper, prompt: &str) -> Result<Vec<u32>> {
+        match &self.prompt_cache {
+            Some(cache) => cache.encode(tokenizer, prompt),
+            None => tokenizer.encode(prompt),
+        }
+    }
+
+    /// Fail if the prompt plus the generation budget exceeds the model's
+    /// context window
+    fn check_context(&self, prompt_tokens: usize, config: &GenerationConfig) -> Result<()> {
+        let context_length = self.config.context_length;
+        let max_tokens = config.effective_max_tokens(context_length, prompt_tokens);
+        if prompt_tokens + max_tokens > context_length {
+            return Err(LlmError::Config(format!(
+                "Prompt of {} tokens plus max_tokens {} exceeds the {}-token context window; \
+                 truncate the prompt or lower max_tokens",
+                prompt_tokens, max_tokens, context_length
+            ))
+            .into());
         }
+        Ok(())
     }
 
     /// Load the model from the configured URL
     pub async fn load(&mut self) -> Result<()> {
-        log::info!("Loading Phi-3 model from: {}", self.config.model_url);
-
-        // Step 1: Load tokenizer first
-        log::info!("Loading tokenizer from: {}", self.config.tokenizer_url);
-        let mut tokenizer = TokenizerWrapper::new(self.config.tokenizer_url.clone());
-        tokenizer.load().await
-            .context("Failed to load tokenizer")?;
+        self.config.validate().map_err(LlmError::Config)?;
 
-        self.tokenizer = Some(tokenizer);
-        log::info!("Tokenizer loaded successfully");
+        // Resolve the device first so a missing required GPU fails fast
+        let device = self
+            .config
+            .device_preference()
+            .resolve(&self.webgpu_probe)?;
+        log::info!("Loading Phi-3 model from: {} on {:?}", self.config.model_url, device);
 
-        // Step 2: Fetch model weights
-        log::info!("Fetching model weights...");
-        let model_bytes = self.fetch_model_bytes(&self.config.model_url).await
-            .context("Failed to fetch model bytes")?;
+        // Step 1: Fetch the tokenizer (unless preloaded) and the weights
+        // concurrently; they are independent downloads
+        let tokenizer = async {
+            if self.is_tokenizer_loaded() {
+                return Ok(None);
+            }
+            self.fetch_tokenizer().await.map(Some)
+        };
+        let weights = async {
+            log::info!("Fetching model weights...");
+            self.fetch_model_bytes(&self.config.model_url).await
+                .context("Failed to fetch model bytes")
+        };
+        let (tokenizer, model_bytes) = load_concurrently(tokenizer, weights).await?;
 
+        if let Some(tokenizer) = tokenizer {
+            self.tokenizer = Some(tokenizer);
+        }
         log::info!("Model bytes fetched: {} bytes", model_bytes.len());
 
-        // Step 3: Initialize device
+        // Step 2: Initialize device
         // Note: Full Candle WASM initialization will go here when ready
         // For now, we mark as loaded
+        self.device = Some(device);
         self.model_loaded = true;
 
         log::info!("✅ Model loaded successfully (placeholder mode until Candle WASM is fully supported)");
@@ -63,121 +222,476 @@ impl PhiModel {
 
     /// Fetch model bytes from URL
     async fn fetch_model_bytes(&self, url: &str) -> Result<Vec<u8>> {
-        let window = web_sys::window()
-            .context("No window object available")?;
-
-        let mut opts = RequestInit::new();
-        opts.method("GET");
-        opts.mode(RequestMode::Cors);
-
-        let request = Request::new_with_str_and_init(url, &opts)
-            .map_err(|e| anyhow::anyhow!("Failed to create request: {:?}", e))?;
-
-        let resp_value = JsFuture::from(window.fetch_with_request(&request))
+        fetch_bytes(url, &self.config.fetch_options())
             .await
-            .map_err(|e| anyhow::anyhow!("Fetch failed: {:?}", e))?;
-
-        let resp: Response = resp_value.dyn_into()
-            .map_err(|e| anyhow::anyhow!("Response conversion failed: {:?}", e))?;
+            .map_err(|e| LlmError::from(e).into())
+    }
 
-        if !resp.ok() {
-            anyhow::bail!("HTTP error: {}", resp.status());
-        }
+    /// Generate text based on a prompt
+    pub async fn generate(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+    ) -> Result<String> {
+        Ok(self.generate_with_details(prompt, config).await?.text)
+    }
 
-        let array_buffer = JsFuture::from(resp.array_buffer()
-            .map_err(|e| anyhow::anyhow!("array_buffer() failed: {:?}", e))?)
-            .await
-            .map_err(|e| anyhow::anyhow!("array_buffer await failed: {:?}", e))?;
+    /// Generate text and report why generation stopped
+    pub async fn generate_with_details(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+    ) -> Result<GenerationOutput> {
+        self.check_prompt(prompt)?;
 
-        let uint8_array = Uint8Array::new(&array_buffer);
-        let bytes = uint8_array.to_vec();
+        let mut output = self.generate_raw(prompt, config).await?;
+        if let Some(filter) = self.redaction.as_deref() {
+            output.text = filter.redact(&output.text);
+        }
+        if let Some(moderation) = &self.moderation {
+            if let ModerationResult::Block { reason } = (moderation.post)(&output.text) {
+                log::warn!("Generated response blocked by moderation: {}", reason);
+                output.text = BLOCKED_RESPONSE.to_string();
+            }
+        }
+        Ok(output)
+    }
 
-        Ok(bytes)
+    /// Generate `n` independent completions of the same prompt
+    ///
+    /// Each candidate samples with its own state; when `config.seed` is set,
+    /// candidate `i` uses a seed derived from it, so the set is reproducible.
+    pub async fn generate_n(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+        n: usize,
+    ) -> Result<Vec<GenerationOutput>> {
+        let mut outputs = Vec::with_capacity(n);
+        for i in 0..n {
+            let candidate_config = GenerationConfig {
+                seed: config.seed.map(|seed| derive_seed(seed, i)),
+                ..config.clone()
+            };
+            outputs.push(self.generate_with_details(prompt, &candidate_config).await?);
+        }
+        Ok(outputs)
     }
 
-    /// Generate text based on a prompt
-    pub async fn generate(
+    /// Generate without applying the redaction filter
+    async fn generate_raw(
         &self,
         prompt: &str,
         config: &GenerationConfig,
-    ) -> Result<String> {
+    ) -> Result<GenerationOutput> {
         if !self.is_loaded() {
-            anyhow::bail!("Model not loaded. Call load() first.");
+            return Err(LlmError::NotLoaded.into());
         }
 
         log::info!("Generating text for prompt: {} (max_tokens: {})", prompt, config.max_tokens);
 
         let tokenizer = self.tokenizer.as_ref()
-            .context("Tokenizer not loaded")?;
+            .ok_or(LlmError::NotLoaded)?;
 
         // Tokenize the prompt
-        let token_ids = tokenizer.encode(prompt)?;
+        let token_ids = self.encode_prompt(tokenizer, prompt)?;
+        self.check_context(token_ids.len(), config)?;
         log::debug!("Prompt tokenized to {} tokens", token_ids.len());
 
+        let mut no_metrics = |_: usize, _: f64, _: f64| Ok(());
+        let mut meter = ThroughputMeter::new(0, &mut no_metrics);
+
+        if let Some(backend) = self.backend.as_deref() {
+            let state =
+                DecodeState::new(tokenizer, token_ids, config, self.config.context_length);
+            return self
+                .decode_loop(backend, tokenizer, state, config, |_| Ok(()), &mut meter)
+                .await;
+        }
+
         // TODO: When Candle WASM is ready, implement actual inference here
-        // For now, provide an intelligent mock response
-        let response = self.mock_generate(prompt, config)?;
+        // For now, decode a canned response
+        let (backend, state) = self.mock_decode(tokenizer, prompt, token_ids, config)?;
+        self.decode_loop(&backend, tokenizer, state, config, |_| Ok(()), &mut meter)
+            .await
+    }
 
-        log::info!("Generation complete: {} tokens", response.split_whitespace().count());
+    /// Generate the assistant reply to a conversation
+    ///
+    /// Messages are rendered with the configured chat template.
+    pub async fn chat(&self, messages: &[ChatMessage], config: &GenerationConfig) -> Result<String> {
+        let prompt = self.render_chat(messages)?;
+        self.generate(&prompt, config).await
+    }
 
-        Ok(response)
+    /// Render a conversation with the configured chat template
+    pub fn render_chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        let template = self.config.chat_template().map_err(LlmError::Config)?;
+        Ok(template.render(messages))
     }
 
     /// Generate text with streaming (call callback for each token)
     pub async fn generate_stream<F>(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+        callback: F,
+    ) -> Result<GenerationOutput>
+    where
+        F: FnMut(String) -> Result<()>,
+    {
+        let mut no_metrics = |_: usize, _: f64, _: f64| Ok(());
+        self.generate_stream_with_metrics(prompt, config, callback, &mut no_metrics)
+            .await
+    }
+
+    /// Generate text with streaming and live throughput reporting
+    ///
+    /// Every `config.metrics_every` tokens, `on_metrics` is called with
+    /// `(tokens_generated, elapsed_ms, tokens_per_sec)`.
+    pub async fn generate_stream_with_metrics<F>(
         &self,
         prompt: &str,
         config: &GenerationConfig,
         mut callback: F,
-    ) -> Result<()>
+        on_metrics: &mut MetricsCallback<'_>,
+    ) -> Result<GenerationOutput>
+    where
+        F: FnMut(String) -> Result<()>,
+    {
+        self.check_prompt(prompt)?;
+
+        if config.stream_granularity == StreamGranularity::Token {
+            return self
+                .stream_redacted(prompt, config, callback, on_metrics)
+                .await;
+        }
+
+        let mut buffer = StreamBuffer::new(config.stream_granularity);
+        let output = self
+            .stream_redacted(
+                prompt,
+                config,
+                |delta| match buffer.push(&delta) {
+                    Some(text) => callback(text),
+                    None => Ok(()),
+                },
+                on_metrics,
+            )
+            .await?;
+
+        if let Some(text) = buffer.finish() {
+            callback(text)?;
+        }
+
+        Ok(output)
+    }
+
+    /// Stream with the redaction filter applied, if any
+    async fn stream_redacted<F>(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+        mut callback: F,
+        on_metrics: &mut MetricsCallback<'_>,
+    ) -> Result<GenerationOutput>
+    where
+        F: FnMut(String) -> Result<()>,
+    {
+        let Some(filter) = self.redaction.as_deref() else {
+            return self
+                .stream_raw(prompt, config, callback, on_metrics)
+                .await;
+        };
+
+        // Buffer deltas so spans crossing token boundaries are caught
+        let mut redactor = StreamRedactor::new();
+        let mut output = self
+            .stream_raw(
+                prompt,
+                config,
+                |delta| match redactor.push(filter, &delta) {
+                    Some(text) => callback(text),
+                    None => Ok(()),
+                },
+                on_metrics,
+            )
+            .await?;
+
+        if let Some(text) = redactor.finish(filter) {
+            callback(text)?;
+        }
+        output.text = filter.redact(&output.text);
+
+        Ok(output)
+    }
+
+    /// Stream without applying the redaction filter
+    async fn stream_raw<F>(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+        callback: F,
+        on_metrics: &mut MetricsCallback<'_>,
+    ) -> Result<GenerationOutput>
     where
         F: FnMut(String) -> Result<()>,
     {
         if !self.is_loaded() {
-            anyhow::bail!("Model not loaded. Call load() first.");
+            return Err(LlmError::NotLoaded.into());
         }
 
         log::info!("Streaming generation for prompt: {}", prompt);
 
         let tokenizer = self.tokenizer.as_ref()
-            .context("Tokenizer not loaded")?;
+            .ok_or(LlmError::NotLoaded)?;
 
         // Tokenize prompt
-        let _token_ids = tokenizer.encode(prompt)?;
+        let token_ids = self.encode_prompt(tokenizer, prompt)?;
+        self.check_context(token_ids.len(), config)?;
+
+        let mut meter = ThroughputMeter::new(config.metrics_every, on_metrics);
+
+        if let Some(backend) = self.backend.as_deref() {
+            let state =
+                DecodeState::new(tokenizer, token_ids, config, self.config.context_length);
+            return self
+                .decode_loop(backend, tokenizer, state, config, callback, &mut meter)
+                .await;
+        }
 
         // TODO: Implement actual streaming with Candle when ready
-        // For now, simulate streaming with mock response
-        let response = self.mock_generate(prompt, config)?;
+        // For now, stream a canned response
+        let (backend, state) = self.mock_decode(tokenizer, prompt, token_ids, config)?;
+        self.decode_loop(&backend, tokenizer, state, config, callback, &mut meter)
+            .await
+    }
 
-        // Simulate token-by-token streaming
-        let words: Vec<&str> = response.split_whitespace().collect();
-        for (i, word) in words.iter().enumerate() {
-            let token_text = if i < words.len() - 1 {
-                format!("{} ", word)
-            } else {
-                word.to_string()
-            };
+    /// Start a pull-based generation
+    ///
+    /// Nothing is decoded until `TokenStream::next_delta` is called, and each
+    /// call decodes only until new text is available.
+    pub fn token_stream(
+        self: &Rc<Self>,
+        prompt: &str,
+        config: &GenerationConfig,
+    ) -> Result<TokenStream> {
+        if !self.is_loaded() {
+            return Err(LlmError::NotLoaded.into());
+        }
+        self.check_prompt(prompt)?;
 
-            callback(token_text)?;
-
-            // Small delay to simulate inference (remove in production)
-            #[cfg(target_arch = "wasm32")]
-            {
-                use wasm_bindgen_futures::JsFuture;
-                let promise = js_sys::Promise::new(&mut |resolve, _reject| {
-                    web_sys::window()
-                        .unwrap()
-                        .set_timeout_with_callback_and_timeout_and_arguments_0(
-                            &resolve,
-                            10, // 10ms delay per token
-                        )
-                        .unwrap();
-                });
-                let _ = JsFuture::from(promise).await;
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+        let token_ids = self.encode_prompt(tokenizer, prompt)?;
+        self.check_context(token_ids.len(), config)?;
+        let device = self.run_device(config)?;
+
+        let (mock, state) = if self.backend.is_some() {
+            let context_length = self.config.context_length;
+            (None, DecodeState::new(tokenizer, token_ids, config, context_length))
+        } else {
+            let (backend, state) = self.mock_decode(tokenizer, prompt, token_ids, config)?;
+            (Some(backend), state)
+        };
+
+        Ok(TokenStream {
+            model: Rc::clone(self),
+            config: config.clone(),
+            state: Box::new(state),
+            mock,
+            device,
+            redactor: StreamRedactor::new(),
+            finished: false,
+        })
+    }
+
+    /// Generate text with streaming, passing both the new text and the full
+    /// text generated so far to the callback
+    pub async fn generate_stream_accumulated<F>(
+        &self,
+        prompt: &str,
+        config: &GenerationConfig,
+        mut callback: F,
+    ) -> Result<String>
+    where
+        F: FnMut(&str, &str) -> Result<()>,
+    {
+        let mut accumulated = String::new();
+        let output = self
+            .generate_stream(prompt, config, |delta| {
+                accumulated.push_str(&delta);
+                callback(&delta, &accumulated)
+            })
+            .await?;
+
+        Ok(output.text)
+    }
+
+    /// Get the top-`n` next-token candidates for a prompt
+    ///
+    /// Runs a single forward pass and returns decoded tokens with their
+    /// probabilities, most likely first. Intended for debugging UIs.
+    pub fn next_token_distribution(&self, prompt: &str, n: usize) -> Result<Vec<(String, f32)>> {
+        if !self.is_loaded() {
+            return Err(LlmError::NotLoaded.into());
+        }
+
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+        let backend = self.backend.as_deref()
+            .ok_or(LlmError::NotLoaded)
+            .context("No inference backend available")?;
+
+        let token_ids = tokenizer.encode(prompt)?;
+        let logits = backend.forward(&token_ids)?;
+        let probs = softmax(&logits);
+
+        let mut ranked: Vec<(usize, f32)> = probs.into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(n);
+
+        Ok(ranked
+            .into_iter()
+            .map(|(id, prob)| {
+                let token = tokenizer.token_text(id as u32).unwrap_or_default();
+                (token, prob)
+            })
+            .collect())
+    }
+
+    /// Embed text with the language model itself
+    ///
+    /// Mean-pools the backend's last hidden state over the tokens of `text`.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        if !self.is_loaded() {
+            return Err(LlmError::NotLoaded.into());
+        }
+
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+        let backend = self.backend.as_deref()
+            .ok_or(LlmError::NotLoaded)
+            .context("No inference backend available")?;
+
+        let token_ids = tokenizer.encode(text)?;
+        let states = backend.hidden_states(&token_ids)?;
+        let hidden_size = match states.first() {
+            Some(state) if !state.is_empty() => state.len(),
+            _ => {
+                return Err(LlmError::Tokenize(format!(
+                    "No hidden states to embed for text of length {}",
+                    text.len()
+                ))
+                .into())
+            }
+        };
+
+        let mut pooled = vec![0.0f32; hidden_size];
+        for state in &states {
+            for (sum, value) in pooled.iter_mut().zip(state) {
+                *sum += value;
             }
         }
+        let count = states.len() as f32;
+        pooled.iter_mut().for_each(|v| *v /= count);
 
-        Ok(())
+        Ok(pooled)
+    }
+
+    /// Token-by-token decoding through the inference backend
+    ///
+    /// Calls `callback` with the text delta for each generated token,
+    /// reports throughput to `meter` and returns the full generated text.
+    async fn decode_loop<F>(
+        &self,
+        backend: &dyn InferenceBackend,
+        tokenizer: &TokenizerWrapper,
+        mut state: DecodeState,
+        config: &GenerationConfig,
+        mut callback: F,
+        meter: &mut ThroughputMeter<'_>,
+    ) -> Result<GenerationOutput>
+    where
+        F: FnMut(String) -> Result<()>,
+    {
+        backend.select_device(self.run_device(config)?)?;
+
+        if let Some(prompt) = state.echo_prompt(backend, tokenizer, config)? {
+            callback(prompt)?;
+        }
+
+        while let Some(delta) = state.step(backend, tokenizer, config)? {
+            if !delta.is_empty() {
+                callback(delta)?;
+            }
+
+            meter.record(state.generated.len())?;
+
+            if config.should_yield(state.generated.len()) {
+                yield_now().await;
+            }
+        }
+
+        Ok(state.into_output())
+    }
+
+    /// Remove the last prompt token for token healing
+    ///
+    /// Returns the shortened context and the text of the removed token, which
+    /// the first generated token must then start with.
+    fn heal_prompt(
+        tokenizer: &TokenizerWrapper,
+        mut prompt_ids: Vec<u32>,
+    ) -> (Vec<u32>, Option<String>) {
+        if prompt_ids.len() < 2 {
+            return (prompt_ids, None);
+        }
+
+        let prefix = prompt_ids
+            .last()
+            .and_then(|&id| tokenizer.token_text(id))
+            .filter(|text| !text.trim().is_empty());
+
+        match prefix {
+            Some(prefix) => {
+                prompt_ids.pop();
+                log::debug!("Token healing: continuing prefix '{}'", prefix);
+                (prompt_ids, Some(prefix))
+            }
+            None => (prompt_ids, None),
+        }
+    }
+
+    /// Backend and decode state that generate the mock response for
+    /// `prompt` through the regular sampling and stopping logic
+    fn mock_decode(
+        &self,
+        tokenizer: &TokenizerWrapper,
+        prompt: &str,
+        prompt_ids: Vec<u32>,
+        config: &GenerationConfig,
+    ) -> Result<(ScriptedBackend, DecodeState)> {
+        let response = self.mock_generate(prompt, config)?;
+        let backend = ScriptedBackend::new(
+            prompt_ids.len(),
+            tokenizer.encode(&response)?,
+            tokenizer.eos_token_id(),
+            tokenizer.vocab_size(),
+        );
+
+        // Healing would shift the context the script is counted from
+        let no_healing = GenerationConfig {
+            token_healing: false,
+            ..config.clone()
+        };
+        let mut state =
+            DecodeState::new(tokenizer, prompt_ids, &no_healing, self.config.context_length);
+        if state.eos_token_id.is_none() {
+            state.max_tokens = state.max_tokens.min(backend.len());
+        }
+
+        Ok((backend, state))
     }
 
     /// Mock generation (placeholder until Candle WASM is ready)
@@ -196,13 +710,1245 @@ impl PhiModel {
         Ok(response)
     }
 
+    /// Load only the tokenizer, without fetching the weights
+    ///
+    /// Lets apps count tokens and validate prompts before the large
+    /// download; a later `load` reuses this tokenizer.
+    pub async fn load_tokenizer_only(&mut self) -> Result<()> {
+        self.config.validate().map_err(LlmError::Config)?;
+
+        self.tokenizer = Some(self.fetch_tokenizer().await?);
+        Ok(())
+    }
+
+    /// Fetch and parse the configured tokenizer
+    async fn fetch_tokenizer(&self) -> Result<TokenizerWrapper> {
+        log::info!("Loading tokenizer from: {}", self.config.tokenizer_url);
+        let mut tokenizer = TokenizerWrapper::new(self.config.tokenizer_url.clone())
+            .with_fetch_options(self.config.fetch_options());
+        tokenizer.load().await
+            .context("Failed to load tokenizer")?;
+
+        log::info!("Tokenizer loaded successfully");
+        Ok(tokenizer)
+    }
+
+    /// Number of tokens in `text`; needs only the tokenizer
+    pub fn count_tokens(&self, text: &str) -> Result<usize> {
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+        Ok(tokenizer.encode(text)?.len())
+    }
+
+    /// Split `text` into its tokens for display; needs only the tokenizer
+    ///
+    /// Whitespace the tokenizer skips is attached to the following token
+    /// (trailing whitespace to the last one), so the pieces tile the input.
+    pub fn tokenize_preview(&self, text: &str) -> Result<Vec<TokenPiece>> {
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+        let encoded = tokenizer.encode_with_offsets(text)?;
+        let chars: Vec<char> = text.chars().collect();
+
+        let mut pieces = Vec::with_capacity(encoded.len());
+        let mut start = 0;
+        for (i, &(id, (_, end))) in encoded.iter().enumerate() {
+            let end = if i + 1 == encoded.len() { chars.len() } else { end.max(start) };
+            pieces.push(TokenPiece {
+                id,
+                text: chars[start..end].iter().collect(),
+                start,
+                end,
+            });
+            start = end;
+        }
+        Ok(pieces)
+    }
+
     /// Check if model is loaded
     pub fn is_loaded(&self) -> bool {
         self.model_loaded && self.tokenizer.is_some()
     }
 
+    /// Check if the tokenizer is loaded (by `load` or `load_tokenizer_only`)
+    pub fn is_tokenizer_loaded(&self) -> bool {
+        self.tokenizer.is_some()
+    }
+
     /// Get model configuration
     pub fn config(&self) -> &ModelConfig {
         &self.config
     }
+
+    /// Replace the model configuration without recreating the model
+    ///
+    /// If the new config changes what is loaded (see
+    /// `ModelConfig::requires_reload`) the model is marked unloaded and
+    /// `load` must be called again; returns whether that is the case.
+    pub fn set_config(&mut self, config: ModelConfig) -> Result<bool> {
+        config.validate().map_err(LlmError::Config)?;
+
+        let needs_reload = self.config.requires_reload(&config);
+        if needs_reload && self.model_loaded {
+            log::info!("Model configuration changed, reload required");
+            self.model_loaded = false;
+        }
+        if self.config.tokenizer_url != config.tokenizer_url {
+            self.tokenizer = None;
+        }
+        self.config = config;
+
+        Ok(needs_reload)
+    }
+
+    /// Get mutable model configuration (fetch settings apply on next `load`)
+    pub fn config_mut(&mut self) -> &mut ModelConfig {
+        &mut self.config
+    }
+}
+
+/// Resumable state of token-by-token decoding through a backend
+struct DecodeState {
+    start_ms: f64,
+    context: Vec<u32>,
+    /// Text of the prompt token removed by token healing
+    heal_prefix: Option<String>,
+    heal_allowed: Vec<u32>,
+    /// Set when the output is constrained to JSON
+    json: Option<JsonValidator>,
+    eos_token_id: Option<u32>,
+    max_tokens: usize,
+    sampler: Sampler,
+    generated: Vec<u32>,
+    /// Decoded completion (without the healed prefix)
+    text: String,
+    /// Prefix of `text` already returned to the caller
+    emitted: String,
+    finish_reason: Option<FinishReason>,
+    metrics: GenerationMetrics,
+    /// Filled when `debug_sampling` is on
+    sampling_trace: Vec<SamplingRecord>,
+    /// Prompt tokens to echo, until `echo_prompt` decodes them
+    echo_ids: Option<Vec<u32>>,
+    /// Decoded prompt prepended to the output
+    echo_text: String,
+}
+
+impl DecodeState {
+    fn new(
+        tokenizer: &TokenizerWrapper,
+        prompt_ids: Vec<u32>,
+        config: &GenerationConfig,
+        context_length: usize,
+    ) -> Self {
+        let max_tokens = config.effective_max_tokens(context_length, prompt_ids.len());
+        let echo_ids = config.echo.then(|| prompt_ids.clone());
+        let (context, heal_prefix) = if config.token_healing {
+            PhiModel::heal_prompt(tokenizer, prompt_ids)
+        } else {
+            (prompt_ids, None)
+        };
+        let heal_allowed = heal_prefix
+            .as_deref()
+            .map(|prefix| tokenizer.tokens_with_prefix(prefix))
+            .unwrap_or_default();
+
+        let bias = config
+            .token_bias
+            .iter()
+            .flat_map(|(text, &bias)| {
+                tokenizer
+                    .tokens_containing(text)
+                    .into_iter()
+                    .map(move |id| (id, bias))
+            })
+            .collect::<Vec<_>>();
+        let mut sampler = Sampler::from_config(config);
+        if !bias.is_empty() {
+            sampler = sampler.with_processor(LogitBias::new(bias));
+        }
+
+        Self {
+            start_ms: now_ms(),
+            context,
+            heal_prefix,
+            heal_allowed,
+            json: config.json_constraint.then(JsonValidator::new),
+            eos_token_id: tokenizer.eos_token_id(),
+            max_tokens,
+            sampler,
+            generated: Vec::new(),
+            text: String::new(),
+            emitted: String::new(),
+            finish_reason: None,
+            metrics: GenerationMetrics::new(),
+            sampling_trace: Vec::new(),
+            echo_ids,
+            echo_text: String::new(),
+        }
+    }
+
+    /// Decode one token
+    ///
+    /// Returns the new text (empty while a multi-token character or a
+    /// possible stop sequence is incomplete), or `None` once generation has
+    /// finished.
+    fn step(
+        &mut self,
+        backend: &dyn InferenceBackend,
+        tokenizer: &TokenizerWrapper,
+        config: &GenerationConfig,
+    ) -> Result<Option<String>> {
+        if self.finish_reason.is_some() {
+            return Ok(None);
+        }
+        if self.generated.len() >= self.max_tokens {
+            return Ok(self.finish(FinishReason::Length));
+        }
+        if config.is_timed_out(self.start_ms) {
+            log::warn!("Generation timed out after {} tokens", self.generated.len());
+            return Ok(self.finish(FinishReason::Timeout));
+        }
+        if self.json.as_ref().is_some_and(JsonValidator::is_done) {
+            return Ok(self.finish(FinishReason::Stop));
+        }
+
+        let mut logits = backend.forward(&self.context)?;
+        if let Some(json) = &self.json {
+            if !mask_invalid_json(&mut logits, json, tokenizer) {
+                log::warn!("No token continues the JSON output");
+                return Ok(self.finish(FinishReason::Stop));
+            }
+        }
+
+        let token_id = if self.generated.is_empty() {
+            self.sampler.sample_with_allowed(&logits, config, &self.heal_allowed)?
+        } else {
+            self.sampler.sample(&logits, config)?
+        };
+        if config.debug_sampling {
+            self.trace_sample(tokenizer, token_id);
+        }
+
+        if Some(token_id) == self.eos_token_id {
+            return Ok(self.finish(FinishReason::Stop));
+        }
+
+        self.context.push(token_id);
+        self.generated.push(token_id);
+        self.metrics.record_token();
+        if let Some(json) = self.json.as_mut() {
+            json.push(&tokenizer.token_text(token_id).unwrap_or_default());
+        }
+
+        // Decode the whole completion and emit only the new text, so
+        // multi-token characters are never split
+        self.text = match self.decode_after_echo(tokenizer, config)? {
+            Some(completion) => completion,
+            None => {
+                let decoded = tokenizer.decode(&self.generated)?;
+                match self.heal_prefix.as_deref() {
+                    Some(prefix) => decoded.strip_prefix(prefix).unwrap_or(&decoded).to_string(),
+                    None => decoded,
+                }
+            }
+        };
+
+        if let Some(end) = find_stop(&self.text, &config.stop_sequences) {
+            self.text.truncate(end);
+            return Ok(self.finish(FinishReason::Stop));
+        }
+        if let Some(max) = config.max_consecutive_newlines {
+            if let Some(end) = newline_run_end(&self.text, max) {
+                log::warn!("Stopping after more than {} consecutive newlines", max);
+                self.text.truncate(end);
+                return Ok(self.finish(FinishReason::Stop));
+            }
+        }
+
+        // Hold back text that may be the start of a stop sequence
+        let ready = self.text.len() - stop_prefix_len(&self.text, &config.stop_sequences);
+        Ok(Some(self.emit(ready)))
+    }
+
+    /// Decode the prompt for `echo`, tracing its tokens when
+    /// `debug_sampling` is on; returns the text to emit first
+    fn echo_prompt(
+        &mut self,
+        backend: &dyn InferenceBackend,
+        tokenizer: &TokenizerWrapper,
+        config: &GenerationConfig,
+    ) -> Result<Option<String>> {
+        let Some(prompt_ids) = self.echo_ids.take() else {
+            return Ok(None);
+        };
+
+        if config.debug_sampling {
+            // The first token has no preceding context to score it
+            for position in 1..prompt_ids.len() {
+                let probs = softmax(&backend.forward(&prompt_ids[..position])?);
+                let token_id = prompt_ids[position];
+                self.sampling_trace.push(SamplingRecord {
+                    step: position,
+                    token_id,
+                    token: tokenizer.token_text(token_id).unwrap_or_default(),
+                    probability: probs.get(token_id as usize).copied().unwrap_or(0.0),
+                    greedy: false,
+                    prompt: true,
+                });
+            }
+        }
+
+        self.echo_text = tokenizer.decode(&prompt_ids)?;
+        Ok(Some(self.echo_text.clone()).filter(|text| !text.is_empty()))
+    }
+
+    /// With `echo`, the completion as decoded together with the prompt, so
+    /// the boundary between them (such as a separating space) is kept
+    fn decode_after_echo(
+        &self,
+        tokenizer: &TokenizerWrapper,
+        config: &GenerationConfig,
+    ) -> Result<Option<String>> {
+        if !config.echo {
+            return Ok(None);
+        }
+        let joint = tokenizer.decode(&self.context)?;
+        Ok(joint.strip_prefix(self.echo_text.as_str()).map(str::to_string))
+    }
+
+    /// Log and record how `token_id` was sampled
+    fn trace_sample(&mut self, tokenizer: &TokenizerWrapper, token_id: u32) {
+        let Some(sample) = self.sampler.last_sample() else {
+            return;
+        };
+        let record = SamplingRecord {
+            step: self.generated.len(),
+            token_id,
+            token: tokenizer.token_text(token_id).unwrap_or_default(),
+            probability: sample.probability,
+            greedy: sample.greedy,
+            prompt: false,
+        };
+        log::info!(
+            "Step {}: token {} {:?} p={:.4} ({})",
+            record.step,
+            record.token_id,
+            record.token,
+            record.probability,
+            if record.greedy { "greedy" } else { "sampled" }
+        );
+        self.sampling_trace.push(record);
+    }
+
+    /// Mark generation finished, returning any held-back text
+    fn finish(&mut self, reason: FinishReason) -> Option<String> {
+        log::info!("Decoded {} tokens ({:?})", self.generated.len(), reason);
+        self.finish_reason = Some(reason);
+        self.metrics.finish();
+        Some(self.emit(self.text.len())).filter(|rest| !rest.is_empty())
+    }
+
+    /// Return the text up to byte `end` that was not emitted yet
+    fn emit(&mut self, end: usize) -> String {
+        let delta = self.text[..end]
+            .strip_prefix(self.emitted.as_str())
+            .unwrap_or_default()
+            .to_string();
+        self.emitted.push_str(&delta);
+        delta
+    }
+
+    fn into_output(self) -> GenerationOutput {
+        GenerationOutput {
+            text: self.echo_text + &self.emitted,
+            finish_reason: self.finish_reason.unwrap_or(FinishReason::Length),
+            tokens_generated: self.generated.len(),
+            metrics: self.metrics.snapshot(),
+            sampling_trace: self.sampling_trace,
+        }
+    }
+}
+
+/// If the whitespace at the end of `text` holds more than `max` newlines,
+/// the byte offset just after its `max`-th newline
+fn newline_run_end(text: &str, max: usize) -> Option<usize> {
+    let start = text.trim_end().len();
+    let newlines: Vec<usize> = text[start..].match_indices('\n').map(|(i, _)| start + i).collect();
+    if newlines.len() <= max {
+        return None;
+    }
+    Some(match max {
+        0 => start,
+        _ => newlines[max - 1] + 1,
+    })
+}
+
+/// Mask the logits of tokens that would make the output invalid JSON
+///
+/// Returns false if no token remains.
+fn mask_invalid_json(
+    logits: &mut [f32],
+    json: &JsonValidator,
+    tokenizer: &TokenizerWrapper,
+) -> bool {
+    let mut any_valid = false;
+    for (id, logit) in logits.iter_mut().enumerate() {
+        if *logit == f32::NEG_INFINITY {
+            continue;
+        }
+        let valid = tokenizer
+            .token_text(id as u32)
+            .is_some_and(|text| !text.is_empty() && json.accepts(&text));
+        if valid {
+            any_valid = true;
+        } else {
+            *logit = f32::NEG_INFINITY;
+        }
+    }
+    any_valid
+}
+
+/// Byte offset of the earliest stop sequence in `text`
+fn find_stop(text: &str, stop_sequences: &[String]) -> Option<usize> {
+    stop_sequences
+        .iter()
+        .filter(|stop| !stop.is_empty())
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+/// Length of the longest suffix of `text` that is a proper prefix of a
+/// stop sequence
+fn stop_prefix_len(text: &str, stop_sequences: &[String]) -> usize {
+    stop_sequences
+        .iter()
+        .flat_map(|stop| {
+            (1..stop.len())
+                .filter(|&len| stop.is_char_boundary(len) && text.ends_with(&stop[..len]))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Pull-based generation started by `PhiModel::token_stream`
+///
+/// Holds a reference to the model, so consumers such as a JS
+/// `ReadableStream` can drive decoding one token at a time. The redaction
+/// filter is applied as in `generate_stream`.
+pub struct TokenStream {
+    model: Rc<PhiModel>,
+    config: GenerationConfig,
+    state: Box<DecodeState>,
+    /// Backend producing the mock response when the model has none
+    mock: Option<ScriptedBackend>,
+    /// Device each step runs on
+    device: Device,
+    redactor: StreamRedactor,
+    finished: bool,
+}
+
+impl TokenStream {
+    /// Decode until new text is available; `None` once generation is done
+    pub fn next_delta(&mut self) -> Result<Option<String>> {
+        while !self.finished {
+            let Some(delta) = self.next_raw()? else {
+                self.finished = true;
+                return Ok(self
+                    .model
+                    .redaction
+                    .as_deref()
+                    .and_then(|filter| self.redactor.finish(filter)));
+            };
+
+            let text = match self.model.redaction.as_deref() {
+                Some(filter) => self.redactor.push(filter, &delta),
+                None => Some(delta),
+            };
+            if let Some(text) = text.filter(|text| !text.is_empty()) {
+                return Ok(Some(text));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Number of tokens decoded so far
+    pub fn tokens_generated(&self) -> usize {
+        self.state.generated.len()
+    }
+
+    /// Timing and throughput so far
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.state.metrics.snapshot()
+    }
+
+    /// Generation config the stream was started with
+    pub fn config(&self) -> &GenerationConfig {
+        &self.config
+    }
+
+    /// Next unredacted delta from the backend or mock response
+    fn next_raw(&mut self) -> Result<Option<String>> {
+        let backend = match &self.mock {
+            Some(mock) => Some(mock as &dyn InferenceBackend),
+            None => self.model.backend.as_deref(),
+        };
+        let (Some(backend), Some(tokenizer)) = (backend, self.model.tokenizer.as_ref()) else {
+            return Err(LlmError::NotLoaded.into());
+        };
+        backend.select_device(self.device)?;
+        if let Some(prompt) = self.state.echo_prompt(backend, tokenizer, &self.config)? {
+            return Ok(Some(prompt));
+        }
+        self.state.step(backend, tokenizer, &self.config)
+    }
+}
+
+/// Drive the tokenizer and weight downloads together, failing as soon as
+/// either fails (the other is dropped)
+async fn load_concurrently<T, W>(
+    tokenizer: impl Future<Output = Result<T>>,
+    weights: impl Future<Output = Result<W>>,
+) -> Result<(T, W)> {
+    futures::try_join!(tokenizer, weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::MockBackend;
+    use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+
+    #[tokio::test]
+    async fn test_token_healing_continues_prefix() {
+        // Vocab: <unk>=0, </s>=1, say=2, hel=3, hello=4, help=5, world=6
+        let tokenizer = word_level_tokenizer(&["say", "hel", "hello", "help", "world"]);
+        // The backend strongly prefers "world", then "hello"
+        let logits = vec![0.0, 0.0, 0.0, 1.0, 5.0, 2.0, 10.0];
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(logits)),
+        );
+
+        let config = GenerationConfig {
+            max_tokens: 1,
+            temperature: 0.0,
+            token_healing: true,
+            ..GenerationConfig::default()
+        };
+        let healed = model.generate("say hel", &config).await.unwrap();
+        assert_eq!(healed, "lo");
+
+        let config = GenerationConfig {
+            token_healing: false,
+            ..config
+        };
+        let unhealed = model.generate("say hel", &config).await.unwrap();
+        assert_eq!(unhealed, "world");
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream_accumulated() {
+        let tokenizer = word_level_tokenizer(&["to", "be", "or", "not"]);
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(vec![0.0, 0.0, 3.0, 2.5, 1.0, 0.5])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 4,
+            temperature: 0.0,
+            repetition_penalty: 2.0,
+            ..GenerationConfig::default()
+        };
+
+        let mut snapshots: Vec<String> = Vec::new();
+        let mut deltas = String::new();
+        let streamed = model
+            .generate_stream_accumulated("to", &config, |delta, accumulated| {
+                deltas.push_str(delta);
+                snapshots.push(accumulated.to_string());
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert!(!snapshots.is_empty());
+        for pair in snapshots.windows(2) {
+            assert!(pair[1].starts_with(&pair[0]));
+            assert!(pair[1].len() > pair[0].len());
+        }
+        assert_eq!(snapshots.last().unwrap(), &streamed);
+        assert_eq!(deltas, streamed);
+        assert_eq!(streamed, model.generate("to", &config).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stream_metrics_cadence() {
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["la"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 5.0]).with_delay_ms(2)),
+        );
+        let config = GenerationConfig {
+            max_tokens: 7,
+            temperature: 0.0,
+            metrics_every: 3,
+            ..GenerationConfig::default()
+        };
+
+        let mut reports = Vec::new();
+        let mut on_metrics = |tokens: usize, elapsed_ms: f64, rate: f64| {
+            reports.push((tokens, elapsed_ms, rate));
+            Ok(())
+        };
+        let output = model
+            .generate_stream_with_metrics("la", &config, |_| Ok(()), &mut on_metrics)
+            .await
+            .unwrap();
+
+        assert_eq!(output.tokens_generated, 7);
+        let counts: Vec<usize> = reports.iter().map(|r| r.0).collect();
+        assert_eq!(counts, vec![3, 6]);
+        for &(tokens, elapsed_ms, rate) in &reports {
+            assert!(elapsed_ms > 0.0);
+            assert!((rate - tokens as f64 * 1000.0 / elapsed_ms).abs() < 1e-6);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generation_timeout() {
+        let tokenizer = word_level_tokenizer(&["tick"]);
+        let backend = MockBackend::new(vec![0.0, 0.0, 1.0]).with_delay_ms(20);
+        let model = PhiModel::with_backend(ModelConfig::default(), tokenizer, Box::new(backend));
+        let config = GenerationConfig {
+            max_tokens: 1000,
+            temperature: 0.0,
+            max_duration_ms: Some(100),
+            ..GenerationConfig::default()
+        };
+
+        let output = model.generate_with_details("tick", &config).await.unwrap();
+
+        assert_eq!(output.finish_reason, FinishReason::Timeout);
+        // Each step takes at least 20ms, so at most 5 steps fit before the
+        // deadline however slow the machine is
+        assert!((1..=5).contains(&output.tokens_generated));
+    }
+
+    #[test]
+    fn test_next_token_distribution() {
+        let tokenizer = word_level_tokenizer(&["the", "cat", "dog", "sat"]);
+        let logits = vec![0.0, 0.0, 0.5, 3.0, 2.0, 1.0];
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(logits)),
+        );
+
+        let distribution = model.next_token_distribution("the", 3).unwrap();
+
+        assert_eq!(distribution.len(), 3);
+        assert_eq!(distribution[0].0, "cat");
+        assert!(distribution.windows(2).all(|w| w[0].1 >= w[1].1));
+        let total: f32 = distribution.iter().map(|(_, p)| p).sum();
+        assert!(total <= 1.0 + 1e-6);
+
+        // NaN logits are ranked rather than panicking
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["the", "cat"]),
+            Box::new(MockBackend::new(vec![0.0, f32::NAN, 1.0, 2.0])),
+        );
+        assert_eq!(model.next_token_distribution("the", 2).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_redaction_filter_applies_to_generate_and_stream() {
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["secret"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 5.0])),
+        )
+        .with_redaction_filter(Box::new(|text: &str| text.replace("secret", "***")));
+        let config = GenerationConfig {
+            max_tokens: 3,
+            temperature: 0.0,
+            ..GenerationConfig::default()
+        };
+
+        let text = model.generate("secret", &config).await.unwrap();
+        assert!(!text.contains("secret"));
+        assert!(text.contains("***"));
+
+        let mut streamed = String::new();
+        let output = model
+            .generate_stream("secret", &config, |delta| {
+                streamed.push_str(&delta);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(streamed, output.text);
+        assert_eq!(output.text, text);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_cache_reuses_shared_prefix() {
+        let tokenizer = word_level_tokenizer(&["user", "hi", "there", "again"]);
+        let mut model =
+            PhiModel::new(ModelConfig::default()).with_prompt_cache(PromptCache::default());
+        model.tokenizer = Some(tokenizer);
+        model.model_loaded = true;
+        let config = GenerationConfig::default();
+
+        model.generate("user hi there user again", &config).await.unwrap();
+        model.generate("user hi there user again user hi", &config).await.unwrap();
+
+        let cache = model.prompt_cache().unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(
+            cache.encoded_bytes(),
+            "user hi there user again".len() + "hi there user again user hi".len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_n_seeded_candidates() {
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["a", "b", "c", "d"]),
+            Box::new(MockBackend::new(vec![-10.0, -10.0, 1.0, 1.0, 1.0, 1.0])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 8,
+            temperature: 1.0,
+            top_k: 0,
+            top_p: 1.0,
+            repetition_penalty: 1.0,
+            seed: Some(42),
+            ..GenerationConfig::default()
+        };
+
+        let texts = |outputs: Vec<GenerationOutput>| {
+            outputs.into_iter().map(|o| o.text).collect::<Vec<_>>()
+        };
+        let first = texts(model.generate_n("a", &config, 3).await.unwrap());
+        let second = texts(model.generate_n("a", &config, 3).await.unwrap());
+
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first[1], first[2]);
+    }
+
+    #[tokio::test]
+    async fn test_token_stream_matches_generate_stream() {
+        let model = Rc::new(PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["to", "be", "or", "not"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 3.0, 2.5, 1.0, 0.5])),
+        ));
+        let config = GenerationConfig {
+            max_tokens: 6,
+            temperature: 0.0,
+            repetition_penalty: 2.0,
+            ..GenerationConfig::default()
+        };
+
+        let mut stream = model.token_stream("to", &config).unwrap();
+        let mut pulled = Vec::new();
+        while let Some(delta) = stream.next_delta().unwrap() {
+            pulled.push(delta);
+        }
+
+        assert_eq!(stream.tokens_generated(), 6);
+        assert!(stream.next_delta().unwrap().is_none());
+        assert_eq!(pulled.concat(), model.generate("to", &config).await.unwrap());
+    }
+
+    #[test]
+    fn test_set_config_marks_reload() {
+        let mut model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["a"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 1.0])),
+        );
+
+        let retried = ModelConfig {
+            max_retries: 7,
+            ..ModelConfig::default()
+        };
+        assert!(!model.set_config(retried).unwrap());
+        assert!(model.is_loaded());
+        assert_eq!(model.config().max_retries, 7);
+
+        let invalid = ModelConfig {
+            chat_template: "unknown".to_string(),
+            ..ModelConfig::default()
+        };
+        assert!(model.set_config(invalid).is_err());
+        assert!(model.is_loaded());
+
+        let moved = ModelConfig {
+            model_url: "https://example.com/other.gguf".to_string(),
+            ..ModelConfig::default()
+        };
+        assert!(model.set_config(moved).unwrap());
+        assert!(!model.is_loaded());
+    }
+
+    #[tokio::test]
+    async fn test_moderation_blocks_prompt_and_response() {
+        use crate::error::error_kind;
+
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["hello", "forbidden"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 0.0, 1.0])),
+        )
+        .with_moderation(
+            |prompt: &str| {
+                if prompt.contains("hello") {
+                    ModerationResult::Block {
+                        reason: "greetings are off-limits".to_string(),
+                    }
+                } else {
+                    ModerationResult::Allow
+                }
+            },
+            |response: &str| {
+                if response.contains("forbidden") {
+                    ModerationResult::Block {
+                        reason: "forbidden output".to_string(),
+                    }
+                } else {
+                    ModerationResult::Allow
+                }
+            },
+        );
+        let config = GenerationConfig {
+            max_tokens: 2,
+            temperature: 0.0,
+            ..Default::default()
+        };
+
+        let err = model.generate("hello", &config).await.unwrap_err();
+        assert_eq!(error_kind(&err), "Blocked");
+        assert!(err.to_string().contains("greetings are off-limits"));
+
+        // The backend always produces "forbidden", which the post-check replaces
+        let text = model.generate("forbidden", &config).await.unwrap();
+        assert_eq!(text, BLOCKED_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn test_load_requires_available_webgpu() {
+        use crate::llm::DevicePreference;
+
+        let config = ModelConfig::default().with_device(DevicePreference::WebGpu);
+        let mut model = PhiModel::new(config).with_webgpu_probe(|| false);
+
+        let err = model.load().await.unwrap_err();
+        assert!(err.to_string().contains("WebGPU is required"));
+        assert_eq!(model.device(), None);
+        assert!(!model.is_loaded());
+    }
+
+    /// Model without a backend (mock responses) whose tokenizer knows
+    /// every word of the "hi" response
+    fn mock_model() -> PhiModel {
+        let tokenizer = word_level_tokenizer(&[
+            "Hello", "!", "I", "'", "m", "Phi", "-", "3", "mini", "running", "in", "your",
+            "browser", "via", "WebAssembly", ".", "How", "can", "help", "you", "today", "?",
+        ]);
+        let mut model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(Vec::new())),
+        );
+        model.backend = None;
+        model
+    }
+
+    #[tokio::test]
+    async fn test_mock_generation_respects_limits() {
+        let model = mock_model();
+
+        let full = model.generate_with_details("hi", &GenerationConfig::default()).await.unwrap();
+        assert_eq!(full.finish_reason, FinishReason::Stop);
+        assert_eq!(full.tokens_generated, 24);
+        assert!(full.text.starts_with("Hello ! I ' m Phi - 3 - mini running"));
+        assert!(full.text.ends_with("help you today ?"));
+
+        let config = GenerationConfig {
+            max_tokens: 3,
+            ..Default::default()
+        };
+        let short = model.generate_with_details("hi", &config).await.unwrap();
+        assert_eq!(short.text, "Hello ! I");
+        assert_eq!(short.finish_reason, FinishReason::Length);
+        assert_eq!(short.tokens_generated, 3);
+        assert_eq!(short.metrics.tokens_generated, 3);
+        assert!(short.metrics.time_to_first_token_ms.unwrap() <= short.metrics.total_time_ms);
+    }
+
+    #[tokio::test]
+    async fn test_mock_generation_stop_sequences() {
+        let model = Rc::new(mock_model());
+        let config = GenerationConfig {
+            stop_sequences: vec!["your brow".to_string(), "today".to_string()],
+            ..Default::default()
+        };
+        let expected = "Hello ! I ' m Phi - 3 - mini running in ";
+
+        let output = model.generate_with_details("hi", &config).await.unwrap();
+        assert_eq!(output.text, expected);
+        assert_eq!(output.finish_reason, FinishReason::Stop);
+
+        // The partial match "your" is held back, never streamed
+        let mut streamed = String::new();
+        model
+            .generate_stream("hi", &config, |delta| {
+                streamed.push_str(&delta);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(streamed, expected);
+
+        let mut stream = model.token_stream("hi", &config).unwrap();
+        let mut pulled = String::new();
+        while let Some(delta) = stream.next_delta().unwrap() {
+            pulled.push_str(&delta);
+        }
+        assert_eq!(pulled, expected);
+        assert_eq!(stream.tokens_generated(), 14);
+    }
+
+    #[tokio::test]
+    async fn test_long_prompt_reduces_generation_budget() {
+        let mut model = mock_model();
+        model.config.context_length = 30;
+        let config = GenerationConfig {
+            max_new_tokens_ratio: Some(0.5),
+            ..Default::default()
+        };
+
+        // 2 prompt tokens leave 28, half of which may be generated
+        let output = model.generate_with_details("hi you", &config).await.unwrap();
+        assert_eq!(output.tokens_generated, 14);
+        assert_eq!(output.finish_reason, FinishReason::Length);
+
+        // 20 prompt tokens leave 10
+        let long_prompt = ["hi"; 20].join(" ");
+        let output = model.generate_with_details(&long_prompt, &config).await.unwrap();
+        assert_eq!(output.tokens_generated, 5);
+    }
+
+    #[tokio::test]
+    async fn test_json_mode_yields_parseable_json() {
+        // Vocab: <unk>=0, </s>=1, ```=2, json=3, {=4, "answer"=5, :=6, 42=7, }=8, ,=9
+        let tokenizer =
+            word_level_tokenizer(&["```", "json", "{", "\"answer\"", ":", "42", "}", ","]);
+        // The backend prefers a code fence, strings over numbers and closing over `,`
+        let logits = vec![-5.0, -5.0, 10.0, 9.0, 4.0, 8.0, 6.0, 7.0, 5.0, 3.0];
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(logits)),
+        );
+
+        let plain = GenerationConfig {
+            max_tokens: 4,
+            temperature: 0.0,
+            ..Default::default()
+        };
+        let text = model.generate("q", &plain).await.unwrap();
+        assert!(text.starts_with("```"));
+
+        let config = GenerationConfig {
+            seed: Some(7),
+            ..GenerationConfig::json_mode()
+        };
+        let output = model.generate_with_details("q", &config).await.unwrap();
+        assert_eq!(output.finish_reason, FinishReason::Stop);
+        let value: serde_json::Value = serde_json::from_str(&output.text).unwrap();
+        assert_eq!(value, serde_json::json!({ "answer": "answer" }));
+    }
+
+    #[tokio::test]
+    async fn test_per_call_device_override() {
+        use crate::llm::DevicePreference;
+
+        let tokenizer = word_level_tokenizer(&["a"]);
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(vec![0.0, 1.0, 0.0])),
+        )
+        .with_webgpu_probe(|| false);
+        let active = || model.backend.as_ref().unwrap().active_device();
+
+        model.generate("a", &GenerationConfig::default()).await.unwrap();
+        assert_eq!(active(), Some(Device::Cpu));
+
+        // A required GPU that isn't available fails before decoding
+        let gpu = GenerationConfig {
+            device: Some(DevicePreference::WebGpu),
+            ..Default::default()
+        };
+        let err = model.generate("a", &gpu).await.unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Config");
+
+        let model = model.with_webgpu_probe(|| true);
+        model.generate("a", &gpu).await.unwrap();
+        assert_eq!(model.backend.as_ref().unwrap().active_device(), Some(Device::WebGpu));
+        assert_eq!(model.device(), None);
+
+        let cpu = GenerationConfig {
+            device: Some(DevicePreference::Cpu),
+            ..Default::default()
+        };
+        model.generate("a", &cpu).await.unwrap();
+        assert_eq!(model.backend.as_ref().unwrap().active_device(), Some(Device::Cpu));
+    }
+
+    #[test]
+    fn test_count_tokens_with_tokenizer_only() {
+        let mut model = PhiModel::new(ModelConfig::default());
+        let err = model.count_tokens("to be").unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "NotLoaded");
+
+        // What `load_tokenizer_only` leaves behind
+        model.tokenizer = Some(word_level_tokenizer(&["to", "be", "or", "not"]));
+        assert_eq!(model.count_tokens("to be or not to be").unwrap(), 6);
+        assert!(model.is_tokenizer_loaded());
+        assert!(!model.is_loaded());
+
+        // A different tokenizer URL drops the preloaded tokenizer
+        let config = ModelConfig {
+            tokenizer_url: "https://example.com/other/tokenizer.json".to_string(),
+            ..ModelConfig::default()
+        };
+        model.set_config(config).unwrap();
+        assert!(!model.is_tokenizer_loaded());
+    }
+
+    #[tokio::test]
+    async fn test_max_consecutive_newlines_stops_runaway() {
+        // Vocab: <unk>=0, </s>=1, \n=2, ok=3
+        let tokenizer = word_level_tokenizer(&["\n", "ok"]);
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(vec![-5.0, -5.0, 5.0, 0.0])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 10,
+            temperature: 0.0,
+            repetition_penalty: 1.0,
+            ..Default::default()
+        };
+
+        let output = model.generate_with_details("ok", &config).await.unwrap();
+        assert_eq!(output.finish_reason, FinishReason::Length);
+        assert_eq!(output.text.matches('\n').count(), 10);
+
+        let capped = GenerationConfig {
+            max_consecutive_newlines: Some(2),
+            ..config
+        };
+        let output = model.generate_with_details("ok", &capped).await.unwrap();
+        assert_eq!(output.finish_reason, FinishReason::Stop);
+        assert_eq!(output.tokens_generated, 3);
+        assert_eq!(output.text.matches('\n').count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stream_sentence_granularity() {
+        let model = mock_model();
+        let config = GenerationConfig {
+            stream_granularity: StreamGranularity::Sentence,
+            ..Default::default()
+        };
+
+        let mut calls = Vec::new();
+        let output = model
+            .generate_stream("hi", &config, |delta| {
+                calls.push(delta);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            calls,
+            vec![
+                "Hello ! ",
+                "I ' m Phi - 3 - mini running in your browser via WebAssembly . ",
+                "How can I help you today ?",
+            ]
+        );
+        assert_eq!(calls.concat(), output.text);
+    }
+
+    #[test]
+    fn test_tokenize_preview_tiles_input() {
+        let model = mock_model();
+        let text = "Hello!  I'm Phi ";
+
+        let pieces = model.tokenize_preview(text).unwrap();
+        let texts: Vec<&str> = pieces.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, vec!["Hello", "!", "  I", "'", "m", " Phi "]);
+
+        let ids: Vec<u32> = pieces.iter().map(|p| p.id).collect();
+        assert_eq!(ids, model.tokenizer.as_ref().unwrap().encode(text).unwrap());
+
+        assert_eq!(pieces[0].start, 0);
+        assert!(pieces.windows(2).all(|w| w[0].end == w[1].start));
+        assert_eq!(pieces.last().unwrap().end, text.chars().count());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_over_context_length_is_rejected() {
+        let mut model = mock_model();
+        model.config.context_length = 30;
+        let config = GenerationConfig {
+            max_tokens: 10,
+            ..Default::default()
+        };
+
+        // 21 prompt tokens + 10 > 30
+        let long_prompt = ["hi"; 21].join(" ");
+        let err = model.generate(&long_prompt, &config).await.unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Config");
+        assert!(err.to_string().contains("30-token context window"));
+        let streamed = model.generate_stream(&long_prompt, &config, |_| Ok(())).await;
+        assert!(streamed.is_err());
+
+        // 20 + 10 fits exactly
+        let prompt = ["hi"; 20].join(" ");
+        let output = model.generate_with_details(&prompt, &config).await.unwrap();
+        assert_eq!(output.tokens_generated, 10);
+    }
+
+    #[test]
+    fn test_load_fetches_concurrently() {
+        use futures::FutureExt;
+        use std::cell::RefCell;
+
+        let started = RefCell::new(Vec::new());
+        let fetch = |name: &'static str| {
+            let started = &started;
+            async move {
+                started.borrow_mut().push(name);
+                futures::future::pending::<()>().await;
+                Ok(name)
+            }
+        };
+
+        // One poll starts both downloads although neither finishes
+        let pending = load_concurrently(fetch("tokenizer"), fetch("weights")).now_or_never();
+        assert!(pending.is_none());
+        assert_eq!(*started.borrow(), vec!["tokenizer", "weights"]);
+
+        // A tokenizer failure is returned without waiting for the weights
+        let failing = async { Err::<(), _>(anyhow::anyhow!("tokenizer.json: 404")) };
+        let result = load_concurrently(failing, fetch("weights")).now_or_never();
+        let err = result.expect("should fail without waiting").unwrap_err();
+        assert!(err.to_string().contains("404"));
+    }
+
+    #[tokio::test]
+    async fn test_debug_sampling_records_tokens() {
+        // Vocab: <unk>=0, </s>=1, a=2, b=3
+        let tokenizer = word_level_tokenizer(&["a", "b"]);
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(vec![-100.0, -100.0, 1.0, 0.0])),
+        );
+        let greedy = GenerationConfig {
+            max_tokens: 3,
+            temperature: 0.0,
+            repetition_penalty: 1.0,
+            ..Default::default()
+        };
+
+        let output = model.generate_with_details("a", &greedy).await.unwrap();
+        assert!(output.sampling_trace.is_empty());
+
+        let debug = GenerationConfig {
+            debug_sampling: true,
+            ..greedy.clone()
+        };
+        let output = model.generate_with_details("a", &debug).await.unwrap();
+        assert_eq!(output.sampling_trace.len(), 3);
+        for (step, record) in output.sampling_trace.iter().enumerate() {
+            assert_eq!(record.step, step);
+            assert_eq!(record.token, "a");
+            assert!(record.greedy);
+            // e / (e + 1)
+            assert!((record.probability - 0.7311).abs() < 1e-4);
+        }
+
+        let sampled = GenerationConfig {
+            temperature: 1.0,
+            top_p: 1.0,
+            seed: Some(7),
+            max_tokens: 8,
+            ..debug
+        };
+        let output = model.generate_with_details("a", &sampled).await.unwrap();
+        let tokens: Vec<&str> = output.sampling_trace.iter().map(|r| r.token.as_str()).collect();
+        assert_eq!(tokens.join(" "), output.text);
+        assert!(output.sampling_trace.iter().all(|r| !r.greedy));
+    }
+
+    #[tokio::test]
+    async fn test_echo_prepends_prompt() {
+        // Vocab: <unk>=0, </s>=1, a=2, b=3
+        let tokenizer = word_level_tokenizer(&["a", "b"]);
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            tokenizer,
+            Box::new(MockBackend::new(vec![-100.0, -100.0, 0.0, 1.0])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 2,
+            temperature: 0.0,
+            repetition_penalty: 1.0,
+            ..Default::default()
+        };
+
+        let completion = model.generate("a a", &config).await.unwrap();
+        assert_eq!(completion, "b b");
+
+        let echo = GenerationConfig {
+            echo: true,
+            debug_sampling: true,
+            ..config
+        };
+        let mut streamed = Vec::new();
+        let output = model
+            .generate_stream("a a", &echo, |delta| {
+                streamed.push(delta);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(output.text, "a a b b");
+        assert_eq!(streamed[0], "a a");
+        assert_eq!(streamed.concat(), output.text);
+        assert_eq!(output.tokens_generated, 2);
+
+        // The second prompt token, then the two generated ones
+        let trace = &output.sampling_trace;
+        assert_eq!(trace.len(), 3);
+        assert!(trace[0].prompt && trace[0].token == "a");
+        assert!((trace[0].probability - 1.0 / (1.0 + std::f32::consts::E)).abs() < 1e-4);
+        assert!(trace[1..].iter().all(|r| !r.prompt && r.token == "b"));
+    }
 }
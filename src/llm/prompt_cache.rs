@@ -0,0 +1,244 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use super::tokenizer_wrapper::{TokenOffsets, TokenizerWrapper};
+
+/// Default number of prompts kept by a `PromptCache`
+pub const DEFAULT_PROMPT_CACHE_ENTRIES: usize = 8;
+
+/// Cached tokens encoded again to check the boundary with the new text
+const BOUNDARY_TOKENS: usize = 3;
+
+/// A tokenized prompt
+struct CachedPrompt {
+    prompt: String,
+    token_ids: Vec<u32>,
+    /// Byte offsets of each token in `prompt`
+    offsets: TokenOffsets,
+}
+
+/// Caches prompt tokenizations so a growing prompt only encodes its suffix
+///
+/// Chat apps resubmit the whole conversation each turn. When a new prompt
+/// starts with a cached one, only the text from a few tokens before the end
+/// of the cached prompt is encoded. Encoding that overlap again checks that
+/// the tokenizer splits the boundary as a full encode would (BPE merges and
+/// SentencePiece's leading `▁` depend on context); when it does not, the
+/// whole prompt is encoded instead, so the result always equals a full
+/// encode. Holds token ids only; backends keep their own KV state.
+pub struct PromptCache {
+    capacity: usize,
+    entries: RefCell<VecDeque<CachedPrompt>>,
+    hits: Cell<usize>,
+    /// Bytes of prompt text passed to the tokenizer
+    encoded_bytes: Cell<usize>,
+}
+
+impl PromptCache {
+    /// Create a cache holding up to `capacity` prompts
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: RefCell::new(VecDeque::new()),
+            hits: Cell::new(0),
+            encoded_bytes: Cell::new(0),
+        }
+    }
+
+    /// Encode `prompt`, reusing the tokens of the longest cached prefix
+    ///
+    /// Always returns the same ids as `tokenizer.encode(prompt)`.
+    pub fn encode(&self, tokenizer: &TokenizerWrapper, prompt: &str) -> Result<Vec<u32>> {
+        let reused = match self.longest_prefix(prompt) {
+            Some((index, keep)) => {
+                let entries = self.entries.borrow();
+                self.encode_after(tokenizer, prompt, &entries[index], keep)?
+            }
+            None => None,
+        };
+        let (token_ids, offsets) = match reused {
+            Some(encoded) => {
+                self.hits.set(self.hits.get() + 1);
+                encoded
+            }
+            None => {
+                self.encoded_bytes.set(self.encoded_bytes.get() + prompt.len());
+                tokenizer.encode_with_byte_offsets(prompt)?
+            }
+        };
+
+        self.insert(CachedPrompt {
+            prompt: prompt.to_string(),
+            token_ids: token_ids.clone(),
+            offsets,
+        });
+        Ok(token_ids)
+    }
+
+    /// Number of prompts served partly from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// Total bytes of prompt text that had to be tokenized
+    pub fn encoded_bytes(&self) -> usize {
+        self.encoded_bytes.get()
+    }
+
+    /// Number of cached prompts
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Drop all cached prompts
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+
+    /// Encode `prompt` from `BOUNDARY_TOKENS` before the first `keep`
+    /// tokens of `cached` end, and splice the result onto the cached tokens
+    ///
+    /// The first re-encoded token may differ (e.g. gain a `▁`), so it is
+    /// taken from the cache; the following re-encoded tokens up to `keep`
+    /// must equal the cached ones, otherwise `None` is returned.
+    fn encode_after(
+        &self,
+        tokenizer: &TokenizerWrapper,
+        prompt: &str,
+        cached: &CachedPrompt,
+        keep: usize,
+    ) -> Result<Option<(Vec<u32>, TokenOffsets)>> {
+        let first = keep - BOUNDARY_TOKENS;
+        let window_start = cached.offsets[first].0;
+        let window = &prompt[window_start..];
+        let (ids, window_offsets) = tokenizer.encode_with_byte_offsets(window)?;
+        self.encoded_bytes.set(self.encoded_bytes.get() + window.len());
+
+        let offsets: TokenOffsets = window_offsets
+            .into_iter()
+            .map(|(start, end)| (start + window_start, end + window_start))
+            .collect();
+        let in_sync = ids.len() >= BOUNDARY_TOKENS
+            && ids[1..BOUNDARY_TOKENS] == cached.token_ids[first + 1..keep]
+            && offsets[1..BOUNDARY_TOKENS] == cached.offsets[first + 1..keep];
+        if !in_sync {
+            log::debug!("Prompt cache boundary differs from a full encode; re-encoding");
+            return Ok(None);
+        }
+
+        let mut token_ids = cached.token_ids[..=first].to_vec();
+        token_ids.extend_from_slice(&ids[1..]);
+        let mut all_offsets = cached.offsets[..=first].to_vec();
+        all_offsets.extend_from_slice(&offsets[1..]);
+        Ok(Some((token_ids, all_offsets)))
+    }
+
+    /// Index of the longest cached prefix of `prompt` and how many of its
+    /// tokens can be reused
+    fn longest_prefix(&self, prompt: &str) -> Option<(usize, usize)> {
+        let entries = self.entries.borrow();
+        let (index, entry) = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| prompt.starts_with(entry.prompt.as_str()))
+            .max_by_key(|(_, entry)| entry.prompt.len())?;
+
+        // Keep all but the last token; it may merge with the new text
+        let keep = entry.token_ids.len().checked_sub(1)?;
+        (keep >= BOUNDARY_TOKENS).then_some((index, keep))
+    }
+
+    fn insert(&self, cached: CachedPrompt) {
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|entry| entry.prompt != cached.prompt);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(cached);
+    }
+}
+
+impl Default for PromptCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROMPT_CACHE_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::tokenizer_wrapper::{metaspace_bpe_tokenizer, word_level_tokenizer};
+
+    #[test]
+    fn test_shared_prefix_encodes_only_suffix() {
+        let tokenizer = word_level_tokenizer(&["user", "assistant", "hi", "hello", "bye"]);
+        let cache = PromptCache::default();
+
+        let first = "user hi assistant hello user bye";
+        let second = "user hi assistant hello user bye assistant hello";
+
+        assert_eq!(cache.encode(&tokenizer, first).unwrap(), tokenizer.encode(first).unwrap());
+        assert_eq!(cache.encoded_bytes(), first.len());
+
+        assert_eq!(cache.encode(&tokenizer, second).unwrap(), tokenizer.encode(second).unwrap());
+        assert_eq!(cache.hits(), 1);
+        // Only the last cached tokens and the new text are re-encoded
+        let reencoded = "assistant hello user bye assistant hello";
+        assert_eq!(cache.encoded_bytes(), first.len() + reencoded.len());
+    }
+
+    #[test]
+    fn test_metaspace_bpe_matches_full_encode() {
+        let tokenizer = metaspace_bpe_tokenizer(&[
+            ("\u{2581}", "h"),
+            ("\u{2581}h", "i"),
+            ("e", "l"),
+            ("el", "l"),
+            ("ell", "o"),
+            ("\u{2581}h", "ello"),
+            ("\u{2581}", "w"),
+            ("o", "r"),
+            ("\u{2581}w", "or"),
+        ]);
+        let cache = PromptCache::default();
+
+        // Each prompt extends the last, often in the middle of a word
+        let prompts = [
+            "hi hi hi",
+            "hi hi hi h",
+            "hi hi hi hel",
+            "hi hi hi hello",
+            "hi hi hi hellowor",
+        ];
+        for prompt in prompts {
+            assert_eq!(
+                cache.encode(&tokenizer, prompt).unwrap(),
+                tokenizer.encode(prompt).unwrap(),
+                "{}",
+                prompt
+            );
+        }
+        assert!(cache.hits() > 0);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let tokenizer = word_level_tokenizer(&["a", "b", "c"]);
+        let cache = PromptCache::new(2);
+
+        for prompt in ["a a", "b b", "c c"] {
+            cache.encode(&tokenizer, prompt).unwrap();
+        }
+        assert_eq!(cache.len(), 2);
+
+        cache.encode(&tokenizer, "a a b").unwrap();
+        assert_eq!(cache.hits(), 0);
+    }
+}
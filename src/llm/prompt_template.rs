@@ -0,0 +1,88 @@
+// `{{variable}}` substitution for system prompts and other templates
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::error::LlmError;
+
+/// What `render_with` does with a placeholder that has no value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingVariable {
+    /// Leave the placeholder in the output unchanged
+    #[default]
+    Keep,
+    /// Fail with a `Config` error naming the variable
+    Error,
+}
+
+/// Replace every `{{name}}` in `template` with `vars[name]`, leaving
+/// placeholders without a value as they are
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String> {
+    render_with(template, vars, MissingVariable::Keep)
+}
+
+/// `render` with a choice of what happens to unknown variables
+///
+/// Names are trimmed, so `{{ name }}` works too. Substituted values are not
+/// scanned again, and an unclosed `{{` is copied through.
+pub fn render_with(
+    template: &str,
+    vars: &HashMap<String, String>,
+    missing: MissingVariable,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find("{{") {
+        let Some(close) = rest[open + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[open..open + 2 + close + 2];
+        let name = placeholder[2..placeholder.len() - 2].trim();
+
+        output.push_str(&rest[..open]);
+        match vars.get(name) {
+            Some(value) => output.push_str(value),
+            None if missing == MissingVariable::Keep => output.push_str(placeholder),
+            None => {
+                return Err(
+                    LlmError::Config(format!("Missing prompt variable: {}", name)).into()
+                )
+            }
+        }
+        rest = &rest[open + placeholder.len()..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_variables() {
+        let vars = HashMap::from([
+            ("name".to_string(), "Ada".to_string()),
+            ("date".to_string(), "{{name}}".to_string()),
+        ]);
+        let template = "Hi {{ name }}, today is {{date}}. {{tone}} {{unclosed";
+
+        assert_eq!(
+            render(template, &vars).unwrap(),
+            "Hi Ada, today is {{name}}. {{tone}} {{unclosed"
+        );
+
+        let err = render_with(template, &vars, MissingVariable::Error).unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Config");
+        assert!(err.to_string().contains("tone"));
+
+        let complete = "{{name}}: {{date}}";
+        assert_eq!(
+            render_with(complete, &vars, MissingVariable::Error).unwrap(),
+            "Ada: {{name}}"
+        );
+    }
+}
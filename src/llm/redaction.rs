@@ -0,0 +1,149 @@
+use anyhow::Result;
+use regex::Regex;
+
+use crate::error::LlmError;
+
+/// Characters held back while streaming so spans split across tokens can
+/// still be matched before any part of them is emitted
+pub const DEFAULT_HOLDBACK_CHARS: usize = 64;
+
+/// Rewrites generated text before it reaches the caller
+pub trait RedactionFilter {
+    /// Return `text` with blocked content replaced
+    fn redact(&self, text: &str) -> String;
+}
+
+impl<F: Fn(&str) -> String> RedactionFilter for F {
+    fn redact(&self, text: &str) -> String {
+        self(text)
+    }
+}
+
+/// Regex-based redaction, replacing each pattern with its label
+pub struct PatternRedactor {
+    patterns: Vec<(Regex, String)>,
+}
+
+impl PatternRedactor {
+    /// Create a redactor from `(pattern, replacement)` pairs
+    pub fn new(patterns: &[(&str, &str)]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|&(pattern, replacement)| {
+                Regex::new(pattern)
+                    .map(|regex| (regex, replacement.to_string()))
+                    .map_err(|e| LlmError::Config(format!("Invalid pattern '{}': {}", pattern, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    /// Built-in redactor for email addresses and phone numbers
+    pub fn pii() -> Self {
+        Self::new(&[
+            (r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "[EMAIL]"),
+            (
+                r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{3}\)|\b\d{3})[\s.-]?\d{3}[\s.-]?\d{4}\b",
+                "[PHONE]",
+            ),
+        ])
+        .expect("built-in patterns are valid")
+    }
+}
+
+impl RedactionFilter for PatternRedactor {
+    fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for (regex, replacement) in &self.patterns {
+            redacted = regex
+                .replace_all(&redacted, replacement.as_str())
+                .into_owned();
+        }
+        redacted
+    }
+}
+
+/// Applies a `RedactionFilter` to streamed text deltas
+///
+/// The whole stream is redacted on every push and only the part more than
+/// `holdback` characters from the end is released, so a span completed by
+/// a later token is redacted before any of it is emitted. The filter is
+/// passed on each call, so the redactor can live alongside its owner.
+pub(crate) struct StreamRedactor {
+    holdback: usize,
+    raw: String,
+    emitted: usize,
+}
+
+impl StreamRedactor {
+    pub(crate) fn new() -> Self {
+        Self {
+            holdback: DEFAULT_HOLDBACK_CHARS,
+            raw: String::new(),
+            emitted: 0,
+        }
+    }
+
+    /// Add a raw delta; returns redacted text that is safe to emit
+    pub(crate) fn push(&mut self, filter: &dyn RedactionFilter, delta: &str) -> Option<String> {
+        self.raw.push_str(delta);
+        let redacted = filter.redact(&self.raw);
+
+        let release = redacted
+            .char_indices()
+            .rev()
+            .nth(self.holdback.saturating_sub(1))
+            .map_or(0, |(i, _)| i);
+        self.release(&redacted, release)
+    }
+
+    /// Release everything still held back
+    pub(crate) fn finish(&mut self, filter: &dyn RedactionFilter) -> Option<String> {
+        let redacted = filter.redact(&self.raw);
+        self.release(&redacted, redacted.len())
+    }
+
+    fn release(&mut self, redacted: &str, end: usize) -> Option<String> {
+        if end <= self.emitted {
+            return None;
+        }
+        let text = redacted.get(self.emitted..end)?.to_string();
+        self.emitted = end;
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pii_patterns() {
+        let redactor = PatternRedactor::pii();
+        assert_eq!(
+            redactor.redact("Mail jane.doe@example.com or call (555) 123-4567."),
+            "Mail [EMAIL] or call [PHONE]."
+        );
+        assert_eq!(redactor.redact("Version 3.5 of 12 items"), "Version 3.5 of 12 items");
+    }
+
+    #[test]
+    fn test_streamed_email_split_across_tokens() {
+        let redactor = PatternRedactor::pii();
+        let mut stream = StreamRedactor::new();
+        let filler = "This sentence is long enough to push text past the holdback. ";
+
+        let mut chunks = Vec::new();
+        for delta in [filler, "Write to jo", "hn@exam", "ple.com for ", "details.", filler] {
+            chunks.extend(stream.push(&redactor, delta));
+        }
+        chunks.extend(stream.finish(&redactor));
+
+        assert!(chunks.iter().all(|c| !c.contains("jo") && !c.contains('@')));
+        assert_eq!(
+            chunks.concat(),
+            format!("{}Write to [EMAIL] for details.{}", filler, filler)
+        );
+    }
+}
@@ -1,14 +1,35 @@
 use anyhow::Result;
-use std::collections::HashMap;
 
+use super::logit_processor::{processors_from_config, LogitProcessor, RepetitionPenalty};
 use super::GenerationConfig;
 
+/// How the last token was chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleInfo {
+    pub token_id: u32,
+    /// Probability of the token in the final (processed, filtered)
+    /// distribution
+    pub probability: f32,
+    /// Picked by argmax (temperature 0) rather than drawn
+    pub greedy: bool,
+}
+
 /// Token sampler for text generation
 pub struct Sampler {
-    /// Previously generated token IDs (for repetition penalty)
+    /// Previously generated token IDs
     generated_tokens: Vec<u32>,
-    /// Token frequency count (for repetition penalty)
-    token_counts: HashMap<u32, usize>,
+    /// Applied in order to the logits before temperature and filtering
+    processors: Vec<Box<dyn LogitProcessor>>,
+    /// Applies `config.repetition_penalty` on each call to `sample` (None
+    /// when `from_config` installed the penalty as a processor)
+    config_penalty: Option<RepetitionPenalty>,
+    /// Reusable logits/probabilities buffer (vocab_size)
+    buffer: Vec<f32>,
+    /// Reusable token order buffer for top-k/top-p filtering
+    order: Vec<usize>,
+    /// Seeded random state (None uses the platform RNG)
+    rng_state: Option<u64>,
+    last_sample: Option<SampleInfo>,
 }
 
 impl Sampler {
@@ -16,18 +37,52 @@ impl Sampler {
     pub fn new() -> Self {
         Self {
             generated_tokens: Vec::new(),
-            token_counts: HashMap::new(),
+            processors: Vec::new(),
+            config_penalty: Some(RepetitionPenalty::new(1.0)),
+            buffer: Vec::new(),
+            order: Vec::new(),
+            rng_state: None,
+            last_sample: None,
+        }
+    }
+
+    /// Create a sampler whose random draws are reproducible from `seed`
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng_state: Some(seed),
+            ..Self::new()
+        }
+    }
+
+    /// Create a sampler seeded from `config.seed`, if set, with the logit
+    /// processors `config` asks for (such as the repetition penalty)
+    pub fn from_config(config: &GenerationConfig) -> Self {
+        Self {
+            processors: processors_from_config(config),
+            config_penalty: None,
+            ..config.seed.map_or_else(Self::new, Self::with_seed)
         }
     }
 
+    /// Append a logit processor to the end of the chain
+    pub fn with_processor(mut self, processor: impl LogitProcessor + 'static) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
     /// Reset the sampler state
     pub fn reset(&mut self) {
         self.generated_tokens.clear();
-        self.token_counts.clear();
+        for processor in self.history_processors() {
+            processor.reset();
+        }
     }
 
     /// Sample the next token from logits
     ///
+    /// All intermediate steps run in place on a buffer owned by the sampler,
+    /// so no vocab-sized allocations happen after the first call.
+    ///
     /// # Arguments
     /// * `logits` - Raw logits from the model (vocab_size)
     /// * `config` - Generation configuration (temperature, top_k, top_p, etc.)
@@ -39,74 +94,242 @@ impl Sampler {
             anyhow::bail!("Logits cannot be empty");
         }
 
-        // Step 1: Apply repetition penalty
-        let mut adjusted_logits = logits.to_vec();
-        self.apply_repetition_penalty(&mut adjusted_logits, config.repetition_penalty);
+        self.buffer.clear();
+        self.buffer.extend_from_slice(logits);
 
-        // Step 2: Apply temperature scaling
-        if config.temperature > 0.0 {
-            for logit in &mut adjusted_logits {
-                *logit /= config.temperature as f32;
+        self.sample_buffer(config)
+    }
+
+    /// Sample the next token, restricted to a set of allowed token IDs
+    ///
+    /// Used by token healing to force the first generated token to continue
+    /// the prefix removed from the prompt. An empty `allowed` set leaves the
+    /// logits unconstrained.
+    pub fn sample_with_allowed(
+        &mut self,
+        logits: &[f32],
+        config: &GenerationConfig,
+        allowed: &[u32],
+    ) -> Result<u32> {
+        if allowed.is_empty() {
+            return self.sample(logits, config);
+        }
+        if logits.is_empty() {
+            anyhow::bail!("Logits cannot be empty");
+        }
+
+        self.buffer.clear();
+        self.buffer.resize(logits.len(), f32::NEG_INFINITY);
+        for &token_id in allowed {
+            let idx = token_id as usize;
+            if idx < logits.len() {
+                self.buffer[idx] = logits[idx];
             }
         }
 
-        // Step 3: Convert logits to probabilities (softmax)
-        let probs = softmax(&adjusted_logits);
+        self.sample_buffer(config)
+    }
 
-        // Step 4: Apply top-k filtering
-        let probs = if config.top_k > 0 && config.top_k < probs.len() {
-            top_k_filtering(&probs, config.top_k)
-        } else {
-            probs
-        };
+    /// Sample one draft token per row of logits without updating history
+    ///
+    /// Every row is sampled against the same processor state, as a
+    /// speculative decoder would see it before verification. Call `accept`
+    /// with the tokens that are kept; rejected drafts leave the sampler
+    /// unchanged.
+    pub fn sample_batch(
+        &mut self,
+        logits_batch: &[&[f32]],
+        config: &GenerationConfig,
+    ) -> Result<Vec<u32>> {
+        let mut tokens = Vec::with_capacity(logits_batch.len());
+
+        for logits in logits_batch {
+            if logits.is_empty() {
+                anyhow::bail!("Logits cannot be empty");
+            }
 
-        // Step 5: Apply top-p (nucleus) filtering
-        let probs = if config.top_p < 1.0 {
-            top_p_filtering(&probs, config.top_p)
-        } else {
-            probs
-        };
+            self.buffer.clear();
+            self.buffer.extend_from_slice(logits);
+            tokens.push(self.pick(config)?);
+        }
+
+        Ok(tokens)
+    }
+
+    /// Commit accepted tokens to the history seen by the processors
+    pub fn accept(&mut self, tokens: &[u32]) {
+        for &token_id in tokens {
+            self.record(token_id);
+        }
+    }
+
+    /// Turn the logits in the buffer into the final distribution and sample
+    fn sample_buffer(&mut self, config: &GenerationConfig) -> Result<u32> {
+        let token_id = self.pick(config)?;
+
+        // Step 7: Track this token for the processors
+        self.record(token_id);
+
+        Ok(token_id)
+    }
+
+    /// Choose a token from the logits in the buffer
+    fn pick(&mut self, config: &GenerationConfig) -> Result<u32> {
+        let temperature = self.compute_probs(config);
 
         // Step 6: Sample from the filtered distribution
-        let token_id = if config.temperature == 0.0 {
+        let greedy = temperature == 0.0;
+        let token_id = if greedy {
             // Greedy sampling (temperature 0)
-            argmax(&probs)
+            argmax(&self.buffer)
         } else {
             // Multinomial sampling
-            multinomial_sample(&probs)?
+            let random_value = self.next_random();
+            multinomial_sample(&self.buffer, random_value)
         };
 
-        // Step 7: Track this token for repetition penalty
+        self.last_sample = Some(SampleInfo {
+            token_id,
+            probability: self.buffer.get(token_id as usize).copied().unwrap_or(0.0),
+            greedy,
+        });
+        Ok(token_id)
+    }
+
+    /// How the most recently sampled token was chosen
+    pub fn last_sample(&self) -> Option<SampleInfo> {
+        self.last_sample
+    }
+
+    /// Uniform random value in [0, 1)
+    fn next_random(&mut self) -> f32 {
+        match self.rng_state.as_mut() {
+            Some(state) => {
+                let bits = splitmix64(state);
+                // Top 24 bits give a uniformly spaced f32 in [0, 1)
+                (bits >> 40) as f32 / (1u64 << 24) as f32
+            }
+            None => platform_random(),
+        }
+    }
+
+    /// Track a generated token
+    fn record(&mut self, token_id: u32) {
         self.generated_tokens.push(token_id);
-        *self.token_counts.entry(token_id).or_insert(0) += 1;
+        for processor in self.history_processors() {
+            processor.observe(token_id);
+        }
+    }
 
-        Ok(token_id)
+    /// Every processor that tracks the token history
+    fn history_processors(&mut self) -> impl Iterator<Item = &mut (dyn LogitProcessor + 'static)> {
+        let config_penalty = self.config_penalty.iter_mut().map(|p| p as &mut dyn LogitProcessor);
+        config_penalty.chain(self.processors.iter_mut().map(|p| p.as_mut()))
     }
 
-    /// Apply repetition penalty to logits
-    fn apply_repetition_penalty(&self, logits: &mut [f32], penalty: f64) {
-        if penalty == 1.0 {
-            return; // No penalty
+    /// Convert the logits in the buffer to filtered probabilities in place
+    ///
+    /// Returns the temperature used for the current generation step.
+    fn compute_probs(&mut self, config: &GenerationConfig) -> f64 {
+        // Step 1: Run the logit processors
+        let step = self.generated_tokens.len();
+        if let Some(penalty) = &mut self.config_penalty {
+            penalty.set_penalty(config.repetition_penalty);
+            penalty.process(&mut self.buffer, step);
+        }
+        for processor in &mut self.processors {
+            processor.process(&mut self.buffer, step);
         }
 
-        for (token_id, &count) in &self.token_counts {
-            let idx = *token_id as usize;
-            if idx < logits.len() {
-                // Apply penalty: divide logit by penalty for each occurrence
-                let total_penalty = penalty.powi(count as i32) as f32;
-                if logits[idx] > 0.0 {
-                    logits[idx] /= total_penalty;
-                } else {
-                    logits[idx] *= total_penalty;
-                }
+        // Greedy choice to fall back on if the distribution degenerates
+        let fallback = argmax_finite(&self.buffer);
+
+        // Step 2: Apply temperature scaling
+        let temperature = config.temperature_at(step);
+        if temperature > 0.0 {
+            for logit in &mut self.buffer {
+                *logit /= temperature as f32;
+            }
+        }
+
+        // Step 3: Convert logits to probabilities (softmax)
+        softmax_in_place(&mut self.buffer);
+
+        // Extreme temperatures or NaN logits leave NaN or zero mass, which
+        // would otherwise sample token 0 every time
+        let total: f32 = self.buffer.iter().sum();
+        if !total.is_finite() || total < 1e-6 {
+            log::warn!("Degenerate token distribution (sum {}), falling back to greedy", total);
+            self.buffer.fill(0.0);
+            if let Some(token) = fallback {
+                self.buffer[token] = 1.0;
             }
         }
+
+        let vocab_size = self.buffer.len();
+        let mut sorted = false;
+
+        // Step 4: Apply top-k filtering
+        if config.top_k > 0 && config.top_k < vocab_size {
+            sort_descending(&self.buffer, &mut self.order);
+            sorted = true;
+            keep_top(&mut self.buffer, &self.order, config.top_k);
+        }
+
+        // Step 5: Apply top-p (nucleus) filtering
+        if config.top_p < 1.0 {
+            // Renormalizing after top-k preserves the order of kept tokens
+            if !sorted {
+                sort_descending(&self.buffer, &mut self.order);
+            }
+
+            let cutoff_idx = top_p_cutoff(
+                self.order.iter().map(|&idx| self.buffer[idx]),
+                config.top_p as f32,
+                config.top_p_epsilon,
+            );
+
+            keep_top(&mut self.buffer, &self.order, cutoff_idx);
+        }
+
+        temperature
     }
 
     /// Get the generated tokens so far
     pub fn generated_tokens(&self) -> &[u32] {
         &self.generated_tokens
     }
+
+    /// How many times `token_id` is in the history
+    pub fn token_count(&self, token_id: u32) -> usize {
+        self.generated_tokens.iter().filter(|&&t| t == token_id).count()
+    }
+
+    /// Remove the last generated token from the history (for "undo")
+    ///
+    /// The processors forget it too, so penalties match the shorter output.
+    pub fn pop_token(&mut self) -> Option<u32> {
+        let token_id = self.generated_tokens.pop()?;
+        for processor in self.history_processors() {
+            processor.unobserve(token_id);
+        }
+        Some(token_id)
+    }
+
+    /// Remove every occurrence of `token_id` from the history
+    ///
+    /// Returns the number of occurrences removed.
+    pub fn remove_token_history(&mut self, token_id: u32) -> usize {
+        let before = self.generated_tokens.len();
+        self.generated_tokens.retain(|&t| t != token_id);
+        let removed = before - self.generated_tokens.len();
+        for processor in self.history_processors() {
+            for _ in 0..removed {
+                processor.unobserve(token_id);
+            }
+        }
+        removed
+    }
 }
 
 impl Default for Sampler {
@@ -116,7 +339,7 @@ impl Default for Sampler {
 }
 
 /// Softmax function to convert logits to probabilities
-fn softmax(logits: &[f32]) -> Vec<f32> {
+pub(crate) fn softmax(logits: &[f32]) -> Vec<f32> {
     // Find max for numerical stability
     let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
 
@@ -132,6 +355,70 @@ fn softmax(logits: &[f32]) -> Vec<f32> {
     exp_logits.iter().map(|&x| x / sum).collect()
 }
 
+/// In-place softmax (same arithmetic as `softmax`)
+fn softmax_in_place(values: &mut [f32]) {
+    let max_logit = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+    for x in values.iter_mut() {
+        *x = (*x - max_logit).exp();
+    }
+
+    let sum: f32 = values.iter().sum();
+
+    for x in values.iter_mut() {
+        *x /= sum;
+    }
+}
+
+/// Fill `order` with token indices sorted by probability descending
+///
+/// Ties keep ascending index order (matching a stable sort) without the
+/// allocation a stable sort would need.
+fn sort_descending(probs: &[f32], order: &mut Vec<usize>) {
+    order.clear();
+    order.extend(0..probs.len());
+    order.sort_unstable_by(|&a, &b| probs[b].partial_cmp(&probs[a]).unwrap().then(a.cmp(&b)));
+}
+
+/// Keep the first `keep` tokens of `order`, zero the rest and renormalize
+fn keep_top(probs: &mut [f32], order: &[usize], keep: usize) {
+    let keep = keep.min(order.len());
+
+    let mut sum = 0.0;
+    for &idx in &order[..keep] {
+        sum += probs[idx];
+    }
+    for &idx in &order[keep..] {
+        probs[idx] = 0.0;
+    }
+
+    if sum > 0.0 {
+        for p in probs.iter_mut() {
+            *p /= sum;
+        }
+    } else if let Some(&top) = order.first() {
+        // Never leave an all-zero distribution
+        probs[top] = 1.0;
+    }
+}
+
+/// Number of tokens, in descending probability order, needed to reach `p`
+///
+/// `epsilon` absorbs rounding in the running sum, so a token whose
+/// probability equals `p` is kept on its own. At least one token is kept.
+fn top_p_cutoff(sorted_probs: impl Iterator<Item = f32>, p: f32, epsilon: f32) -> usize {
+    let mut cumulative = 0.0;
+    let mut count = 0;
+    for prob in sorted_probs {
+        cumulative += prob;
+        count += 1;
+        if cumulative >= p - epsilon {
+            break;
+        }
+    }
+    count.max(1)
+}
+
 /// Top-k filtering: keep only top k tokens
 fn top_k_filtering(probs: &[f32], k: usize) -> Vec<f32> {
     // Create (index, prob) pairs and sort by probability descending
@@ -162,7 +449,7 @@ fn top_k_filtering(probs: &[f32], k: usize) -> Vec<f32> {
 }
 
 /// Top-p (nucleus) filtering: keep tokens with cumulative probability >= p
-fn top_p_filtering(probs: &[f32], p: f64) -> Vec<f32> {
+fn top_p_filtering(probs: &[f32], p: f64, epsilon: f32) -> Vec<f32> {
     // Create (index, prob) pairs and sort by probability descending
     let mut indexed_probs: Vec<(usize, f32)> = probs
         .iter()
@@ -173,16 +460,7 @@ fn top_p_filtering(probs: &[f32], p: f64) -> Vec<f32> {
     indexed_probs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
 
     // Find cutoff index where cumulative probability >= p
-    let mut cumulative = 0.0;
-    let mut cutoff_idx = indexed_probs.len();
-
-    for (i, &(_, prob)) in indexed_probs.iter().enumerate() {
-        cumulative += prob;
-        if cumulative >= p as f32 {
-            cutoff_idx = i + 1;
-            break;
-        }
-    }
+    let cutoff_idx = top_p_cutoff(indexed_probs.iter().map(|&(_, prob)| prob), p as f32, epsilon);
 
     // Zero out probabilities beyond cutoff
     let mut filtered = vec![0.0; probs.len()];
@@ -212,54 +490,74 @@ fn argmax(probs: &[f32]) -> u32 {
         .unwrap_or(0)
 }
 
-/// Multinomial sampling from a probability distribution
-fn multinomial_sample(probs: &[f32]) -> Result<u32> {
-    // Simple implementation using cumulative distribution
-    // In a real implementation, you'd use a proper RNG
-    // For WASM, we can use js_sys::Math::random()
+/// Index of the largest non-NaN value
+fn argmax_finite(values: &[f32]) -> Option<usize> {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| !v.is_nan())
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(idx, _)| idx)
+}
 
-    #[cfg(target_arch = "wasm32")]
-    {
-        let random_value = js_sys::Math::random() as f32;
-        let mut cumulative = 0.0;
+/// Multinomial sampling from a probability distribution
+///
+/// `random_value` is a uniform draw in [0, 1).
+fn multinomial_sample(probs: &[f32], random_value: f32) -> u32 {
+    let mut cumulative = 0.0;
 
-        for (idx, &prob) in probs.iter().enumerate() {
-            cumulative += prob;
-            if random_value <= cumulative {
-                return Ok(idx as u32);
-            }
+    for (idx, &prob) in probs.iter().enumerate() {
+        cumulative += prob;
+        if random_value <= cumulative {
+            return idx as u32;
         }
+    }
 
-        // Fallback: return last non-zero token
-        for (idx, &prob) in probs.iter().enumerate().rev() {
-            if prob > 0.0 {
-                return Ok(idx as u32);
-            }
-        }
+    // Rounding can leave the total just below random_value: return the
+    // last non-zero token
+    probs
+        .iter()
+        .rposition(|&prob| prob > 0.0)
+        .map_or_else(|| argmax(probs), |idx| idx as u32)
+}
+
+/// Uniform random value in [0, 1) from the platform RNG
+fn platform_random() -> f32 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Math::random() as f32
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     {
         // For non-WASM (testing), use simple random
         use rand::Rng;
-        let random_value: f32 = rand::thread_rng().gen();
-        let mut cumulative = 0.0;
-
-        for (idx, &prob) in probs.iter().enumerate() {
-            cumulative += prob;
-            if random_value <= cumulative {
-                return Ok(idx as u32);
-            }
-        }
+        rand::thread_rng().gen()
     }
+}
+
+/// Advance a SplitMix64 state and return the next output
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
 
-    // Final fallback
-    Ok(argmax(probs))
+/// Seed for the `index`-th of several independent samples from `base`
+///
+/// Distinct indices give well-separated seeds, so candidates generated
+/// from one base seed do not share random streams.
+pub fn derive_seed(base: u64, index: usize) -> u64 {
+    let mut state = base ^ (index as u64).wrapping_mul(0xD1B5_4A32_D192_ED03);
+    splitmix64(&mut state)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::logit_processor::RepetitionPenalty;
 
     #[test]
     fn test_softmax() {
@@ -307,4 +605,260 @@ mod tests {
         // Should track generated token
         assert_eq!(sampler.generated_tokens().len(), 1);
     }
+
+    /// The original allocating pipeline, kept as a reference
+    fn reference_probs(sampler: &Sampler, logits: &[f32], config: &GenerationConfig) -> Vec<f32> {
+        let mut adjusted_logits = logits.to_vec();
+        let mut penalty = RepetitionPenalty::new(config.repetition_penalty);
+        for &token_id in sampler.generated_tokens() {
+            penalty.observe(token_id);
+        }
+        penalty.process(&mut adjusted_logits, 0);
+        if config.temperature > 0.0 {
+            for logit in &mut adjusted_logits {
+                *logit /= config.temperature as f32;
+            }
+        }
+        let probs = softmax(&adjusted_logits);
+        let probs = if config.top_k > 0 && config.top_k < probs.len() {
+            top_k_filtering(&probs, config.top_k)
+        } else {
+            probs
+        };
+        if config.top_p < 1.0 {
+            top_p_filtering(&probs, config.top_p, config.top_p_epsilon)
+        } else {
+            probs
+        }
+    }
+
+    #[test]
+    fn test_in_place_matches_reference() {
+        let logits: Vec<f32> = (0..1000).map(|i| ((i * 7919) % 613) as f32 / 50.0).collect();
+        let configs = [
+            GenerationConfig::default(),
+            GenerationConfig { top_k: 0, ..GenerationConfig::default() },
+            GenerationConfig { top_p: 1.0, top_k: 5, ..GenerationConfig::default() },
+            GenerationConfig { temperature: 0.0, ..GenerationConfig::default() },
+        ];
+
+        let mut sampler = Sampler::new();
+        for config in &configs {
+            // Build up some repetition history
+            sampler.sample(&logits, config).unwrap();
+
+            let expected = reference_probs(&sampler, &logits, config);
+            sampler.buffer.clear();
+            sampler.buffer.extend_from_slice(&logits);
+            sampler.compute_probs(config);
+
+            assert_eq!(sampler.buffer, expected);
+        }
+    }
+
+    #[test]
+    fn test_no_allocation_after_warmup() {
+        let logits: Vec<f32> = (0..5000).map(|i| (i % 97) as f32 / 10.0).collect();
+        let config = GenerationConfig::default();
+        let mut sampler = Sampler::new();
+
+        sampler.sample(&logits, &config).unwrap();
+        let buffer_ptr = sampler.buffer.as_ptr();
+        let order_ptr = sampler.order.as_ptr();
+
+        for _ in 0..10 {
+            sampler.sample(&logits, &config).unwrap();
+        }
+
+        assert_eq!(sampler.buffer.as_ptr(), buffer_ptr);
+        assert_eq!(sampler.order.as_ptr(), order_ptr);
+    }
+
+    #[test]
+    fn test_sample_with_allowed() {
+        let mut sampler = Sampler::new();
+        // Token 0 dominates, but only tokens 2 and 3 are allowed
+        let logits = vec![10.0, 1.0, 2.0, 3.0];
+        let config = GenerationConfig {
+            temperature: 0.0,
+            ..GenerationConfig::default()
+        };
+
+        let token = sampler.sample_with_allowed(&logits, &config, &[2, 3]).unwrap();
+        assert_eq!(token, 3);
+    }
+
+    #[test]
+    fn test_sample_batch_and_accept() {
+        let config = GenerationConfig {
+            temperature: 0.0,
+            repetition_penalty: 10.0,
+            ..GenerationConfig::default()
+        };
+        let row = [1.0, 3.0, 2.0];
+        let mut sampler = Sampler::new();
+
+        // Both drafts see the same (empty) history, so both pick token 1
+        let drafts = sampler.sample_batch(&[&row, &row], &config).unwrap();
+        assert_eq!(drafts, vec![1, 1]);
+        assert!(sampler.generated_tokens().is_empty());
+
+        // Rejecting the drafts leaves sampling unchanged
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![1]);
+
+        sampler.accept(&drafts[..1]);
+        assert_eq!(sampler.generated_tokens(), &[1]);
+
+        // The accepted token is now penalized
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![2]);
+
+        sampler.accept(&[2, 1]);
+        assert_eq!(sampler.generated_tokens(), &[1, 2, 1]);
+        // Tokens 1 (3.0 / 100) and 2 (2.0 / 10) now rank below token 0
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn test_top_p_equal_to_max_probability() {
+        // Softmax gives roughly [0.5, 0.25, 0.25], with rounding either side
+        let logits = vec![0.0, -std::f32::consts::LN_2, -std::f32::consts::LN_2];
+        let config = GenerationConfig {
+            temperature: 1.0,
+            top_k: 0,
+            top_p: 0.5,
+            repetition_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+
+        let mut sampler = Sampler::new();
+        sampler.buffer.extend_from_slice(&logits);
+        sampler.compute_probs(&config);
+        assert_eq!(sampler.buffer, vec![1.0, 0.0, 0.0]);
+
+        for _ in 0..20 {
+            assert_eq!(sampler.sample(&logits, &config).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_top_p_keeps_at_least_one_token() {
+        assert_eq!(top_p_cutoff([0.0, 0.0].into_iter(), 0.9, 1e-6), 2);
+        assert_eq!(top_p_cutoff(std::iter::empty(), 0.9, 1e-6), 1);
+
+        let mut probs = vec![0.0, 0.0, 0.0];
+        keep_top(&mut probs, &[1, 0, 2], 1);
+        assert_eq!(probs, vec![0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_seeded_sampling_is_reproducible() {
+        let logits: Vec<f32> = (0..50).map(|i| (i % 5) as f32).collect();
+        let config = GenerationConfig {
+            top_k: 0,
+            top_p: 1.0,
+            repetition_penalty: 1.0,
+            temperature: 1.0,
+            ..GenerationConfig::default()
+        };
+
+        let draw = |seed: u64| {
+            let mut sampler = Sampler::with_seed(seed);
+            (0..20)
+                .map(|_| sampler.sample(&logits, &config).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(derive_seed(7, 0)), draw(derive_seed(7, 1)));
+    }
+
+    #[test]
+    fn test_temperature_schedule_anneals_to_greedy() {
+        let config = GenerationConfig {
+            temperature_schedule: Some(vec![(9, 0.0), (0, 1.0)]),
+            top_k: 0,
+            top_p: 1.0,
+            repetition_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        assert_eq!(config.temperature_at(0), 1.0);
+        assert!((config.temperature_at(3) - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(config.temperature_at(9), 0.0);
+        assert_eq!(config.temperature_at(50), 0.0);
+
+        // Nearly flat logits: only greedy decoding always picks token 3
+        let logits = vec![1.0, 1.0, 1.0, 1.1];
+        let mut sampler = Sampler::with_seed(1);
+        for _ in 0..9 {
+            sampler.sample(&logits, &config).unwrap();
+        }
+        for _ in 0..5 {
+            assert_eq!(sampler.sample(&logits, &config).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn test_degenerate_distribution_falls_back_to_greedy() {
+        // A vanishing temperature overflows the scaled logits to infinity,
+        // so softmax yields NaN everywhere
+        let config = GenerationConfig {
+            temperature: 1e-40,
+            ..GenerationConfig::default()
+        };
+        let logits = vec![0.5, 1.0, 3.0, 2.0];
+
+        let mut sampler = Sampler::new();
+        for _ in 0..5 {
+            assert_eq!(sampler.sample(&logits, &config).unwrap(), 2);
+            sampler.reset();
+        }
+
+        let nan_logits = vec![f32::NAN, 0.1, f32::NAN, 0.7, 0.2];
+        assert_eq!(sampler.sample(&nan_logits, &GenerationConfig::default()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_derive_seed_is_distinct_and_stable() {
+        let seeds: std::collections::HashSet<u64> =
+            (0..1000).map(|i| derive_seed(42, i)).collect();
+        assert_eq!(seeds.len(), 1000);
+
+        // Pinned so seeded runs stay reproducible across releases
+        assert_eq!(derive_seed(42, 3), 0x4e2c_2220_d2ae_df95);
+        assert_ne!(derive_seed(42, 3), derive_seed(43, 3));
+        // Neighbouring indices differ in roughly half their bits
+        let differing = (derive_seed(42, 0) ^ derive_seed(42, 1)).count_ones();
+        assert!((16..=48).contains(&differing), "{} bits differ", differing);
+    }
+
+    #[test]
+    fn test_pop_token_and_remove_history() {
+        let config = GenerationConfig {
+            temperature: 0.0,
+            repetition_penalty: 10.0,
+            ..GenerationConfig::default()
+        };
+        let row = [1.0, 3.0, 2.0];
+        let mut sampler = Sampler::from_config(&config);
+
+        sampler.accept(&[1, 2, 1]);
+        assert_eq!(sampler.token_count(1), 2);
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![0]);
+
+        // Undoing the last token lifts one of token 1's penalties (3.0 / 10)
+        assert_eq!(sampler.pop_token(), Some(1));
+        assert_eq!(sampler.generated_tokens(), &[1, 2]);
+        assert_eq!(sampler.token_count(1), 1);
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![0]);
+
+        assert_eq!(sampler.remove_token_history(2), 1);
+        assert_eq!(sampler.generated_tokens(), &[1]);
+        assert_eq!(sampler.token_count(2), 0);
+        // Token 2 (2.0) is unpenalized again and beats token 1 (0.3)
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![2]);
+
+        assert_eq!(sampler.remove_token_history(1), 1);
+        assert_eq!(sampler.pop_token(), None);
+        assert_eq!(sampler.sample_batch(&[&row], &config).unwrap(), vec![1]);
+    }
 }
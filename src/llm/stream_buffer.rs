@@ -0,0 +1,110 @@
+// Buffering of streamed deltas to word or sentence boundaries
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::text::split_sentences;
+
+/// How often `generate_stream` invokes its callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StreamGranularity {
+    /// Every decoded delta
+    #[default]
+    #[serde(rename = "token")]
+    Token,
+    /// Complete words, each with its trailing whitespace
+    #[serde(rename = "word")]
+    Word,
+    /// Complete sentences, each with its trailing whitespace
+    #[serde(rename = "sentence")]
+    Sentence,
+}
+
+/// Holds streamed text back until a `StreamGranularity` boundary
+///
+/// Concatenating everything released by `push` and `finish` gives back
+/// exactly the pushed text.
+pub(crate) struct StreamBuffer {
+    granularity: StreamGranularity,
+    pending: String,
+}
+
+impl StreamBuffer {
+    pub(crate) fn new(granularity: StreamGranularity) -> Self {
+        Self {
+            granularity,
+            pending: String::new(),
+        }
+    }
+
+    /// Add a delta; returns the text completed up to the last boundary
+    pub(crate) fn push(&mut self, delta: &str) -> Option<String> {
+        if self.granularity == StreamGranularity::Token {
+            return (!delta.is_empty()).then(|| delta.to_string());
+        }
+
+        self.pending.push_str(delta);
+        let end = match self.granularity {
+            StreamGranularity::Token => self.pending.len(),
+            StreamGranularity::Word => self
+                .pending
+                .char_indices()
+                .rfind(|(_, c)| c.is_whitespace())
+                .map_or(0, |(i, c)| i + c.len_utf8()),
+            StreamGranularity::Sentence => sentence_release(&self.pending),
+        };
+        self.release(end)
+    }
+
+    /// Release whatever is still buffered
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        self.release(self.pending.len())
+    }
+
+    fn release(&mut self, end: usize) -> Option<String> {
+        if end == 0 {
+            return None;
+        }
+        let rest = self.pending.split_off(end);
+        Some(std::mem::replace(&mut self.pending, rest))
+    }
+}
+
+/// Length of the prefix of `text` made of finished sentences
+///
+/// A sentence only counts as finished once whatever follows it is known, so
+/// a placeholder word is appended: "Hi. " releases "Hi. ", while "Hi." may
+/// still become "Hi.5" and is held.
+fn sentence_release(text: &str) -> usize {
+    let probe = format!("{}x", text);
+    match split_sentences(&probe).last() {
+        Some(&(start, _, _)) => start.min(text.len()),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(granularity: StreamGranularity, deltas: &[&str]) -> Vec<String> {
+        let mut buffer = StreamBuffer::new(granularity);
+        let mut out: Vec<String> = deltas.iter().filter_map(|d| buffer.push(d)).collect();
+        out.extend(buffer.finish());
+        out
+    }
+
+    #[test]
+    fn test_stream_granularity() {
+        let deltas = ["Hel", "lo th", "ere. It", " costs 3.", "50 now.", " Bye"];
+
+        assert_eq!(stream(StreamGranularity::Token, &deltas), deltas);
+        assert_eq!(
+            stream(StreamGranularity::Word, &deltas),
+            vec!["Hello ", "there. ", "It costs ", "3.50 ", "now. ", "Bye"]
+        );
+        assert_eq!(
+            stream(StreamGranularity::Sentence, &deltas),
+            vec!["Hello there. ", "It costs 3.50 now. ", "Bye"]
+        );
+    }
+}
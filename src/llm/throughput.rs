@@ -0,0 +1,100 @@
+use anyhow::Result;
+
+use crate::utils::now_ms;
+
+/// Callback receiving `(tokens_generated, elapsed_ms, tokens_per_sec)`
+pub type MetricsCallback<'a> = dyn FnMut(usize, f64, f64) -> Result<()> + 'a;
+
+/// Reports live generation throughput every `every` tokens
+pub(crate) struct ThroughputMeter<'a> {
+    every: usize,
+    clock: Box<dyn Fn() -> f64 + 'a>,
+    start_ms: f64,
+    on_metrics: &'a mut MetricsCallback<'a>,
+}
+
+impl<'a> ThroughputMeter<'a> {
+    /// Meter timed with `Date.now()`; `every = 0` disables reporting
+    pub(crate) fn new(every: usize, on_metrics: &'a mut MetricsCallback<'a>) -> Self {
+        Self::with_clock(every, now_ms, on_metrics)
+    }
+
+    /// Meter using a custom millisecond clock
+    pub(crate) fn with_clock(
+        every: usize,
+        clock: impl Fn() -> f64 + 'a,
+        on_metrics: &'a mut MetricsCallback<'a>,
+    ) -> Self {
+        let start_ms = clock();
+        Self {
+            every,
+            clock: Box::new(clock),
+            start_ms,
+            on_metrics,
+        }
+    }
+
+    /// Record that `tokens_generated` tokens have been produced so far
+    pub(crate) fn record(&mut self, tokens_generated: usize) -> Result<()> {
+        if self.every == 0 || tokens_generated == 0 {
+            return Ok(());
+        }
+        if !tokens_generated.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let elapsed_ms = (self.clock)() - self.start_ms;
+        let tokens_per_sec = if elapsed_ms > 0.0 {
+            tokens_generated as f64 * 1000.0 / elapsed_ms
+        } else {
+            0.0
+        };
+
+        (self.on_metrics)(tokens_generated, elapsed_ms, tokens_per_sec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_cadence_and_rate() {
+        let clock = Cell::new(1000.0);
+        let mut reports = Vec::new();
+        let mut on_metrics = |tokens: usize, elapsed: f64, rate: f64| {
+            reports.push((tokens, elapsed, rate));
+            Ok(())
+        };
+
+        {
+            let mut meter = ThroughputMeter::with_clock(4, || clock.get(), &mut on_metrics);
+            for token in 1..=10 {
+                // 50ms per token
+                clock.set(clock.get() + 50.0);
+                meter.record(token).unwrap();
+            }
+        }
+
+        assert_eq!(reports, vec![(4, 200.0, 20.0), (8, 400.0, 20.0)]);
+    }
+
+    #[test]
+    fn test_disabled_meter_never_reports() {
+        let mut calls = 0;
+        let mut on_metrics = |_: usize, _: f64, _: f64| {
+            calls += 1;
+            Ok(())
+        };
+
+        {
+            let mut meter = ThroughputMeter::with_clock(0, || 0.0, &mut on_metrics);
+            for token in 1..=10 {
+                meter.record(token).unwrap();
+            }
+        }
+
+        assert_eq!(calls, 0);
+    }
+}
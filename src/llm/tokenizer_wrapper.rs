@@ -1,12 +1,17 @@
 use anyhow::{Result, Context};
-use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::JsFuture;
-use web_sys::{Request, RequestInit, RequestMode, Response};
+
+use crate::error::LlmError;
+use crate::utils::text::CharOffsets;
+use crate::utils::fetch::{fetch_bytes, FetchOptions, RetryPolicy};
+
+/// Byte offsets `(start, end)` of each token in the encoded text
+pub type TokenOffsets = Vec<(usize, usize)>;
 
 /// Wrapper around the tokenizers crate for WASM compatibility
 pub struct TokenizerWrapper {
     tokenizer: Option<tokenizers::Tokenizer>,
     tokenizer_url: String,
+    fetch_options: FetchOptions,
 }
 
 impl TokenizerWrapper {
@@ -15,9 +20,34 @@ impl TokenizerWrapper {
         Self {
             tokenizer: None,
             tokenizer_url,
+            fetch_options: FetchOptions::default(),
         }
     }
 
+    /// Set the retry policy used when fetching tokenizer.json
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.fetch_options.retry = retry_policy;
+        self
+    }
+
+    /// Set the retry policy and headers used when fetching tokenizer.json
+    pub fn with_fetch_options(mut self, fetch_options: FetchOptions) -> Self {
+        self.fetch_options = fetch_options;
+        self
+    }
+
+    /// Create a tokenizer directly from tokenizer.json bytes
+    pub fn from_bytes(tokenizer_json: &[u8]) -> Result<Self> {
+        let tokenizer = tokenizers::Tokenizer::from_bytes(tokenizer_json)
+            .map_err(|e| LlmError::Parse(format!("Failed to parse tokenizer: {:?}", e)))?;
+
+        Ok(Self {
+            tokenizer: Some(tokenizer),
+            tokenizer_url: String::new(),
+            fetch_options: FetchOptions::default(),
+        })
+    }
+
     /// Load the tokenizer from a URL
     pub async fn load(&mut self) -> Result<()> {
         log::info!("Loading tokenizer from: {}", self.tokenizer_url);
@@ -30,13 +60,13 @@ impl TokenizerWrapper {
 
         // Step 2: Parse JSON and create Tokenizer
         let tokenizer = tokenizers::Tokenizer::from_bytes(&tokenizer_json)
-            .map_err(|e| anyhow::anyhow!("Failed to parse tokenizer: {:?}", e))?;
+            .map_err(|e| LlmError::Parse(format!("Failed to parse tokenizer: {:?}", e)))?;
 
         log::info!("Tokenizer parsed successfully (vocab size: {})", tokenizer.get_vocab_size(true));
 
         // Step 3: Verify tokenizer works with a simple test
         let test_encoding = tokenizer.encode("Hello", false)
-            .map_err(|e| anyhow::anyhow!("Tokenizer verification failed: {:?}", e))?;
+            .map_err(|e| LlmError::Tokenize(format!("Tokenizer verification failed: {:?}", e)))?;
 
         log::debug!("Tokenizer verification passed (test encoding: {} tokens)", test_encoding.len());
 
@@ -48,47 +78,20 @@ impl TokenizerWrapper {
 
     /// Fetch tokenizer.json from URL
     async fn fetch_tokenizer_json(&self, url: &str) -> Result<Vec<u8>> {
-        let window = web_sys::window()
-            .context("No window object available")?;
-
-        let mut opts = RequestInit::new();
-        opts.method("GET");
-        opts.mode(RequestMode::Cors);
-
-        let request = Request::new_with_str_and_init(url, &opts)
-            .map_err(|e| anyhow::anyhow!("Failed to create request: {:?}", e))?;
-
-        let resp_value = JsFuture::from(window.fetch_with_request(&request))
-            .await
-            .map_err(|e| anyhow::anyhow!("Fetch failed: {:?}", e))?;
-
-        let resp: Response = resp_value.dyn_into()
-            .map_err(|e| anyhow::anyhow!("Response conversion failed: {:?}", e))?;
-
-        if !resp.ok() {
-            anyhow::bail!("HTTP error: {}", resp.status());
-        }
-
-        let array_buffer = JsFuture::from(resp.array_buffer()
-            .map_err(|e| anyhow::anyhow!("array_buffer() failed: {:?}", e))?)
+        fetch_bytes(url, &self.fetch_options)
             .await
-            .map_err(|e| anyhow::anyhow!("array_buffer await failed: {:?}", e))?;
-
-        let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-        let bytes = uint8_array.to_vec();
-
-        Ok(bytes)
+            .map_err(|e| LlmError::from(e).into())
     }
 
     /// Encode text to token IDs
     pub fn encode(&self, text: &str) -> Result<Vec<u32>> {
         let tokenizer = self.tokenizer.as_ref()
-            .context("Tokenizer not loaded. Call load() first.")?;
+            .ok_or(LlmError::NotLoaded)?;
 
         log::debug!("Encoding text: {} chars", text.len());
 
         let encoding = tokenizer.encode(text, false)
-            .map_err(|e| anyhow::anyhow!("Encoding failed: {:?}", e))?;
+            .map_err(|e| LlmError::Tokenize(format!("Encoding failed: {:?}", e)))?;
 
         let ids = encoding.get_ids().to_vec();
 
@@ -100,25 +103,62 @@ impl TokenizerWrapper {
     /// Decode token IDs to text
     pub fn decode(&self, token_ids: &[u32]) -> Result<String> {
         let tokenizer = self.tokenizer.as_ref()
-            .context("Tokenizer not loaded. Call load() first.")?;
+            .ok_or(LlmError::NotLoaded)?;
 
         log::debug!("Decoding {} tokens", token_ids.len());
 
         let text = tokenizer.decode(token_ids, true)
-            .map_err(|e| anyhow::anyhow!("Decoding failed: {:?}", e))?;
+            .map_err(|e| LlmError::Tokenize(format!("Decoding failed: {:?}", e)))?;
 
         log::debug!("Decoded to {} chars", text.len());
 
         Ok(text)
     }
 
+    /// Byte offsets of each token in `text`
+    pub fn token_offsets(&self, text: &str) -> Result<Vec<(usize, usize)>> {
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+
+        let encoding = tokenizer.encode(text, false)
+            .map_err(|e| LlmError::Tokenize(format!("Encoding failed: {:?}", e)))?;
+
+        Ok(encoding.get_offsets().to_vec())
+    }
+
+    /// Encode text and return each token ID with its character span
+    ///
+    /// Spans are `(start, end)` character (not byte) indices into `text`,
+    /// e.g. for highlighting the source of a citation.
+    pub fn encode_with_offsets(&self, text: &str) -> Result<Vec<(u32, (usize, usize))>> {
+        let (ids, offsets) = self.encode_with_byte_offsets(text)?;
+        let chars = CharOffsets::new(text);
+
+        Ok(ids
+            .into_iter()
+            .zip(offsets)
+            .map(|(id, (start, end))| (id, (chars.to_char(start), chars.to_char(end))))
+            .collect())
+    }
+
+    /// Encode text and return token IDs with their byte offsets
+    pub fn encode_with_byte_offsets(&self, text: &str) -> Result<(Vec<u32>, TokenOffsets)> {
+        let tokenizer = self.tokenizer.as_ref()
+            .ok_or(LlmError::NotLoaded)?;
+
+        let encoding = tokenizer.encode(text, false)
+            .map_err(|e| LlmError::Tokenize(format!("Encoding failed: {:?}", e)))?;
+
+        Ok((encoding.get_ids().to_vec(), encoding.get_offsets().to_vec()))
+    }
+
     /// Encode text and return both tokens and IDs
     pub fn encode_with_ids(&self, text: &str) -> Result<(Vec<String>, Vec<u32>)> {
         let tokenizer = self.tokenizer.as_ref()
-            .context("Tokenizer not loaded. Call load() first.")?;
+            .ok_or(LlmError::NotLoaded)?;
 
         let encoding = tokenizer.encode(text, false)
-            .map_err(|e| anyhow::anyhow!("Encoding failed: {:?}", e))?;
+            .map_err(|e| LlmError::Tokenize(format!("Encoding failed: {:?}", e)))?;
 
         let ids = encoding.get_ids().to_vec();
         let tokens: Vec<String> = encoding.get_tokens()
@@ -129,6 +169,57 @@ impl TokenizerWrapper {
         Ok((tokens, ids))
     }
 
+    /// Get the display text of a single token
+    ///
+    /// SentencePiece (`▁`) and byte-level BPE (`Ġ`) space markers are
+    /// converted to plain spaces.
+    pub fn token_text(&self, token_id: u32) -> Option<String> {
+        self.tokenizer
+            .as_ref()?
+            .id_to_token(token_id)
+            .map(|token| normalize_token(&token))
+    }
+
+    /// Get all token IDs whose text starts with the given prefix
+    pub fn tokens_with_prefix(&self, prefix: &str) -> Vec<u32> {
+        let Some(tokenizer) = self.tokenizer.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut ids: Vec<u32> = tokenizer
+            .get_vocab(true)
+            .into_iter()
+            .filter(|(token, _)| normalize_token(token).starts_with(prefix))
+            .map(|(_, id)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Get all token IDs whose text contains `needle`
+    pub fn tokens_containing(&self, needle: &str) -> Vec<u32> {
+        let Some(tokenizer) = self.tokenizer.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut ids: Vec<u32> = tokenizer
+            .get_vocab(true)
+            .into_iter()
+            .filter(|(token, _)| normalize_token(token).contains(needle))
+            .map(|(_, id)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Get the end-of-sequence token ID, if the vocabulary has one
+    pub fn eos_token_id(&self) -> Option<u32> {
+        let tokenizer = self.tokenizer.as_ref()?;
+        ["<|endoftext|>", "<|end|>", "</s>", "<|im_end|>"]
+            .iter()
+            .find_map(|token| tokenizer.token_to_id(token))
+    }
+
     /// Get vocabulary size
     pub fn vocab_size(&self) -> usize {
         self.tokenizer
@@ -147,3 +238,113 @@ impl TokenizerWrapper {
         self.tokenizer.as_ref()
     }
 }
+
+/// Convert a raw vocabulary token to the text it represents
+fn normalize_token(token: &str) -> String {
+    token.replace(['\u{2581}', '\u{0120}'], " ")
+}
+
+/// Build a whitespace word-level tokenizer for tests
+#[cfg(test)]
+pub(crate) fn word_level_tokenizer(words: &[&str]) -> TokenizerWrapper {
+    let vocab: serde_json::Map<String, serde_json::Value> = ["<unk>", "</s>"]
+        .iter()
+        .chain(words.iter())
+        .enumerate()
+        .map(|(id, word)| (word.to_string(), serde_json::Value::from(id)))
+        .collect();
+
+    let json = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "Whitespace" },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" }
+    });
+
+    TokenizerWrapper::from_bytes(json.to_string().as_bytes()).unwrap()
+}
+
+/// Build a SentencePiece-style BPE tokenizer (Metaspace `▁`) for tests
+///
+/// The vocabulary holds `<unk>`, `</s>`, `▁`, the lowercase letters and
+/// the results of `merges`, applied in order.
+#[cfg(test)]
+pub(crate) fn metaspace_bpe_tokenizer(merges: &[(&str, &str)]) -> TokenizerWrapper {
+    let mut tokens: Vec<String> = ["<unk>", "</s>", "\u{2581}"]
+        .iter()
+        .map(|t| t.to_string())
+        .chain(('a'..='z').map(String::from))
+        .collect();
+    tokens.extend(merges.iter().map(|(a, b)| format!("{}{}", a, b)));
+    let vocab: serde_json::Map<String, serde_json::Value> = tokens
+        .into_iter()
+        .enumerate()
+        .map(|(id, token)| (token, serde_json::Value::from(id)))
+        .collect();
+    let merges: Vec<String> = merges.iter().map(|(a, b)| format!("{} {}", a, b)).collect();
+
+    let metaspace = serde_json::json!({
+        "type": "Metaspace",
+        "replacement": "\u{2581}",
+        "prepend_scheme": "first",
+        "split": true
+    });
+    let json = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": metaspace,
+        "post_processor": null,
+        "decoder": metaspace,
+        "model": {
+            "type": "BPE",
+            "vocab": vocab,
+            "merges": merges,
+            "unk_token": "<unk>"
+        }
+    });
+
+    TokenizerWrapper::from_bytes(json.to_string().as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_with_prefix() {
+        let tokenizer = word_level_tokenizer(&["hel", "hello", "help", "world"]);
+
+        let ids = tokenizer.tokens_with_prefix("hel");
+        let texts: Vec<String> = ids
+            .iter()
+            .map(|&id| tokenizer.token_text(id).unwrap())
+            .collect();
+
+        assert_eq!(texts, vec!["hel", "hello", "help"]);
+        assert_eq!(tokenizer.eos_token_id(), Some(1));
+    }
+
+    #[test]
+    fn test_encode_with_offsets_are_char_spans() {
+        let tokenizer = word_level_tokenizer(&["Café", "au", "lait"]);
+        let text = "Café au  lait";
+
+        let encoded = tokenizer.encode_with_offsets(text).unwrap();
+        assert_eq!(encoded.len(), 3);
+
+        let chars: Vec<char> = text.chars().collect();
+        for &(id, (start, end)) in &encoded {
+            let source: String = chars[start..end].iter().collect();
+            assert_eq!(source, tokenizer.token_text(id).unwrap());
+        }
+        assert_eq!(encoded[2].1, (9, 13));
+    }
+}
@@ -1,5 +1,11 @@
 use anyhow::Result;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use super::{Chunk, ChunkMetadata, Document};
+use crate::error::LlmError;
+use crate::llm::TokenizerWrapper;
+use crate::utils::{content_hash, current_timestamp};
+use crate::utils::text::{split_sentences, CharOffsets};
 
 /// Chunking strategy
 #[derive(Debug, Clone, Copy)]
@@ -7,6 +13,10 @@ pub enum ChunkingStrategy {
     FixedSize { size: usize, overlap: usize },
     Recursive { size: usize, overlap: usize },
     Semantic { threshold: f32 },
+    /// Windows of `size` tokens (requires `DocumentChunker::with_tokenizer`)
+    TokenBased { size: usize, overlap: usize },
+    /// One chunk per sentence (see `Retriever::retrieve_sentence_window`)
+    Sentence,
 }
 
 impl Default for ChunkingStrategy {
@@ -18,29 +28,219 @@ impl Default for ChunkingStrategy {
     }
 }
 
+/// How chunk IDs are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkIdStrategy {
+    /// `{document_id}_{chunk_index}`
+    #[default]
+    Sequential,
+    /// Hash of the document ID, start offset and content; stable across
+    /// re-chunking for chunks whose text and position are unchanged, and
+    /// distinct for repeated text within or across documents
+    ContentHash,
+    /// Random UUID v4; globally unique
+    Uuid,
+}
+
+impl ChunkIdStrategy {
+    /// Generate the ID for a chunk
+    pub fn chunk_id(
+        &self,
+        document_id: &str,
+        chunk_index: usize,
+        start_char: usize,
+        content: &str,
+    ) -> String {
+        match self {
+            ChunkIdStrategy::Sequential => format!("{}_{}", document_id, chunk_index),
+            ChunkIdStrategy::ContentHash => {
+                let key = format!("{}\u{1f}{}\u{1f}{}", document_id, start_char, content);
+                content_hash(key.as_bytes())
+            }
+            ChunkIdStrategy::Uuid => uuid::Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+/// Character range `start..end` of a chunk, with its token count when known
+type ChunkSpan = (usize, usize, Option<usize>);
+
+/// Span of about `parent_chars` characters centred on `start..end`,
+/// shifted back inside a text of `len` characters rather than shrunk
+pub(crate) fn parent_span(
+    start: usize,
+    end: usize,
+    len: usize,
+    parent_chars: usize,
+) -> (usize, usize) {
+    let target = parent_chars.max(end - start);
+    let window_end = (start.saturating_sub((target - (end - start)) / 2) + target).min(len);
+    (window_end.saturating_sub(target), window_end)
+}
+
+/// ID of the span `start..end` of a document, as stored in `parent_id`
+pub(crate) fn span_id(document_id: &str, start: usize, end: usize) -> String {
+    format!("{}:{}-{}", document_id, start, end)
+}
+
+/// The span encoded in a `span_id` of `document_id`
+pub(crate) fn parse_span_id(id: &str, document_id: &str) -> Option<(usize, usize)> {
+    let (start, end) = id.strip_prefix(document_id)?.strip_prefix(':')?.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+/// Text cleaning step applied before chunking
+pub type TextCleaner = Box<dyn Fn(&str) -> String>;
+
 /// Document chunker
 pub struct DocumentChunker {
     strategy: ChunkingStrategy,
+    cleaner: Option<TextCleaner>,
+    id_strategy: ChunkIdStrategy,
+    tokenizer: Option<TokenizerWrapper>,
+    /// Document metadata keys copied into each chunk's `extra`
+    inherited_metadata: Vec<String>,
+    /// Size of the parent span recorded in each chunk's `parent_id`
+    parent_chars: Option<usize>,
 }
 
 impl DocumentChunker {
     /// Create a new document chunker
     pub fn new(strategy: ChunkingStrategy) -> Self {
-        Self { strategy }
+        Self {
+            strategy,
+            cleaner: None,
+            id_strategy: ChunkIdStrategy::default(),
+            tokenizer: None,
+            inherited_metadata: Vec::new(),
+            parent_chars: None,
+        }
+    }
+
+    /// Record in each chunk's `parent_id` a span of about `parent_chars`
+    /// characters centred on it, for small-to-big retrieval
+    /// (`Retriever::retrieve_with_parents`)
+    pub fn with_parent_chars(mut self, parent_chars: usize) -> Self {
+        self.parent_chars = Some(parent_chars);
+        self
+    }
+
+    /// Copy document metadata into each chunk's `extra` map
+    ///
+    /// Keys may be `file_type`, `uploaded_at` or any key of
+    /// `DocumentMetadata::extra`; keys the document lacks are skipped.
+    pub fn with_inherited_metadata(mut self, keys: &[&str]) -> Self {
+        self.inherited_metadata = keys.iter().map(|key| key.to_string()).collect();
+        self
+    }
+
+    /// Use a tokenizer for token-based chunking and to fill
+    /// `ChunkMetadata::token_count`
+    pub fn with_tokenizer(mut self, tokenizer: TokenizerWrapper) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Set how chunk IDs are generated
+    pub fn with_id_strategy(mut self, id_strategy: ChunkIdStrategy) -> Self {
+        self.id_strategy = id_strategy;
+        self
+    }
+
+    /// Clean document text before splitting (e.g. `strip_repeated_lines`)
+    ///
+    /// Chunk offsets refer to the cleaned text.
+    pub fn with_cleaner(mut self, cleaner: impl Fn(&str) -> String + 'static) -> Self {
+        self.cleaner = Some(Box::new(cleaner));
+        self
     }
 
     /// Chunk a document into smaller pieces
     pub fn chunk(&self, document: &Document) -> Result<Vec<Chunk>> {
+        Ok(self.chunk_iter(document)?.collect())
+    }
+
+    /// Chunk a document lazily, yielding the same chunks as `chunk`
+    ///
+    /// Only chunk boundaries are computed up front (so errors surface here);
+    /// each chunk's content is copied out when it is yielded.
+    pub fn chunk_iter<'a>(
+        &'a self,
+        document: &'a Document,
+    ) -> Result<impl Iterator<Item = Chunk> + 'a> {
+        let document = match &self.cleaner {
+            Some(cleaner) => Cow::Owned(Document {
+                content: cleaner(&document.content),
+                ..document.clone()
+            }),
+            None => Cow::Borrowed(document),
+        };
+        let offsets = CharOffsets::new(&document.content);
+        let spans = self.spans(&document, &offsets)?;
+
+        Ok(spans.into_iter().enumerate().map(move |(index, (start, end, token_count))| {
+            let mut chunk = self.build_chunk(&document, &offsets, index, start, end);
+            chunk.metadata.token_count = token_count;
+            chunk
+        }))
+    }
+
+    /// Shift a chunk produced from a slice of a larger document
+    ///
+    /// Offsets (including the parent span) move by `base_offset`, the index
+    /// by `base_index`, and the ID is regenerated for the new index.
+    pub(crate) fn rebase(&self, chunk: &mut Chunk, base_offset: usize, base_index: usize) {
+        let metadata = &mut chunk.metadata;
+        metadata.start_char += base_offset;
+        metadata.end_char += base_offset;
+        metadata.chunk_index += base_index;
+        let parent = metadata
+            .parent_id
+            .as_deref()
+            .and_then(|id| parse_span_id(id, &metadata.document_id));
+        if let Some((start, end)) = parent {
+            metadata.parent_id =
+                Some(span_id(&metadata.document_id, start + base_offset, end + base_offset));
+        }
+        chunk.id = self.id_strategy.chunk_id(
+            &metadata.document_id,
+            metadata.chunk_index,
+            metadata.start_char,
+            &chunk.content,
+        );
+    }
+
+    /// Chunk spans of already-cleaned document text, with token counts
+    /// filled in when a tokenizer is set
+    fn spans(&self, document: &Document, offsets: &CharOffsets) -> Result<Vec<ChunkSpan>> {
+        let mut spans = self.split(document, offsets)?;
+
+        if let Some(tokenizer) = &self.tokenizer {
+            for (start, end, token_count) in spans.iter_mut().filter(|s| s.2.is_none()) {
+                let content = &document.content[offsets.to_byte(*start)..offsets.to_byte(*end)];
+                *token_count = Some(tokenizer.encode(content)?.len());
+            }
+        }
+
+        Ok(spans)
+    }
+
+    /// Split document text with the configured strategy
+    fn split(&self, document: &Document, offsets: &CharOffsets) -> Result<Vec<ChunkSpan>> {
         match self.strategy {
             ChunkingStrategy::FixedSize { size, overlap } => {
-                self.chunk_fixed_size(document, size, overlap)
+                self.chunk_fixed_size(document, offsets, size, overlap)
             }
             ChunkingStrategy::Recursive { size, overlap } => {
-                self.chunk_recursive(document, size, overlap)
+                self.chunk_recursive(document, offsets, size, overlap)
             }
             ChunkingStrategy::Semantic { threshold } => {
-                self.chunk_semantic(document, threshold)
+                self.chunk_semantic(document, offsets, threshold)
+            }
+            ChunkingStrategy::TokenBased { size, overlap } => {
+                self.chunk_tokens(document, offsets, size, overlap)
             }
+            ChunkingStrategy::Sentence => Ok(self.chunk_sentences(document, offsets)),
         }
     }
 
@@ -48,37 +248,31 @@ impl DocumentChunker {
     fn chunk_fixed_size(
         &self,
         document: &Document,
+        offsets: &CharOffsets,
         size: usize,
         overlap: usize,
-    ) -> Result<Vec<Chunk>> {
-        let content = &document.content;
+    ) -> Result<Vec<ChunkSpan>> {
+        if size == 0 {
+            return Err(LlmError::Config("Chunk size must be positive".to_string()).into());
+        }
+        // Every chunk must advance by at least one character
+        if overlap >= size {
+            log::warn!("Chunk overlap {} >= size {}, using {}", overlap, size, size - 1);
+        }
+        let overlap = overlap.min(size - 1);
+
+        let char_len = offsets.char_len();
         let mut chunks = Vec::new();
-        let mut chunk_index = 0;
 
         let mut start = 0;
-        while start < content.len() {
-            let end = (start + size).min(content.len());
-            let chunk_content = content[start..end].to_string();
-
-            let chunk = Chunk {
-                id: format!("{}_{}", document.id, chunk_index),
-                content: chunk_content,
-                embedding: None,
-                metadata: ChunkMetadata {
-                    document_id: document.id.clone(),
-                    document_name: document.name.clone(),
-                    chunk_index,
-                    start_char: start,
-                    end_char: end,
-                    created_at: Self::current_timestamp(),
-                },
-            };
+        while start < char_len {
+            let end = (start + size).min(char_len);
 
-            chunks.push(chunk);
-            chunk_index += 1;
+            chunks.push((start, end, None));
 
-            // Move start position
-            if end >= content.len() {
+            // Move start position (a full chunk ends at start + size, so this
+            // always moves forward)
+            if end >= char_len {
                 break;
             }
             start = end - overlap;
@@ -97,28 +291,259 @@ impl DocumentChunker {
     fn chunk_recursive(
         &self,
         document: &Document,
+        offsets: &CharOffsets,
+        size: usize,
+        overlap: usize,
+    ) -> Result<Vec<ChunkSpan>> {
+        if size == 0 {
+            return Err(LlmError::Config("Chunk size must be positive".to_string()).into());
+        }
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        // Sentences in the chunk being built
+        let mut current: Vec<(usize, usize)> = Vec::new();
+
+        for (start, end, _) in split_sentences(&document.content) {
+            let (start, end) = (offsets.to_char(start), offsets.to_char(end));
+
+            if end - start > size {
+                // Oversized sentence: flush and split it at fixed size
+                if let Some(span) = Self::span_of(&current) {
+                    spans.push(span);
+                }
+                current.clear();
+
+                let mut piece_start = start;
+                while piece_start < end {
+                    let piece_end = (piece_start + size).min(end);
+                    spans.push((piece_start, piece_end));
+                    piece_start = piece_end;
+                }
+                continue;
+            }
+
+            if current.first().is_some_and(|&(first, _)| end - first > size) {
+                spans.push(Self::span_of(&current).unwrap());
+
+                // Carry trailing sentences that fit within the overlap
+                let last_end = current.last().unwrap().1;
+                let keep = current
+                    .iter()
+                    .rposition(|&(s, _)| last_end - s > overlap || end - s > size)
+                    .map_or(0, |i| i + 1);
+                current.drain(..keep);
+            }
+            current.push((start, end));
+        }
+
+        if let Some(span) = Self::span_of(&current) {
+            spans.push(span);
+        }
+
+        let chunks: Vec<ChunkSpan> =
+            spans.into_iter().map(|(start, end)| (start, end, None)).collect();
+
+        log::info!(
+            "Chunked document '{}' into {} chunks using recursive strategy",
+            document.name,
+            chunks.len()
+        );
+
+        Ok(chunks)
+    }
+
+    /// Token-window chunking
+    fn chunk_tokens(
+        &self,
+        document: &Document,
+        char_offsets: &CharOffsets,
         size: usize,
         overlap: usize,
-    ) -> Result<Vec<Chunk>> {
-        // TODO: Implement recursive chunking with separators
-        // Separators: ["\n\n", "\n", ". ", " "]
-        // For now, fall back to fixed-size
-        log::warn!("Recursive chunking not yet implemented, using fixed-size");
-        self.chunk_fixed_size(document, size, overlap)
+    ) -> Result<Vec<ChunkSpan>> {
+        let tokenizer = self.tokenizer.as_ref().ok_or_else(|| {
+            LlmError::Config("Token-based chunking requires a tokenizer".to_string())
+        })?;
+        if size == 0 {
+            return Err(LlmError::Config("Chunk size must be positive".to_string()).into());
+        }
+
+        let offsets = tokenizer.token_offsets(&document.content)?;
+        let step = size.saturating_sub(overlap).max(1);
+        let mut chunks = Vec::new();
+
+        let mut start = 0;
+        while start < offsets.len() {
+            let end = (start + size).min(offsets.len());
+
+            chunks.push((
+                char_offsets.to_char(offsets[start].0),
+                char_offsets.to_char(offsets[end - 1].1),
+                Some(end - start),
+            ));
+
+            if end == offsets.len() {
+                break;
+            }
+            start += step;
+        }
+
+        log::info!(
+            "Chunked document '{}' into {} chunks using token-based strategy",
+            document.name,
+            chunks.len()
+        );
+
+        Ok(chunks)
+    }
+
+    /// Sentence chunking
+    fn chunk_sentences(&self, document: &Document, offsets: &CharOffsets) -> Vec<ChunkSpan> {
+        let chunks: Vec<ChunkSpan> = split_sentences(&document.content)
+            .into_iter()
+            .map(|(start, end, _)| (offsets.to_char(start), offsets.to_char(end), None))
+            .collect();
+
+        log::info!(
+            "Chunked document '{}' into {} chunks using sentence strategy",
+            document.name,
+            chunks.len()
+        );
+
+        chunks
+    }
+
+    /// Span from the first to the last sentence
+    fn span_of(sentences: &[(usize, usize)]) -> Option<(usize, usize)> {
+        Some((sentences.first()?.0, sentences.last()?.1))
+    }
+
+    /// Build a chunk covering characters `start..end` of the document
+    fn build_chunk(
+        &self,
+        document: &Document,
+        offsets: &CharOffsets,
+        chunk_index: usize,
+        start: usize,
+        end: usize,
+    ) -> Chunk {
+        let content = document.content[offsets.to_byte(start)..offsets.to_byte(end)].to_string();
+        let parent_id = self.parent_chars.map(|parent_chars| {
+            let (parent_start, parent_end) =
+                parent_span(start, end, offsets.char_len(), parent_chars);
+            span_id(&document.id, parent_start, parent_end)
+        });
+
+        Chunk {
+            id: self.id_strategy.chunk_id(&document.id, chunk_index, start, &content),
+            content,
+            embedding: None,
+            metadata: ChunkMetadata {
+                document_id: document.id.clone(),
+                document_name: document.name.clone(),
+                chunk_index,
+                start_char: start,
+                end_char: end,
+                token_count: None,
+                created_at: current_timestamp(),
+                parent_id,
+                extra: self.inherited_extra(document),
+            },
+        }
+    }
+
+    /// Selected document metadata for a chunk's `extra` map
+    fn inherited_extra(&self, document: &Document) -> HashMap<String, String> {
+        self.inherited_metadata
+            .iter()
+            .filter_map(|key| {
+                let value = match key.as_str() {
+                    "file_type" => Some(&document.metadata.file_type),
+                    "uploaded_at" => Some(&document.metadata.uploaded_at),
+                    _ => document.metadata.extra.get(key),
+                };
+                value.map(|value| (key.clone(), value.clone()))
+            })
+            .collect()
     }
 
     /// Semantic chunking (based on embedding similarity)
-    fn chunk_semantic(&self, document: &Document, _threshold: f32) -> Result<Vec<Chunk>> {
+    fn chunk_semantic(
+        &self,
+        document: &Document,
+        offsets: &CharOffsets,
+        _threshold: f32,
+    ) -> Result<Vec<ChunkSpan>> {
         // TODO: Implement semantic chunking
         // Requires embedding model integration
         log::warn!("Semantic chunking not yet implemented, using fixed-size");
-        self.chunk_fixed_size(document, 512, 50)
+        self.chunk_fixed_size(document, offsets, 512, 50)
+    }
+}
+
+/// Remove header/footer lines repeated across pages
+///
+/// Pages are separated by form feeds (`\x0c`), as produced by PDF text
+/// extraction. A line is dropped when it appears on at least half of the
+/// pages (and at least two). Digits are ignored when comparing lines, so
+/// "Page 3 of 10" matches "Page 4 of 10".
+pub fn strip_repeated_lines(text: &str) -> String {
+    let pages: Vec<&str> = text.split('\x0c').collect();
+    if pages.len() < 2 {
+        return text.to_string();
     }
 
-    /// Get current timestamp as ISO 8601 string
-    fn current_timestamp() -> String {
-        // TODO: Use proper timestamp
-        "2025-01-01T00:00:00Z".to_string()
+    let line_key = |line: &str| -> String {
+        line.trim()
+            .chars()
+            .map(|c| if c.is_ascii_digit() { '#' } else { c })
+            .collect()
+    };
+
+    let mut page_counts: HashMap<String, usize> = HashMap::new();
+    for page in &pages {
+        let mut keys: Vec<String> = page
+            .lines()
+            .map(line_key)
+            .filter(|key| !key.is_empty())
+            .collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            *page_counts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    let min_pages = (pages.len() / 2).max(2);
+
+    pages
+        .iter()
+        .map(|page| {
+            page.lines()
+                .filter(|line| {
+                    let key = line_key(line);
+                    key.is_empty() || page_counts.get(&key).copied().unwrap_or(0) < min_pages
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\x0c")
+}
+
+/// Build a plain-text document for tests
+#[cfg(test)]
+pub(crate) fn document(id: &str, content: &str) -> Document {
+    Document {
+        id: id.to_string(),
+        name: id.to_string(),
+        content: content.to_string(),
+        metadata: super::DocumentMetadata {
+            file_type: "txt".to_string(),
+            size_bytes: content.len(),
+            char_count: content.chars().count(),
+            uploaded_at: "2025-01-01".to_string(),
+            num_chunks: 0,
+            extra: Default::default(),
+        },
     }
 }
 
@@ -135,8 +560,10 @@ mod tests {
             metadata: super::super::DocumentMetadata {
                 file_type: "txt".to_string(),
                 size_bytes: 1000,
+                char_count: 1000,
                 uploaded_at: "2025-01-01".to_string(),
                 num_chunks: 0,
+                extra: Default::default(),
             },
         };
 
@@ -150,4 +577,276 @@ mod tests {
         assert!(!chunks.is_empty());
         assert!(chunks[0].content.len() <= 100);
     }
+
+    #[test]
+    fn test_strip_repeated_footer() {
+        let bodies = [
+            "Introduction to the quarterly report.",
+            "Revenue grew in every region.",
+            "Costs were flat year over year.",
+            "Outlook remains positive.",
+        ];
+        let content = bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| format!("{}\nACME Corp Confidential - Page {} of 4", body, i + 1))
+            .collect::<Vec<_>>()
+            .join("\x0c");
+
+        let document = document("test_doc", &content);
+
+        let chunker = DocumentChunker::new(ChunkingStrategy::FixedSize {
+            size: 60,
+            overlap: 0,
+        })
+        .with_cleaner(strip_repeated_lines);
+
+        let chunks = chunker.chunk(&document).unwrap();
+
+        assert!(!chunks.is_empty());
+        assert!(chunks.iter().all(|c| !c.content.contains("Confidential")));
+        let joined: String = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert!(joined.contains("Costs were flat"));
+    }
+
+    #[test]
+    fn test_content_hash_ids() {
+        let hashed = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 10, overlap: 0 })
+            .with_id_strategy(ChunkIdStrategy::ContentHash);
+        let rechunked = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 10, overlap: 5 })
+            .with_id_strategy(ChunkIdStrategy::ContentHash);
+
+        let first = hashed.chunk(&document("a", "0123456789abcdefghij")).unwrap();
+        let second = rechunked.chunk(&document("a", "0123456789abcdefghij")).unwrap();
+
+        // Unchanged chunks keep their ID across chunking strategies
+        assert_eq!(first[0].id, second[0].id);
+        // Different content gets a different ID
+        assert_ne!(first[0].id, first[1].id);
+        assert_ne!(second[0].id, second[1].id);
+
+        // Identical text in another document, or repeated within one, does not
+        let other = hashed.chunk(&document("b", "0123456789abcdefghij")).unwrap();
+        assert_ne!(first[0].id, other[0].id);
+        let twice = hashed.chunk(&document("c", "0123456789012345678901234567890")).unwrap();
+        assert_eq!(twice[0].content, twice[1].content);
+        assert_ne!(twice[0].id, twice[1].id);
+
+        let sequential = DocumentChunker::new(ChunkingStrategy::default())
+            .chunk(&document("a", "text"))
+            .unwrap();
+        assert_eq!(sequential[0].id, "a_0");
+    }
+
+    #[test]
+    fn test_recursive_chunking_on_sentences() {
+        let content = "First sentence here. Second one follows. Third is last.";
+        let document = document("doc", content);
+
+        let chunker = DocumentChunker::new(ChunkingStrategy::Recursive {
+            size: 45,
+            overlap: 20,
+        });
+        let chunks = chunker.chunk(&document).unwrap();
+
+        let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            vec![
+                "First sentence here. Second one follows.",
+                "Second one follows. Third is last."
+            ]
+        );
+        for chunk in &chunks {
+            assert_eq!(
+                &content[chunk.metadata.start_char..chunk.metadata.end_char],
+                chunk.content
+            );
+        }
+
+        let zero = DocumentChunker::new(ChunkingStrategy::Recursive { size: 0, overlap: 0 });
+        assert!(zero.chunk(&document).is_err());
+    }
+
+    #[test]
+    fn test_token_based_chunking_counts_tokens() {
+        use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+
+        let words = ["one", "two", "three", "four", "five", "six", "seven"];
+        let content = "one two  three four\nfive six seven";
+        let document = document("doc", content);
+
+        let chunker = DocumentChunker::new(ChunkingStrategy::TokenBased { size: 3, overlap: 1 })
+            .with_tokenizer(word_level_tokenizer(&words));
+        let chunks = chunker.chunk(&document).unwrap();
+
+        let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, vec!["one two  three", "three four\nfive", "five six seven"]);
+
+        let tokenizer = word_level_tokenizer(&words);
+        for chunk in &chunks {
+            assert_eq!(
+                chunk.metadata.token_count,
+                Some(tokenizer.encode(&chunk.content).unwrap().len())
+            );
+        }
+
+        // Other strategies fill the count when a tokenizer is available
+        let fixed = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 8, overlap: 0 })
+            .with_tokenizer(word_level_tokenizer(&words))
+            .chunk(&document)
+            .unwrap();
+        assert_eq!(fixed[0].metadata.token_count, Some(2));
+
+        // Without a tokenizer, token-based chunking is a configuration error
+        let err = DocumentChunker::new(ChunkingStrategy::TokenBased { size: 3, overlap: 1 })
+            .chunk(&document)
+            .unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Config");
+    }
+
+    #[test]
+    fn test_cjk_offsets_are_character_indices() {
+        let content = "東京は日本の首都です。大阪は西日本の中心です。";
+        let document = document("cjk", content);
+        assert!(document.metadata.char_count < document.metadata.size_bytes);
+
+        let chunks = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 8, overlap: 2 })
+            .chunk(&document)
+            .unwrap();
+
+        let chars: Vec<char> = content.chars().collect();
+        for chunk in &chunks {
+            let expected: String = chars[chunk.metadata.start_char..chunk.metadata.end_char]
+                .iter()
+                .collect();
+            assert_eq!(chunk.content, expected);
+            assert!(chunk.content.chars().count() <= 8);
+        }
+        assert_eq!(chunks.last().unwrap().metadata.end_char, chars.len());
+    }
+
+    #[test]
+    fn test_chunks_inherit_document_metadata() {
+        let mut document = document("report", &"b".repeat(300));
+        document.metadata.file_type = "pdf".to_string();
+        document.metadata.extra.insert("team".to_string(), "finance".to_string());
+
+        let chunker = DocumentChunker::new(ChunkingStrategy::FixedSize {
+            size: 100,
+            overlap: 0,
+        })
+        .with_inherited_metadata(&["file_type", "team", "missing"]);
+
+        let chunks = chunker.chunk(&document).unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        for chunk in &chunks {
+            assert_eq!(chunk.metadata.extra.len(), 2);
+            assert_eq!(chunk.metadata.extra["file_type"], "pdf");
+            assert_eq!(chunk.metadata.extra["team"], "finance");
+        }
+    }
+
+    // `rand` is only usable natively
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_chunk_offsets_match_content() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        const PIECES: [&str; 12] = [
+            "word", "Dr.", " ", "  ", ". ", "?! ", "\n", "\n\n", "東京", "é", "\"Quote.\" ", "x",
+        ];
+        let mut rng = StdRng::seed_from_u64(157);
+
+        for _ in 0..300 {
+            let content: String = (0..rng.gen_range(0..60))
+                .map(|_| PIECES[rng.gen_range(0..PIECES.len())])
+                .collect();
+            let document = document("doc", &content);
+
+            let size = rng.gen_range(1..40);
+            let overlap = rng.gen_range(0..size);
+            let strategy = if rng.gen_bool(0.5) {
+                ChunkingStrategy::FixedSize { size, overlap }
+            } else {
+                ChunkingStrategy::Recursive { size, overlap }
+            };
+
+            let chars: Vec<char> = content.chars().collect();
+            for chunk in DocumentChunker::new(strategy).chunk(&document).unwrap() {
+                let (start, end) = (chunk.metadata.start_char, chunk.metadata.end_char);
+                assert!(start <= end && end <= chars.len(), "{:?} on {:?}", strategy, content);
+                let expected: String = chars[start..end].iter().collect();
+                assert_eq!(chunk.content, expected, "{:?} on {:?}", strategy, content);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_iter_matches_chunk() {
+        let content = "First sentence here. Second one follows! A third? ".repeat(20);
+        let document = document("doc", &content);
+
+        for strategy in [
+            ChunkingStrategy::FixedSize { size: 64, overlap: 8 },
+            ChunkingStrategy::Recursive { size: 80, overlap: 20 },
+        ] {
+            let chunker =
+                DocumentChunker::new(strategy).with_cleaner(|text| text.trim().to_string());
+            let expected = chunker.chunk(&document).unwrap();
+            let lazy: Vec<Chunk> = chunker.chunk_iter(&document).unwrap().collect();
+
+            assert!(expected.len() > 1);
+            assert_eq!(lazy.len(), expected.len());
+            for (a, b) in lazy.iter().zip(&expected) {
+                assert_eq!(a.id, b.id);
+                assert_eq!(a.content, b.content);
+                assert_eq!(a.metadata.chunk_index, b.metadata.chunk_index);
+                assert_eq!(
+                    (a.metadata.start_char, a.metadata.end_char),
+                    (b.metadata.start_char, b.metadata.end_char)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixed_size_edge_cases() {
+        let content = "Grüße aus 東京! ".repeat(3);
+        let char_len = content.chars().count();
+        let chars: Vec<char> = content.chars().collect();
+        let document = document("doc", &content);
+
+        for size in [1, 7, char_len, char_len + 10] {
+            for overlap in [0, size / 2, size - 1, size, size + 5] {
+                let chunker = DocumentChunker::new(ChunkingStrategy::FixedSize { size, overlap });
+                let chunks = chunker.chunk(&document).unwrap();
+                let case = format!("size {} overlap {}", size, overlap);
+
+                assert!(chunks.len() <= char_len, "{}", case);
+                assert_eq!(chunks[0].metadata.start_char, 0, "{}", case);
+                assert_eq!(chunks.last().unwrap().metadata.end_char, char_len, "{}", case);
+
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let (start, end) = (chunk.metadata.start_char, chunk.metadata.end_char);
+                    assert!(start < end && end - start <= size, "{}", case);
+                    let expected: String = chars[start..end].iter().collect();
+                    assert_eq!(chunk.content, expected, "{}", case);
+
+                    if i > 0 {
+                        let previous = &chunks[i - 1].metadata;
+                        // Contiguous coverage, always moving forward
+                        assert!(start > previous.start_char, "{}", case);
+                        assert!(start <= previous.end_char, "{}", case);
+                        assert_eq!(previous.end_char - start, overlap.min(size - 1), "{}", case);
+                    }
+                }
+            }
+        }
+
+        let zero = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 0, overlap: 0 });
+        assert!(zero.chunk(&document).is_err());
+    }
 }
@@ -0,0 +1,314 @@
+// Web worker bridge for parallel batch embedding
+//
+// Message protocol:
+//   main -> worker: { id: number, shard: number, texts: string[] }
+//   worker -> main: { id: number, shard: number, embeddings: number[][], error?: string }
+//
+// Each worker runs its own embedding pipeline (e.g. Transformers.js
+// 'feature-extraction') and replies once per request, echoing its `id`.
+// Several requests may be in flight on one worker; replies are matched to
+// requests by `id`, not by arrival order.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::LlmError;
+
+/// Request sent to an embedding worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedRequest {
+    /// Request ID, echoed in the response
+    #[serde(default)]
+    pub id: u64,
+    /// Shard index, used to restore input order
+    pub shard: usize,
+    pub texts: Vec<String>,
+}
+
+/// Response returned by an embedding worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedResponse {
+    #[serde(default)]
+    pub id: u64,
+    pub shard: usize,
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Split texts into at most `num_shards` contiguous shards
+pub fn split_into_shards(texts: &[String], num_shards: usize) -> Vec<EmbedRequest> {
+    if texts.is_empty() {
+        return Vec::new();
+    }
+
+    let num_shards = num_shards.clamp(1, texts.len());
+    let shard_size = texts.len().div_ceil(num_shards);
+
+    texts
+        .chunks(shard_size)
+        .enumerate()
+        .map(|(shard, texts)| EmbedRequest {
+            id: 0,
+            shard,
+            texts: texts.to_vec(),
+        })
+        .collect()
+}
+
+/// Reassemble shard responses into input order
+///
+/// Responses may arrive in any order; every shard must be present and
+/// the total number of embeddings must match `expected`.
+pub fn gather_shards(mut responses: Vec<EmbedResponse>, expected: usize) -> Result<Vec<Vec<f32>>> {
+    responses.sort_by_key(|r| r.shard);
+
+    let mut embeddings = Vec::with_capacity(expected);
+    for (i, response) in responses.into_iter().enumerate() {
+        if let Some(error) = response.error {
+            let message = format!("failed on shard {}: {}", response.shard, error);
+            return Err(LlmError::Worker(message).into());
+        }
+        if response.shard != i {
+            anyhow::bail!("Missing embedding shard {}", i);
+        }
+        embeddings.extend(response.embeddings);
+    }
+
+    if embeddings.len() != expected {
+        anyhow::bail!(
+            "Expected {} embeddings from workers, got {}",
+            expected,
+            embeddings.len()
+        );
+    }
+
+    Ok(embeddings)
+}
+
+/// Promise callbacks of an in-flight request
+#[cfg(target_arch = "wasm32")]
+struct PendingReply {
+    /// Index of the worker the request was posted to
+    worker: usize,
+    resolve: js_sys::Function,
+    reject: js_sys::Function,
+}
+
+/// In-flight requests by request ID
+#[cfg(target_arch = "wasm32")]
+type PendingReplies =
+    std::rc::Rc<std::cell::RefCell<std::collections::HashMap<u64, PendingReply>>>;
+
+/// Pool of web workers, each running its own embedding pipeline
+///
+/// Workers are spawned once and reused for every batch, so each loads its
+/// model only once.
+#[cfg(target_arch = "wasm32")]
+pub struct EmbeddingWorkerPool {
+    workers: Vec<web_sys::Worker>,
+    pending: PendingReplies,
+    next_id: std::cell::Cell<u64>,
+    /// Keeps the workers' message and error handlers alive
+    _handlers: Vec<wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl EmbeddingWorkerPool {
+    /// Spawn `num_workers` workers from the given script URL
+    pub fn new(script_url: &str, num_workers: usize) -> Result<Self> {
+        use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+
+        let workers = (0..num_workers.max(1))
+            .map(|_| {
+                web_sys::Worker::new(script_url)
+                    .map_err(|e| LlmError::Worker(format!("failed to spawn: {:?}", e)).into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let pending = PendingReplies::default();
+        let mut handlers = Vec::with_capacity(workers.len() * 3);
+        for (index, worker) in workers.iter().enumerate() {
+            let replies = pending.clone();
+            let on_message = Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+                let data = event.unchecked_into::<web_sys::MessageEvent>().data();
+                let id = js_sys::Reflect::get(&data, &JsValue::from_str("id"))
+                    .ok()
+                    .and_then(|id| id.as_f64());
+                let reply = id.and_then(|id| replies.borrow_mut().remove(&(id as u64)));
+                match reply {
+                    Some(reply) => {
+                        let _ = reply.resolve.call1(&JsValue::NULL, &data);
+                    }
+                    None => log::warn!("Embedding worker reply with unknown id: {:?}", id),
+                }
+            });
+            worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            handlers.push(on_message);
+
+            // A worker that fails to load or throws never replies, so fail
+            // its in-flight requests instead of leaving them pending
+            let on_error = Self::error_handler(&pending, index);
+            worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            handlers.push(on_error);
+
+            let on_message_error = Self::error_handler(&pending, index);
+            worker.set_onmessageerror(Some(on_message_error.as_ref().unchecked_ref()));
+            handlers.push(on_message_error);
+        }
+
+        Ok(Self {
+            workers,
+            pending,
+            next_id: std::cell::Cell::new(0),
+            _handlers: handlers,
+        })
+    }
+
+    /// Handler that rejects every request in flight on worker `index`
+    fn error_handler(
+        pending: &PendingReplies,
+        index: usize,
+    ) -> wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)> {
+        use wasm_bindgen::{closure::Closure, JsValue};
+
+        let pending = pending.clone();
+        Closure::new(move |event: JsValue| {
+            let message = js_sys::Reflect::get(&event, &JsValue::from_str("message"))
+                .ok()
+                .and_then(|message| message.as_string())
+                .unwrap_or_else(|| "worker error".to_string());
+            let error = format!("Embedding worker {} failed: {}", index, message);
+            let error = JsValue::from_str(&error);
+
+            let failed: Vec<PendingReply> = {
+                let mut pending = pending.borrow_mut();
+                let ids: Vec<u64> = pending
+                    .iter()
+                    .filter(|(_, reply)| reply.worker == index)
+                    .map(|(&id, _)| id)
+                    .collect();
+                ids.iter().filter_map(|id| pending.remove(id)).collect()
+            };
+            for reply in failed {
+                let _ = reply.reject.call1(&JsValue::NULL, &error);
+            }
+        })
+    }
+
+    /// Embed texts across the pool, preserving input order
+    pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let requests = split_into_shards(texts, self.workers.len());
+
+        let pending = requests
+            .into_iter()
+            .enumerate()
+            .map(|(index, request)| self.send(index, request))
+            .collect::<Result<Vec<_>>>()?;
+
+        let responses = futures::future::try_join_all(pending).await?;
+
+        gather_shards(responses, texts.len())
+    }
+
+    /// Post one request and wait for the reply carrying its ID
+    fn send(
+        &self,
+        worker: usize,
+        mut request: EmbedRequest,
+    ) -> Result<impl std::future::Future<Output = Result<EmbedResponse>>> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        request.id = id;
+
+        let message = serde_wasm_bindgen::to_value(&request)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize request: {}", e))?;
+
+        // The executor runs synchronously, so the callbacks are registered
+        // before the request is posted
+        let pending = self.pending.clone();
+        let promise = js_sys::Promise::new(&mut move |resolve, reject| {
+            pending.borrow_mut().insert(id, PendingReply { worker, resolve, reject });
+        });
+
+        if let Err(e) = self.workers[worker].post_message(&message) {
+            self.pending.borrow_mut().remove(&id);
+            return Err(LlmError::Worker(format!("failed to post message: {:?}", e)).into());
+        }
+
+        Ok(async move {
+            let data = wasm_bindgen_futures::JsFuture::from(promise)
+                .await
+                .map_err(|e| LlmError::Worker(format!("{:?}", e)))?;
+
+            serde_wasm_bindgen::from_value(data)
+                .map_err(|e| LlmError::Parse(format!("Invalid worker response: {}", e)).into())
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for EmbeddingWorkerPool {
+    fn drop(&mut self) {
+        self.pending.borrow_mut().clear();
+        for worker in &self.workers {
+            worker.terminate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_gather_preserves_order() {
+        let texts: Vec<String> = (0..7).map(|i| format!("text {}", i)).collect();
+
+        let requests = split_into_shards(&texts, 3);
+        assert_eq!(requests.len(), 3);
+
+        // Simulate workers replying out of order
+        let mut responses: Vec<EmbedResponse> = requests
+            .into_iter()
+            .map(|request| EmbedResponse {
+                id: request.id,
+                shard: request.shard,
+                embeddings: request
+                    .texts
+                    .iter()
+                    .map(|t| vec![t[5..].parse::<f32>().unwrap()])
+                    .collect(),
+                error: None,
+            })
+            .collect();
+        responses.reverse();
+
+        let embeddings = gather_shards(responses, texts.len()).unwrap();
+        let order: Vec<f32> = embeddings.iter().map(|e| e[0]).collect();
+        assert_eq!(order, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn test_gather_detects_missing_shard() {
+        let responses = vec![EmbedResponse {
+            id: 0,
+            shard: 1,
+            embeddings: vec![vec![0.0]],
+            error: None,
+        }];
+        assert!(gather_shards(responses, 1).is_err());
+    }
+
+    #[test]
+    fn test_gather_reports_worker_error_kind() {
+        let responses = vec![EmbedResponse {
+            id: 0,
+            shard: 0,
+            embeddings: Vec::new(),
+            error: Some("model failed to load".to_string()),
+        }];
+        let err = gather_shards(responses, 1).unwrap_err();
+        assert_eq!(crate::error::error_kind(&err), "Worker");
+    }
+}
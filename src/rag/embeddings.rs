@@ -1,21 +1,122 @@
-use anyhow::Result;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use super::embedding_workers::{gather_shards, split_into_shards, EmbedResponse};
+use crate::llm::PhiModel;
+
+/// Default script for embedding web workers
+pub const DEFAULT_WORKER_SCRIPT: &str = "./embedding_worker.js";
 
 /// Embedding model wrapper
 /// This will integrate with Transformers.js or Candle for embeddings
 pub struct EmbeddingModel {
     model_name: String,
     dimension: usize,
+    /// Number of web workers used by `embed_batch` (1 = no workers)
+    num_workers: usize,
+    /// Script URL each embedding worker is spawned from
+    worker_script_url: String,
+    /// Instruction prepended to queries by `embed_query`
+    query_prefix: String,
+    /// Instruction prepended to passages by `embed_document`
+    document_prefix: String,
+    /// Most texts `embed_batch` sends to the backend at once (None = all)
+    batch_size: Option<usize>,
+    /// Workers spawned by the first batch that needed them and reused after
+    #[cfg(target_arch = "wasm32")]
+    worker_pool: std::cell::OnceCell<super::embedding_workers::EmbeddingWorkerPool>,
+}
+
+/// Query and passage prefixes expected by asymmetric embedding models
+fn instruction_prefixes(model_name: &str) -> (&'static str, &'static str) {
+    let name = model_name.to_lowercase();
+    if name.contains("e5-") {
+        ("query: ", "passage: ")
+    } else if name.contains("bge-") && name.contains("-en") {
+        ("Represent this sentence for searching relevant passages: ", "")
+    } else {
+        ("", "")
+    }
 }
 
 impl EmbeddingModel {
     /// Create a new embedding model
     pub fn new(model_name: String) -> Self {
+        let (query_prefix, document_prefix) = instruction_prefixes(&model_name);
         Self {
+            query_prefix: query_prefix.to_string(),
+            document_prefix: document_prefix.to_string(),
             model_name,
             dimension: 384, // Default for all-MiniLM-L6-v2
+            num_workers: 1,
+            worker_script_url: DEFAULT_WORKER_SCRIPT.to_string(),
+            batch_size: None,
+            #[cfg(target_arch = "wasm32")]
+            worker_pool: std::cell::OnceCell::new(),
         }
     }
 
+    /// Split `embed_batch` input into sub-batches of at most `batch_size`
+    /// texts, keeping large batches within backend and memory limits
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size.max(1));
+        self
+    }
+
+    /// Split `embed_batch` across `num_workers` web workers
+    pub fn with_workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers.max(1);
+        self.reset_worker_pool();
+        self
+    }
+
+    /// Set the script URL used to spawn embedding workers
+    pub fn with_worker_script(mut self, script_url: String) -> Self {
+        self.worker_script_url = script_url;
+        self.reset_worker_pool();
+        self
+    }
+
+    /// Terminate any spawned workers so the next batch respawns them
+    fn reset_worker_pool(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.worker_pool = std::cell::OnceCell::new();
+        }
+    }
+
+    /// The worker pool, spawning it on first use
+    #[cfg(target_arch = "wasm32")]
+    fn worker_pool(&self) -> Result<&super::embedding_workers::EmbeddingWorkerPool> {
+        use super::embedding_workers::EmbeddingWorkerPool;
+
+        if let Some(pool) = self.worker_pool.get() {
+            return Ok(pool);
+        }
+        let pool = EmbeddingWorkerPool::new(&self.worker_script_url, self.num_workers)?;
+        Ok(self.worker_pool.get_or_init(|| pool))
+    }
+
+    /// Override the instruction prefixes inferred from the model name
+    pub fn with_prefixes(mut self, query_prefix: &str, document_prefix: &str) -> Self {
+        self.query_prefix = query_prefix.to_string();
+        self.document_prefix = document_prefix.to_string();
+        self
+    }
+
+    /// Prefix applied to queries (empty for symmetric models)
+    pub fn query_prefix(&self) -> &str {
+        &self.query_prefix
+    }
+
+    /// Prefix applied to indexed passages (empty for symmetric models)
+    pub fn document_prefix(&self) -> &str {
+        &self.document_prefix
+    }
+
     /// Load the embedding model
     pub async fn load(&mut self) -> Result<()> {
         log::info!("Loading embedding model: {}", self.model_name);
@@ -47,10 +148,73 @@ impl EmbeddingModel {
         Ok(embedding)
     }
 
+    /// Embed a search query, with the model's query prefix
+    pub async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed(&format!("{}{}", self.query_prefix, query)).await
+    }
+
+    /// Embed search queries in one batch, with the model's query prefix
+    pub async fn embed_queries(&self, queries: &[String]) -> Result<Vec<Vec<f32>>> {
+        if self.query_prefix.is_empty() {
+            return self.embed_batch(queries).await;
+        }
+        let prefixed: Vec<String> = queries
+            .iter()
+            .map(|query| format!("{}{}", self.query_prefix, query))
+            .collect();
+        self.embed_batch(&prefixed).await
+    }
+
+    /// Embed a passage for indexing, with the model's document prefix
+    pub async fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed(&format!("{}{}", self.document_prefix, text)).await
+    }
+
+    /// Embed passages for indexing, with the model's document prefix
+    pub async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if self.document_prefix.is_empty() {
+            return self.embed_batch(texts).await;
+        }
+        let prefixed: Vec<String> = texts
+            .iter()
+            .map(|text| format!("{}{}", self.document_prefix, text))
+            .collect();
+        self.embed_batch(&prefixed).await
+    }
+
     /// Generate embeddings for multiple texts (batch)
+    ///
+    /// With a batch size set, the texts are embedded in sub-batches and the
+    /// results concatenated in input order.
     pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
         log::debug!("Generating embeddings for {} texts", texts.len());
 
+        match self.batch_size {
+            Some(batch_size) => {
+                embed_in_batches(texts, batch_size, |batch| self.embed_backend_batch(batch)).await
+            }
+            None => self.embed_backend_batch(texts).await,
+        }
+    }
+
+    /// One backend call embedding all of `texts`
+    async fn embed_backend_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if self.num_workers > 1 && texts.len() > 1 {
+            #[cfg(target_arch = "wasm32")]
+            {
+                let result = match self.worker_pool() {
+                    Ok(pool) => pool.embed_batch(texts).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(embeddings) => return Ok(embeddings),
+                    Err(e) => log::warn!("Worker embedding failed, using single thread: {}", e),
+                }
+            }
+
+            return self.embed_batch_sharded(texts).await;
+        }
+
         // TODO: Implement batch embedding for better performance
         // Transformers.js supports batch processing
 
@@ -63,6 +227,36 @@ impl EmbeddingModel {
         Ok(embeddings)
     }
 
+    /// Single-threaded fallback for the worker pool
+    ///
+    /// Processes the same shards the workers would, in the current thread.
+    async fn embed_batch_sharded(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut responses = Vec::new();
+        for request in split_into_shards(texts, self.num_workers) {
+            let mut embeddings = Vec::with_capacity(request.texts.len());
+            for text in &request.texts {
+                embeddings.push(self.embed(text).await?);
+            }
+            responses.push(EmbedResponse {
+                id: request.id,
+                shard: request.shard,
+                embeddings,
+                error: None,
+            });
+        }
+
+        gather_shards(responses, texts.len())
+    }
+
+    /// Spread embedding requests across several models
+    pub fn multi(backends: Vec<EmbeddingModel>, strategy: MultiStrategy) -> MultiEmbedder {
+        let backends = backends
+            .into_iter()
+            .map(|model| Box::new(model) as Box<dyn Embedder>)
+            .collect();
+        MultiEmbedder::new(backends, strategy)
+    }
+
     /// Quantize embedding to int8
     pub fn quantize_int8(&self, embedding: &[f32]) -> Vec<i8> {
         embedding
@@ -88,6 +282,275 @@ impl EmbeddingModel {
     }
 }
 
+/// Embed `texts` in consecutive sub-batches of at most `batch_size`
+async fn embed_in_batches<'a, F, Fut>(
+    texts: &'a [String],
+    batch_size: usize,
+    mut embed: F,
+) -> Result<Vec<Vec<f32>>>
+where
+    F: FnMut(&'a [String]) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<Vec<f32>>>>,
+{
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size.max(1)) {
+        let batch_embeddings = embed(batch).await?;
+        if batch_embeddings.len() != batch.len() {
+            bail!(
+                "Embedding backend returned {} embeddings for {} texts",
+                batch_embeddings.len(),
+                batch.len()
+            );
+        }
+        embeddings.extend(batch_embeddings);
+    }
+    Ok(embeddings)
+}
+
+/// Source of text embeddings for indexing and retrieval
+#[async_trait(?Send)]
+pub trait Embedder {
+    /// Embed a single text
+    async fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embed several texts
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(text).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Embed a search query (asymmetric models add an instruction prefix)
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed(query).await
+    }
+
+    /// Embed passages for indexing (asymmetric models add a prefix)
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed_batch(texts).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Embedder for EmbeddingModel {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        EmbeddingModel::embed(self, text).await
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        EmbeddingModel::embed_batch(self, texts).await
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        EmbeddingModel::embed_query(self, query).await
+    }
+
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        EmbeddingModel::embed_documents(self, texts).await
+    }
+}
+
+/// Uses the language model's mean-pooled hidden states as embeddings
+#[async_trait(?Send)]
+impl Embedder for PhiModel {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        PhiModel::embed(self, text)
+    }
+}
+
+/// How a `MultiEmbedder` picks a backend for each request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiStrategy {
+    /// Rotate through backends in proportion to their weights
+    RoundRobin,
+    /// Use the first backend, moving to the next one on error
+    Fallback,
+}
+
+/// Embedder distributing requests over several backends
+///
+/// All backends must produce embeddings of the same dimension; the first
+/// embedding returned fixes the dimension and any later mismatch is an error.
+pub struct MultiEmbedder {
+    backends: Vec<Box<dyn Embedder>>,
+    weights: Vec<usize>,
+    strategy: MultiStrategy,
+    /// Position in the weighted round-robin schedule
+    cursor: Cell<usize>,
+    dimension: Cell<Option<usize>>,
+}
+
+impl MultiEmbedder {
+    /// Create a multi-backend embedder with equal weights
+    pub fn new(backends: Vec<Box<dyn Embedder>>, strategy: MultiStrategy) -> Self {
+        let weights = vec![1; backends.len()];
+        Self {
+            backends,
+            weights,
+            strategy,
+            cursor: Cell::new(0),
+            dimension: Cell::new(None),
+        }
+    }
+
+    /// Set round-robin weights, one per backend (0 = never scheduled)
+    pub fn with_weights(mut self, weights: Vec<usize>) -> Self {
+        self.weights = weights;
+        self.weights.resize(self.backends.len(), 1);
+        self
+    }
+
+    /// Embedding dimension, once known
+    pub fn dimension(&self) -> Option<usize> {
+        self.dimension.get()
+    }
+
+    /// Backend indices to try for the next request, in order
+    fn schedule(&self) -> Vec<usize> {
+        match self.strategy {
+            MultiStrategy::Fallback => (0..self.backends.len()).collect(),
+            MultiStrategy::RoundRobin => {
+                let total: usize = self.weights.iter().sum();
+                if total == 0 {
+                    return (0..self.backends.len()).take(1).collect();
+                }
+                let slot = self.cursor.get() % total;
+                self.cursor.set(self.cursor.get().wrapping_add(1));
+
+                let mut acc = 0;
+                let backend = self
+                    .weights
+                    .iter()
+                    .position(|&w| {
+                        acc += w;
+                        slot < acc
+                    })
+                    .unwrap_or(0);
+                vec![backend]
+            }
+        }
+    }
+
+    /// Run `call` on the scheduled backends in turn until one succeeds
+    async fn try_backends<'a, T>(
+        &'a self,
+        call: impl Fn(&'a dyn Embedder) -> BackendFuture<'a, T>,
+    ) -> Result<T> {
+        let mut last_error = None;
+        for backend in self.schedule() {
+            match call(self.backends[backend].as_ref()).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    log::warn!("Embedding backend {} failed: {}", backend, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No embedding backends configured")))
+    }
+
+    fn check_dimensions(&self, embeddings: &[Vec<f32>]) -> Result<()> {
+        embeddings
+            .iter()
+            .try_for_each(|embedding| self.check_dimension(embedding))
+    }
+
+    fn check_dimension(&self, embedding: &[f32]) -> Result<()> {
+        match self.dimension.get() {
+            Some(dim) if dim != embedding.len() => bail!(
+                "Embedding dimension mismatch: expected {}, got {}",
+                dim,
+                embedding.len()
+            ),
+            Some(_) => Ok(()),
+            None => {
+                self.dimension.set(Some(embedding.len()));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Boxed future returned by `Embedder` methods
+type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
+
+/// Every method goes to the backends themselves, so each keeps its own
+/// query and passage prefixes
+#[async_trait(?Send)]
+impl Embedder for MultiEmbedder {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let embedding = self.try_backends(|backend| backend.embed(text)).await?;
+        self.check_dimension(&embedding)?;
+        Ok(embedding)
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let embeddings = self.try_backends(|backend| backend.embed_batch(texts)).await?;
+        self.check_dimensions(&embeddings)?;
+        Ok(embeddings)
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        let embedding = self.try_backends(|backend| backend.embed_query(query)).await?;
+        self.check_dimension(&embedding)?;
+        Ok(embedding)
+    }
+
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let embeddings = self.try_backends(|backend| backend.embed_documents(texts)).await?;
+        self.check_dimensions(&embeddings)?;
+        Ok(embeddings)
+    }
+}
+
+/// How vector search scores a chunk against the query (higher is better)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SimilarityMetric {
+    /// Cosine similarity in `[-1, 1]`
+    #[default]
+    Cosine,
+    /// Negated Euclidean distance in `(-inf, 0]`
+    Euclidean,
+    /// Raw dot product
+    DotProduct,
+}
+
+impl SimilarityMetric {
+    /// Score `b` against `a`
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            SimilarityMetric::Cosine => cosine_similarity(a, b),
+            SimilarityMetric::Euclidean => {
+                let distance: f32 = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum();
+                -distance.sqrt()
+            }
+            SimilarityMetric::DotProduct => a.iter().zip(b).map(|(x, y)| x * y).sum(),
+        }
+    }
+
+    /// Map a score from `score` into `[0, 1]`, preserving order
+    pub fn normalize(&self, score: f32) -> f32 {
+        let normalized = match self {
+            SimilarityMetric::Cosine => (score + 1.0) / 2.0,
+            SimilarityMetric::Euclidean => 1.0 / (1.0 - score),
+            SimilarityMetric::DotProduct => 1.0 / (1.0 + (-score).exp()),
+        };
+        normalized.clamp(0.0, 1.0)
+    }
+}
+
+/// Truncate `embedding` to `target` dimensions, or zero-pad it up to them
+///
+/// A best-effort bridge between embedding models of different sizes; the
+/// resulting similarities are only rough.
+pub fn reconcile_dimension(embedding: &[f32], target: usize) -> Vec<f32> {
+    let mut reconciled = embedding[..embedding.len().min(target)].to_vec();
+    reconciled.resize(target, 0.0);
+    reconciled
+}
+
 /// Cosine similarity between two vectors
 pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     assert_eq!(a.len(), b.len(), "Vectors must have same dimension");
@@ -119,6 +582,41 @@ mod tests {
         assert!((cosine_similarity(&c, &d) - 0.0).abs() < 0.0001);
     }
 
+    #[tokio::test]
+    async fn test_embed_batch_with_workers_fallback() {
+        let model = EmbeddingModel::new("test".to_string()).with_workers(3);
+        let texts: Vec<String> = (0..7).map(|i| format!("text {}", i)).collect();
+
+        let sharded = model.embed_batch(&texts).await.unwrap();
+        let mut sequential = Vec::new();
+        for text in &texts {
+            sequential.push(model.embed(text).await.unwrap());
+        }
+
+        assert_eq!(sharded.len(), texts.len());
+        assert_eq!(sharded, sequential);
+    }
+
+    #[tokio::test]
+    async fn test_embed_batch_in_sub_batches() {
+        let texts: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+
+        let model = EmbeddingModel::new("test".to_string()).with_batch_size(2);
+        let embeddings = model.embed_batch(&texts).await.unwrap();
+        assert_eq!(embeddings.len(), 5);
+
+        let calls = Cell::new(0);
+        let embeddings = embed_in_batches(&texts, 2, |batch| {
+            calls.set(calls.get() + 1);
+            let embeddings = batch.iter().map(|t| vec![t.parse::<f32>().unwrap()]).collect();
+            async move { Ok(embeddings) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls.get(), 3);
+        assert_eq!(embeddings, vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0], vec![4.0]]);
+    }
+
     #[test]
     fn test_quantization() {
         let model = EmbeddingModel::new("test".to_string());
@@ -131,4 +629,118 @@ mod tests {
             assert!((orig - deq).abs() < 0.02); // Allow small error
         }
     }
+
+    struct FixedEmbedder(Option<Vec<f32>>);
+
+    #[async_trait(?Send)]
+    impl Embedder for FixedEmbedder {
+        async fn embed(&self, _text: &str) -> Result<Vec<f32>> {
+            match &self.0 {
+                Some(embedding) => Ok(embedding.clone()),
+                None => bail!("backend unavailable"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_fallback_uses_next_backend() {
+        let multi = MultiEmbedder::new(
+            vec![
+                Box::new(FixedEmbedder(None)),
+                Box::new(FixedEmbedder(Some(vec![1.0, 2.0]))),
+            ],
+            MultiStrategy::Fallback,
+        );
+
+        assert_eq!(multi.embed("text").await.unwrap(), vec![1.0, 2.0]);
+        assert_eq!(multi.dimension(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_multi_weighted_round_robin() {
+        let multi = MultiEmbedder::new(
+            vec![
+                Box::new(FixedEmbedder(Some(vec![1.0]))),
+                Box::new(FixedEmbedder(Some(vec![2.0]))),
+            ],
+            MultiStrategy::RoundRobin,
+        )
+        .with_weights(vec![2, 1]);
+
+        let mut picks = Vec::new();
+        for _ in 0..6 {
+            picks.push(multi.embed("text").await.unwrap()[0]);
+        }
+        assert_eq!(picks, vec![1.0, 1.0, 2.0, 1.0, 1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn test_multi_rejects_dimension_mismatch() {
+        let multi = MultiEmbedder::new(
+            vec![
+                Box::new(FixedEmbedder(Some(vec![1.0, 0.0]))),
+                Box::new(FixedEmbedder(Some(vec![1.0, 0.0, 0.0]))),
+            ],
+            MultiStrategy::RoundRobin,
+        );
+
+        assert!(multi.embed("a").await.is_ok());
+        assert!(multi.embed("b").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multi_keeps_backend_prefixes() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        /// Asymmetric backend recording the texts it embeds
+        struct PrefixedEmbedder(Rc<RefCell<Vec<String>>>);
+
+        #[async_trait(?Send)]
+        impl Embedder for PrefixedEmbedder {
+            async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+                self.0.borrow_mut().push(text.to_string());
+                Ok(vec![1.0])
+            }
+
+            async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+                self.embed(&format!("query: {}", query)).await
+            }
+
+            async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+                let mut embeddings = Vec::new();
+                for text in texts {
+                    embeddings.push(self.embed(&format!("passage: {}", text)).await?);
+                }
+                Ok(embeddings)
+            }
+        }
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let multi = MultiEmbedder::new(
+            vec![
+                Box::new(FixedEmbedder(None)),
+                Box::new(PrefixedEmbedder(seen.clone())),
+            ],
+            MultiStrategy::Fallback,
+        );
+
+        multi.embed_query("cats").await.unwrap();
+        multi.embed_documents(&["dogs".to_string()]).await.unwrap();
+        assert_eq!(*seen.borrow(), vec!["query: cats", "passage: dogs"]);
+    }
+
+    #[test]
+    fn test_instruction_prefixes_by_model() {
+        let e5 = EmbeddingModel::new("intfloat/e5-small-v2".to_string());
+        assert_eq!(e5.query_prefix(), "query: ");
+        assert_eq!(e5.document_prefix(), "passage: ");
+
+        let minilm = EmbeddingModel::new("all-MiniLM-L6-v2".to_string());
+        assert_eq!(minilm.query_prefix(), "");
+        assert_eq!(minilm.document_prefix(), "");
+
+        let custom = minilm.with_prefixes("q: ", "d: ");
+        assert_eq!(custom.query_prefix(), "q: ");
+    }
 }
@@ -1,19 +1,36 @@
 // RAG (Retrieval Augmented Generation) module
 
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
 pub mod chunking;
+pub mod embedding_workers;
 pub mod embeddings;
 pub mod pipeline;
+pub mod prompt;
+pub mod query_cache;
 pub mod retrieval;
 pub mod vector_db;
 
-pub use chunking::{ChunkingStrategy, DocumentChunker};
-pub use embeddings::EmbeddingModel;
-pub use pipeline::RagPipeline;
-pub use retrieval::Retriever;
-pub use vector_db::VectorDatabase;
+pub use chunking::{ChunkIdStrategy, ChunkingStrategy, DocumentChunker};
+pub use embeddings::{
+    reconcile_dimension, Embedder, EmbeddingModel, MultiEmbedder, MultiStrategy,
+    SimilarityMetric,
+};
+pub use pipeline::{RagAnswer, RagPipeline, RagSource, DEFAULT_FALLBACK_ANSWER};
+pub use prompt::PromptBuilder;
+pub use query_cache::QueryCache;
+pub use retrieval::{DocumentResult, ParentHit, Retriever, ScoreAgg, ScoredExplanation};
+pub use vector_db::{
+    ChunkPersistence, EmbeddingStorage, FlushPolicy, MetadataFilter, NormalizationCheck,
+    VectorDatabase, VectorDbStats,
+};
 
 /// Document chunk with metadata
-#[derive(Debug, Clone)]
+///
+/// Equality and hashing use only `id`, so a `HashSet<Chunk>` dedupes the
+/// same chunk retrieved by several queries.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Chunk {
     pub id: String,
     pub content: String,
@@ -21,15 +38,83 @@ pub struct Chunk {
     pub metadata: ChunkMetadata,
 }
 
+impl Chunk {
+    /// Short preview of the content for display
+    ///
+    /// Returns at most `max_chars` characters. Longer content is cut at the
+    /// last word boundary that fits and ends with an ellipsis.
+    pub fn preview(&self, max_chars: usize) -> String {
+        let content = self.content.trim();
+        if content.chars().count() <= max_chars {
+            return content.to_string();
+        }
+        if max_chars == 0 {
+            return String::new();
+        }
+
+        // Leave room for the ellipsis
+        let cut = content
+            .char_indices()
+            .nth(max_chars - 1)
+            .map_or(content.len(), |(i, _)| i);
+        let mut prefix = &content[..cut];
+
+        // Don't end mid-word unless the first word alone is too long
+        let next_is_space = content[cut..].starts_with(char::is_whitespace);
+        if !next_is_space {
+            if let Some(space) = prefix.rfind(char::is_whitespace) {
+                prefix = &prefix[..space];
+            }
+        }
+
+        format!("{}…", prefix.trim_end())
+    }
+
+    /// Number of words in the content
+    pub fn word_count(&self) -> usize {
+        crate::utils::text::split_words(&self.content).len()
+    }
+
+    /// Number of characters (not bytes) in the content
+    pub fn char_count(&self) -> usize {
+        self.content.chars().count()
+    }
+}
+
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Chunk {}
+
+impl Hash for Chunk {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 /// Chunk metadata
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ChunkMetadata {
     pub document_id: String,
     pub document_name: String,
     pub chunk_index: usize,
+    /// Character (not byte) offset of the chunk start in the document
     pub start_char: usize,
+    /// Character offset one past the chunk end
     pub end_char: usize,
+    /// Number of tokens in `content`, when a tokenizer was used at index time
+    pub token_count: Option<usize>,
     pub created_at: String,
+    /// ID of the larger span this chunk belongs to, set at chunking time by
+    /// `DocumentChunker::with_parent_chars` (see
+    /// `Retriever::retrieve_with_parents`)
+    pub parent_id: Option<String>,
+    /// Document metadata copied in at chunking time
+    /// (see `DocumentChunker::with_inherited_metadata`)
+    pub extra: HashMap<String, String>,
 }
 
 /// Document for RAG system
@@ -41,13 +126,29 @@ pub struct Document {
     pub metadata: DocumentMetadata,
 }
 
+impl Document {
+    /// Strip control characters and normalize line endings in place
+    ///
+    /// Prevents null bytes and stray control characters from breaking
+    /// tokenization or JSON serialization. Updates the size metadata.
+    pub fn sanitize(&mut self) {
+        self.content = crate::utils::text::sanitize(&self.content);
+        self.metadata.size_bytes = self.content.len();
+        self.metadata.char_count = self.content.chars().count();
+    }
+}
+
 /// Document metadata
 #[derive(Debug, Clone)]
 pub struct DocumentMetadata {
     pub file_type: String,
     pub size_bytes: usize,
+    /// Number of characters (differs from `size_bytes` for non-ASCII text)
+    pub char_count: usize,
     pub uploaded_at: String,
     pub num_chunks: usize,
+    /// Free-form tags (e.g. `category`, `author`)
+    pub extra: HashMap<String, String>,
 }
 
 /// Search result with similarity score
@@ -56,3 +157,75 @@ pub struct SearchResult {
     pub chunk: Chunk,
     pub score: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunking::document;
+
+    fn chunk(content: &str) -> Chunk {
+        Chunk {
+            id: "c".to_string(),
+            content: content.to_string(),
+            embedding: None,
+            metadata: ChunkMetadata {
+                document_id: "doc".to_string(),
+                document_name: "doc".to_string(),
+                chunk_index: 0,
+                start_char: 0,
+                end_char: content.chars().count(),
+                token_count: None,
+                created_at: "2025-01-01".to_string(),
+                parent_id: None,
+                extra: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_preview_truncates_on_word_boundary() {
+        let c = chunk("The quick brown fox jumps over the lazy dog");
+        assert_eq!(c.preview(100), "The quick brown fox jumps over the lazy dog");
+        assert_eq!(c.preview(18), "The quick brown…");
+        assert_eq!(c.preview(20), "The quick brown fox…");
+        assert!(c.preview(18).chars().count() <= 18);
+
+        // Multibyte content is cut on character boundaries
+        let cjk = chunk("日本語のテキストです");
+        assert_eq!(cjk.preview(4), "日本語…");
+    }
+
+    #[test]
+    fn test_word_and_char_count() {
+        let c = chunk("Don't panic: it's only 42 words… ok?");
+        assert_eq!(c.word_count(), 7);
+        assert_eq!(c.char_count(), 36);
+    }
+
+    #[test]
+    fn test_document_sanitize() {
+        let content = "Header\0\r\nBody\u{1}text\r\n";
+        let mut document = document("doc", content);
+
+        document.sanitize();
+
+        assert_eq!(document.content, "Header\nBodytext\n");
+        assert_eq!(document.metadata.size_bytes, document.content.len());
+        assert_eq!(document.metadata.char_count, 16);
+    }
+
+    #[test]
+    fn test_chunk_equality_by_id() {
+        let first = chunk("original text");
+        let mut updated = chunk("edited text");
+        updated.embedding = Some(vec![1.0, 0.0]);
+        assert_eq!(first, updated);
+
+        let mut other = chunk("original text");
+        other.id = "d".to_string();
+        assert_ne!(first, other);
+
+        let set: std::collections::HashSet<Chunk> = [first, updated, other].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+}
@@ -1,14 +1,33 @@
 use anyhow::Result;
+use futures::{Stream, StreamExt};
+use std::rc::Rc;
+use serde::Serialize;
 use super::{
-    Document, DocumentChunker, ChunkingStrategy, EmbeddingModel,
-    VectorDatabase, Retriever,
+    Chunk, Document, DocumentChunker, DocumentMetadata, ChunkingStrategy, EmbeddingModel,
+    MetadataFilter, VectorDatabase, Retriever, SearchResult, PromptBuilder, embeddings::Embedder,
 };
+use crate::llm::{GenerationConfig, PhiModel};
+
+/// Text buffered by `index_stream` before it is chunked and embedded
+pub const DEFAULT_STREAM_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Chunks embedded and stored together by `index_document`
+const INDEX_BATCH_SIZE: usize = 32;
+
+/// Answer returned when no retrieved chunk is relevant enough
+pub const DEFAULT_FALLBACK_ANSWER: &str = "I don't have information about that.";
 
 /// RAG pipeline that orchestrates the entire RAG workflow
 pub struct RagPipeline {
     chunker: DocumentChunker,
     embedding_model: EmbeddingModel,
+    /// Overrides `embedding_model` (e.g. a shared `PhiModel`)
+    embedder: Option<Rc<dyn Embedder>>,
     vector_db: VectorDatabase,
+    /// Minimum top retrieval score required to generate an answer
+    min_relevance: Option<f32>,
+    fallback_answer: String,
+    stream_buffer_bytes: usize,
 }
 
 impl RagPipeline {
@@ -21,40 +40,197 @@ impl RagPipeline {
         Self {
             chunker: DocumentChunker::new(chunking_strategy),
             embedding_model,
+            embedder: None,
             vector_db,
+            min_relevance: None,
+            fallback_answer: DEFAULT_FALLBACK_ANSWER.to_string(),
+            stream_buffer_bytes: DEFAULT_STREAM_BUFFER_BYTES,
         }
     }
 
+    /// Embed with another source instead of the embedding model
+    ///
+    /// Pass an `Rc<PhiModel>` to reuse the loaded LLM for embeddings.
+    pub fn with_embedder(mut self, embedder: Rc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    /// Embedder used for indexing and retrieval
+    fn embedder(&self) -> &dyn Embedder {
+        match &self.embedder {
+            Some(embedder) => embedder.as_ref(),
+            None => &self.embedding_model,
+        }
+    }
+
+    /// Set how much text `index_stream` buffers between batches
+    pub fn with_stream_buffer_bytes(mut self, stream_buffer_bytes: usize) -> Self {
+        self.stream_buffer_bytes = stream_buffer_bytes.max(1);
+        self
+    }
+
+    /// Skip generation and return the fallback answer when the best
+    /// retrieval score is below `min_relevance`
+    pub fn with_min_relevance(mut self, min_relevance: f32) -> Self {
+        self.min_relevance = Some(min_relevance);
+        self
+    }
+
+    /// Override the answer returned when nothing relevant is retrieved
+    pub fn with_fallback_answer(mut self, fallback_answer: &str) -> Self {
+        self.fallback_answer = fallback_answer.to_string();
+        self
+    }
+
     /// Index a document (chunk + embed + store)
     pub async fn index_document(&mut self, document: Document) -> Result<usize> {
         log::info!("Indexing document: {}", document.name);
 
-        // Step 1: Chunk the document
-        let mut chunks = self.chunker.chunk(&document)?;
-        let num_chunks = chunks.len();
+        if document.content.trim().is_empty() {
+            log::warn!("Skipping document {}: no content", document.name);
+            return Ok(0);
+        }
 
-        log::info!("Created {} chunks", num_chunks);
+        // Chunk lazily and embed + store in batches, so only one batch of
+        // chunks is held in memory at a time. Whitespace-only chunks would
+        // only add noise to search.
+        let mut chunks = self
+            .chunker
+            .chunk_iter(&document)?
+            .filter(|chunk| !chunk.content.trim().is_empty())
+            .peekable();
+        let mut num_chunks = 0;
 
-        // Step 2: Generate embeddings for each chunk
-        log::info!("Generating embeddings...");
-        let texts: Vec<String> = chunks.iter().map(|c| c.content.clone()).collect();
-        let embeddings = self.embedding_model.embed_batch(&texts).await?;
+        while chunks.peek().is_some() {
+            let mut batch: Vec<Chunk> = chunks.by_ref().take(INDEX_BATCH_SIZE).collect();
+            let texts: Vec<String> = batch.iter().map(|c| c.content.clone()).collect();
+            let embeddings = self.embedder().embed_documents(&texts).await?;
 
-        // Attach embeddings to chunks
-        for (chunk, embedding) in chunks.iter_mut().zip(embeddings.iter()) {
-            chunk.embedding = Some(embedding.clone());
+            for (chunk, embedding) in batch.iter_mut().zip(embeddings) {
+                chunk.embedding = Some(embedding);
+            }
+
+            num_chunks += batch.len();
+            self.vector_db.add_chunks(batch).await?;
         }
 
-        log::info!("Generated {} embeddings", embeddings.len());
+        log::info!("Successfully indexed document with {} chunks", num_chunks);
 
-        // Step 3: Store chunks in vector database
-        self.vector_db.add_chunks(chunks).await?;
+        Ok(num_chunks)
+    }
 
-        log::info!("Successfully indexed document with {} chunks", num_chunks);
+    /// Index a document from a stream of lines (e.g. text or JSON-lines)
+    ///
+    /// Lines are joined with `\n` as if they formed one document named
+    /// `name` (also used as its ID). Text is chunked and embedded each time
+    /// the buffer fills, so memory stays bounded by the buffer size rather
+    /// than the file size. Offsets always index into the whole text.
+    ///
+    /// With `ChunkingStrategy::FixedSize` the chunks match indexing the
+    /// whole text at once. Other strategies re-split the text carried over
+    /// at each buffer end, so boundaries near it may differ.
+    pub async fn index_stream(
+        &mut self,
+        name: &str,
+        lines: impl Stream<Item = String>,
+    ) -> Result<usize> {
+        log::info!("Indexing stream: {}", name);
+
+        futures::pin_mut!(lines);
+
+        let mut buffer = String::new();
+        // Character offset of `buffer` within the whole document
+        let mut base_offset = 0;
+        let mut num_chunks = 0;
+        let mut first_line = true;
+
+        while let Some(line) = lines.next().await {
+            if !first_line {
+                buffer.push('\n');
+            }
+            first_line = false;
+            buffer.push_str(&line);
+
+            if buffer.len() < self.stream_buffer_bytes {
+                continue;
+            }
+
+            let mut chunks = self.chunk_stream_buffer(name, &buffer, base_offset, num_chunks)?;
+
+            // The last chunk may be cut off by the buffer end: keep its text
+            // and re-chunk it with the next lines
+            if let Some(last) = chunks.pop() {
+                let keep_from = last.metadata.start_char - base_offset;
+                let keep_from_byte = buffer
+                    .char_indices()
+                    .nth(keep_from)
+                    .map_or(buffer.len(), |(i, _)| i);
+                buffer.drain(..keep_from_byte);
+                base_offset += keep_from;
+            }
+
+            num_chunks += chunks.len();
+            self.embed_and_store(chunks).await?;
+        }
+
+        let chunks = self.chunk_stream_buffer(name, &buffer, base_offset, num_chunks)?;
+        num_chunks += chunks.len();
+        self.embed_and_store(chunks).await?;
+
+        log::info!("Successfully indexed stream with {} chunks", num_chunks);
 
         Ok(num_chunks)
     }
 
+    /// Chunk a slice of a streamed document starting at `base_offset`
+    fn chunk_stream_buffer(
+        &self,
+        name: &str,
+        buffer: &str,
+        base_offset: usize,
+        base_index: usize,
+    ) -> Result<Vec<Chunk>> {
+        if buffer.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let document = Document {
+            id: name.to_string(),
+            name: name.to_string(),
+            content: buffer.to_string(),
+            metadata: DocumentMetadata {
+                file_type: "txt".to_string(),
+                size_bytes: buffer.len(),
+                char_count: buffer.chars().count(),
+                uploaded_at: crate::utils::current_timestamp(),
+                num_chunks: 0,
+                extra: Default::default(),
+            },
+        };
+
+        let mut chunks = self.chunker.chunk(&document)?;
+        for chunk in &mut chunks {
+            self.chunker.rebase(chunk, base_offset, base_index);
+        }
+        Ok(chunks)
+    }
+
+    /// Embed chunks and add them to the vector database
+    async fn embed_and_store(&mut self, mut chunks: Vec<Chunk>) -> Result<()> {
+        if chunks.is_empty() {
+            return Ok(());
+        }
+
+        let texts: Vec<String> = chunks.iter().map(|c| c.content.clone()).collect();
+        let embeddings = self.embedder().embed_documents(&texts).await?;
+        for (chunk, embedding) in chunks.iter_mut().zip(embeddings) {
+            chunk.embedding = Some(embedding);
+        }
+
+        self.vector_db.add_chunks(chunks).await
+    }
+
     /// Query the RAG system
     pub async fn query(&self, question: &str, top_k: usize) -> Result<String> {
         log::info!("RAG query: {} (top_k={})", question, top_k);
@@ -71,6 +247,122 @@ impl RagPipeline {
         Ok(context)
     }
 
+    /// Retrieve the top-k chunks for a question using the pipeline's embedding model
+    pub async fn retrieve(&self, question: &str, top_k: usize) -> Result<Vec<SearchResult>> {
+        let query_embedding = self.embedder().embed_query(question).await?;
+        self.vector_db.search(&query_embedding, top_k).await
+    }
+
+    /// Retrieve the top-k chunks for a question among those matching
+    /// `filter`, e.g. to answer using only one document
+    pub async fn query_with_filter(
+        &self,
+        question: &str,
+        top_k: usize,
+        filter: &MetadataFilter,
+    ) -> Result<Vec<SearchResult>> {
+        let query_embedding = self.embedder().embed_query(question).await?;
+        self.vector_db
+            .search_filtered(&query_embedding, top_k, filter)
+            .await
+    }
+
+    /// Answer a question end to end: retrieve context, prompt the model, generate
+    pub async fn answer(
+        &self,
+        model: &PhiModel,
+        question: &str,
+        top_k: usize,
+        gen_config: &GenerationConfig,
+    ) -> Result<RagAnswer> {
+        log::info!("RAG answer: {} (top_k={})", question, top_k);
+
+        let Some(results) = self.retrieve_relevant(question, top_k).await? else {
+            return Ok(RagAnswer {
+                answer: self.fallback_answer.clone(),
+                sources: Vec::new(),
+            });
+        };
+
+        let messages = PromptBuilder::new(question)
+            .with_context(&results)
+            .build_messages();
+        let answer = model.chat(&messages, gen_config).await?;
+
+        Ok(RagAnswer {
+            answer,
+            sources: results.iter().map(RagSource::from).collect(),
+        })
+    }
+
+    /// Like `answer`, but reports each source through `on_source` before
+    /// streaming the generated text through `on_token`
+    ///
+    /// When nothing relevant is retrieved, no sources are reported and the
+    /// fallback answer arrives as a single token.
+    pub async fn answer_stream<S, T>(
+        &self,
+        model: &PhiModel,
+        question: &str,
+        top_k: usize,
+        gen_config: &GenerationConfig,
+        mut on_source: S,
+        mut on_token: T,
+    ) -> Result<RagAnswer>
+    where
+        S: FnMut(&RagSource) -> Result<()>,
+        T: FnMut(String) -> Result<()>,
+    {
+        log::info!("RAG streaming answer: {} (top_k={})", question, top_k);
+
+        let Some(results) = self.retrieve_relevant(question, top_k).await? else {
+            on_token(self.fallback_answer.clone())?;
+            return Ok(RagAnswer {
+                answer: self.fallback_answer.clone(),
+                sources: Vec::new(),
+            });
+        };
+
+        let sources: Vec<RagSource> = results.iter().map(RagSource::from).collect();
+        for source in &sources {
+            on_source(source)?;
+        }
+
+        let messages = PromptBuilder::new(question)
+            .with_context(&results)
+            .build_messages();
+        let prompt = model.render_chat(&messages)?;
+        let output = model.generate_stream(&prompt, gen_config, on_token).await?;
+
+        Ok(RagAnswer {
+            answer: output.text,
+            sources,
+        })
+    }
+
+    /// Retrieve context, or `None` when the best score is below
+    /// `min_relevance`
+    async fn retrieve_relevant(
+        &self,
+        question: &str,
+        top_k: usize,
+    ) -> Result<Option<Vec<SearchResult>>> {
+        let results = self.retrieve(question, top_k).await?;
+
+        if let Some(min_relevance) = self.min_relevance {
+            let top_score = results.first().map(|r| r.score);
+            if top_score.is_none_or(|score| score < min_relevance) {
+                log::info!(
+                    "Top retrieval score {:?} below {}, returning fallback answer",
+                    top_score,
+                    min_relevance
+                );
+                return Ok(None);
+            }
+        }
+        Ok(Some(results))
+    }
+
     /// Delete a document from the RAG system
     pub async fn delete_document(&mut self, document_id: &str) -> Result<usize> {
         self.vector_db.delete_by_document(document_id).await
@@ -101,15 +393,69 @@ impl RagPipeline {
 }
 
 /// RAG system statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RagStats {
     pub total_chunks: usize,
     pub total_documents: usize,
 }
 
+/// Generated answer with the chunks it was based on
+#[derive(Debug, Clone, Serialize)]
+pub struct RagAnswer {
+    pub answer: String,
+    pub sources: Vec<RagSource>,
+}
+
+/// Retrieved chunk reference returned with an answer
+#[derive(Debug, Clone, Serialize)]
+pub struct RagSource {
+    pub chunk_id: String,
+    pub document_id: String,
+    pub document_name: String,
+    pub content: String,
+    pub score: f32,
+    /// Whether `content` is a shortened preview (see `RagSource::snippet`)
+    pub truncated: bool,
+}
+
+impl RagSource {
+    /// Source whose content is cut to a preview of at most `max_chars`
+    /// characters (see `Chunk::preview`)
+    pub fn snippet(result: &SearchResult, max_chars: usize) -> Self {
+        let content = result.chunk.preview(max_chars);
+        Self {
+            truncated: content != result.chunk.content,
+            content,
+            ..Self::without_content(result)
+        }
+    }
+
+    /// Source for `result` with empty `content`
+    fn without_content(result: &SearchResult) -> Self {
+        Self {
+            chunk_id: result.chunk.id.clone(),
+            document_id: result.chunk.metadata.document_id.clone(),
+            document_name: result.chunk.metadata.document_name.clone(),
+            content: String::new(),
+            score: result.score,
+            truncated: false,
+        }
+    }
+}
+
+impl From<&SearchResult> for RagSource {
+    fn from(result: &SearchResult) -> Self {
+        Self {
+            content: result.chunk.content.clone(),
+            ..Self::without_content(result)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rag::chunking::document;
     use crate::rag::DocumentMetadata;
 
     #[tokio::test]
@@ -127,12 +473,323 @@ mod tests {
             metadata: DocumentMetadata {
                 file_type: "txt".to_string(),
                 size_bytes: 43,
+                char_count: 43,
                 uploaded_at: "2025-01-01".to_string(),
                 num_chunks: 0,
+                extra: Default::default(),
             },
         };
 
         let stats = pipeline.stats();
         assert_eq!(stats.total_chunks, 0);
     }
+
+    #[tokio::test]
+    async fn test_answer_with_mock_backend() {
+        use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+        use crate::llm::{MockBackend, ModelConfig};
+
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::FixedSize { size: 20, overlap: 0 },
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        );
+
+        let document = document("capitals", "Paris is the capital of France.");
+        pipeline.index_document(document).await.unwrap();
+
+        // Vocab: <unk>=0, </s>=1, Paris=2; the backend always predicts "Paris"
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["Paris"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 5.0])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 1,
+            temperature: 0.0,
+            ..GenerationConfig::default()
+        };
+
+        let answer = pipeline
+            .answer(&model, "What is the capital of France?", 2, &config)
+            .await
+            .unwrap();
+        let retrieved = pipeline
+            .retrieve("What is the capital of France?", 2)
+            .await
+            .unwrap();
+
+        assert!(answer.answer.contains("Paris"));
+        let source_ids: Vec<&str> = answer.sources.iter().map(|s| s.chunk_id.as_str()).collect();
+        let retrieved_ids: Vec<&str> = retrieved.iter().map(|r| r.chunk.id.as_str()).collect();
+        assert_eq!(source_ids, retrieved_ids);
+        assert_eq!(source_ids.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_answer_falls_back_below_min_relevance() {
+        use crate::llm::{InferenceBackend, ModelConfig, TokenizerWrapper};
+        use crate::rag::{Chunk, ChunkMetadata};
+
+        struct UnreachableBackend;
+
+        impl InferenceBackend for UnreachableBackend {
+            fn forward(&self, _tokens: &[u32]) -> Result<Vec<f32>> {
+                panic!("model should not be invoked");
+            }
+
+            fn vocab_size(&self) -> usize {
+                0
+            }
+        }
+
+        let embedding_model = EmbeddingModel::new("test".to_string());
+        let query_embedding = embedding_model.embed("off topic").await.unwrap();
+
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::default(),
+            embedding_model,
+            VectorDatabase::new(),
+        )
+        .with_min_relevance(0.5)
+        .with_fallback_answer("No idea.");
+
+        // Points away from every query embedding
+        pipeline
+            .vector_db_mut()
+            .add_chunk(Chunk {
+                id: "unrelated_0".to_string(),
+                content: "Unrelated text".to_string(),
+                embedding: Some(query_embedding.iter().map(|v| -v).collect()),
+                metadata: ChunkMetadata {
+                    document_id: "unrelated".to_string(),
+                    document_name: "Unrelated".to_string(),
+                    chunk_index: 0,
+                    start_char: 0,
+                    end_char: 14,
+                    token_count: None,
+                    created_at: "2025-01-01".to_string(),
+                    parent_id: None,
+                    extra: Default::default(),
+                },
+            })
+            .await
+            .unwrap();
+
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            TokenizerWrapper::new(String::new()),
+            Box::new(UnreachableBackend),
+        );
+
+        let answer = pipeline
+            .answer(&model, "off topic", 3, &GenerationConfig::default())
+            .await
+            .unwrap();
+
+        assert_eq!(answer.answer, "No idea.");
+        assert!(answer.sources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_index_stream_matches_whole_document() {
+        let lines: Vec<String> = (0..40)
+            .map(|i| format!("{{\"id\": {}, \"text\": \"record number {}\"}}", i, i))
+            .collect();
+        let strategy = ChunkingStrategy::FixedSize { size: 50, overlap: 10 };
+
+        let mut whole = RagPipeline::new(
+            strategy,
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        );
+        let content = lines.join("\n");
+        whole
+            .index_document(document("records", &content))
+            .await
+            .unwrap();
+
+        let mut streamed = RagPipeline::new(
+            strategy,
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        )
+        .with_stream_buffer_bytes(200);
+        let count = streamed
+            .index_stream("records", futures::stream::iter(lines))
+            .await
+            .unwrap();
+
+        assert_eq!(count, whole.vector_db().count());
+        assert_eq!(streamed.vector_db().count(), count);
+
+        // All chunks, in index order
+        let query = [1.0; 384];
+        let expected = whole.vector_db().search(&query, count).await.unwrap();
+        let actual = streamed.vector_db().search(&query, count).await.unwrap();
+        let key = |results: &[SearchResult]| {
+            let mut keys: Vec<(usize, String, usize, usize, String)> = results
+                .iter()
+                .map(|r| {
+                    let m = &r.chunk.metadata;
+                    let content = r.chunk.content.clone();
+                    (m.chunk_index, r.chunk.id.clone(), m.start_char, m.end_char, content)
+                })
+                .collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(key(&actual), key(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_with_llm_embeddings() {
+        use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+        use crate::llm::{MockBackend, ModelConfig};
+
+        let words = ["cats", "purr", "rockets", "launch", "do", "what"];
+        let model = Rc::new(PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&words),
+            Box::new(MockBackend::new(vec![0.0; words.len() + 2])),
+        ));
+
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::FixedSize { size: 100, overlap: 0 },
+            EmbeddingModel::new("unused".to_string()),
+            VectorDatabase::new(),
+        )
+        .with_embedder(model.clone());
+
+        for (id, content) in [("pets", "cats purr"), ("space", "rockets launch")] {
+            pipeline
+                .index_document(document(id, content))
+                .await
+                .unwrap();
+        }
+
+        let results = pipeline.retrieve("what do rockets do", 1).await.unwrap();
+        assert_eq!(results[0].chunk.metadata.document_id, "space");
+        assert_eq!(
+            results[0].chunk.embedding.as_ref().unwrap().len(),
+            crate::llm::MOCK_HIDDEN_SIZE
+        );
+
+        let results = pipeline.retrieve("cats", 1).await.unwrap();
+        assert_eq!(results[0].chunk.metadata.document_id, "pets");
+
+        // Text without tokens has nothing to pool
+        assert!(model.embed("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_answer_stream_sends_sources_first() {
+        use crate::llm::tokenizer_wrapper::word_level_tokenizer;
+        use crate::llm::{MockBackend, ModelConfig};
+
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::FixedSize { size: 20, overlap: 0 },
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        );
+        let content = "Paris is the capital of France.";
+        pipeline
+            .index_document(document("capitals", content))
+            .await
+            .unwrap();
+
+        let model = PhiModel::with_backend(
+            ModelConfig::default(),
+            word_level_tokenizer(&["Paris", "France"]),
+            Box::new(MockBackend::new(vec![0.0, 0.0, 5.0, 4.0])),
+        );
+        let config = GenerationConfig {
+            max_tokens: 3,
+            temperature: 0.0,
+            repetition_penalty: 2.0,
+            ..GenerationConfig::default()
+        };
+
+        let events = std::cell::RefCell::new(Vec::new());
+        let answer = pipeline
+            .answer_stream(
+                &model,
+                "What is the capital of France?",
+                2,
+                &config,
+                |source| {
+                    events.borrow_mut().push(format!("source:{}", source.chunk_id));
+                    Ok(())
+                },
+                |token| {
+                    events.borrow_mut().push(format!("token:{}", token));
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
+
+        let events = events.into_inner();
+        let first_token = events.iter().position(|e| e.starts_with("token:")).unwrap();
+        assert_eq!(first_token, answer.sources.len());
+        assert!(events[..first_token].iter().all(|e| e.starts_with("source:")));
+
+        let streamed: String = events[first_token..]
+            .iter()
+            .map(|e| e.trim_start_matches("token:"))
+            .collect();
+        assert_eq!(streamed, answer.answer);
+        assert!(!answer.answer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_with_document_filter() {
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::FixedSize { size: 20, overlap: 0 },
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        );
+        for (id, content) in [
+            ("france", "Paris is the capital of France."),
+            ("spain", "Madrid is the capital of Spain."),
+        ] {
+            pipeline
+                .index_document(document(id, content))
+                .await
+                .unwrap();
+        }
+
+        let question = "What is the capital?";
+        assert_eq!(pipeline.retrieve(question, 10).await.unwrap().len(), 4);
+
+        let filter = MetadataFilter::documents(&["spain"]);
+        let results = pipeline.query_with_filter(question, 10, &filter).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.chunk.metadata.document_id == "spain"));
+    }
+
+    #[tokio::test]
+    async fn test_index_skips_empty_content() {
+        let mut pipeline = RagPipeline::new(
+            ChunkingStrategy::FixedSize { size: 20, overlap: 0 },
+            EmbeddingModel::new("test".to_string()),
+            VectorDatabase::new(),
+        );
+        let empty = pipeline.index_document(document("empty", "")).await.unwrap();
+        let blank = pipeline
+            .index_document(document("blank", &" \n\t ".repeat(30)))
+            .await
+            .unwrap();
+        assert_eq!((empty, blank), (0, 0));
+        assert_eq!(pipeline.vector_db().count(), 0);
+
+        let sparse = format!("First words.{}Last words.", " ".repeat(60));
+        let indexed = pipeline.index_document(document("sparse", &sparse)).await.unwrap();
+        assert_eq!(indexed, pipeline.vector_db().count());
+        let contents: Vec<&str> =
+            pipeline.vector_db().iter_chunks().map(|c| c.content.trim()).collect();
+        assert!(contents.iter().all(|c| !c.is_empty()));
+        assert!(contents.concat().contains("First words."));
+        assert!(contents.concat().contains("Last words."));
+    }
 }
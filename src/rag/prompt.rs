@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use super::SearchResult;
+use crate::llm::prompt_template::render_with;
+use crate::llm::{ChatMessage, MissingVariable};
+
+/// Default instruction for answering from retrieved context
+pub const DEFAULT_RAG_SYSTEM_PROMPT: &str = "Answer the question using only the provided context. \
+If the context does not contain the answer, say that you don't know.";
+
+/// Builds RAG prompts from a question and retrieved chunks
+pub struct PromptBuilder {
+    question: String,
+    system_prompt: String,
+    context: Vec<SearchResult>,
+}
+
+impl PromptBuilder {
+    /// Create a prompt builder for a question
+    pub fn new(question: &str) -> Self {
+        Self {
+            question: question.to_string(),
+            system_prompt: DEFAULT_RAG_SYSTEM_PROMPT.to_string(),
+            context: Vec::new(),
+        }
+    }
+
+    /// Override the system instruction
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = system_prompt.to_string();
+        self
+    }
+
+    /// Use `template` as the system instruction, filling its `{{name}}`
+    /// placeholders from `vars`
+    pub fn with_system_template(
+        mut self,
+        template: &str,
+        vars: &HashMap<String, String>,
+        missing: MissingVariable,
+    ) -> Result<Self> {
+        self.system_prompt = render_with(template, vars, missing)?;
+        Ok(self)
+    }
+
+    /// Add retrieved chunks as context
+    pub fn with_context(mut self, results: &[SearchResult]) -> Self {
+        self.context.extend_from_slice(results);
+        self
+    }
+
+    /// Format the context as numbered sources
+    pub fn build_context(&self) -> String {
+        let mut context = String::new();
+        for (i, result) in self.context.iter().enumerate() {
+            context.push_str(&format!(
+                "[{}] {}\n{}\n\n",
+                i + 1,
+                result.chunk.metadata.document_name,
+                result.chunk.content
+            ));
+        }
+        context
+    }
+
+    /// Build chat messages (system instruction + user turn with context)
+    pub fn build_messages(&self) -> Vec<ChatMessage> {
+        let user = if self.context.is_empty() {
+            self.question.clone()
+        } else {
+            format!(
+                "Context:\n{}Question: {}",
+                self.build_context(),
+                self.question
+            )
+        };
+
+        vec![
+            ChatMessage::system(self.system_prompt.clone()),
+            ChatMessage::user(user),
+        ]
+    }
+}
@@ -0,0 +1,164 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use super::{MetadataFilter, SearchResult};
+use crate::utils::hash::fnv1a_64;
+
+/// Default number of queries kept by a `QueryCache`
+pub const DEFAULT_QUERY_CACHE_ENTRIES: usize = 64;
+
+/// `(query hash, top_k, filter hash)`; the filter hash is 0 for no filter
+pub(crate) type QueryKey = (u64, usize, u64);
+
+struct CachedQuery {
+    results: Vec<SearchResult>,
+    /// `VectorDatabase::version` the results were computed against
+    version: u64,
+    stored_at_ms: f64,
+}
+
+/// Caches retrieval results of repeated queries
+///
+/// Entries expire after `ttl_ms` and are ignored once the vector database
+/// has changed since they were stored, so a hit never needs the query to
+/// be embedded.
+pub struct QueryCache {
+    ttl_ms: f64,
+    capacity: usize,
+    entries: RefCell<HashMap<QueryKey, CachedQuery>>,
+    hits: Cell<usize>,
+}
+
+impl QueryCache {
+    /// Create a cache whose entries live for `ttl_ms` milliseconds
+    pub fn new(ttl_ms: f64) -> Self {
+        Self {
+            ttl_ms,
+            capacity: DEFAULT_QUERY_CACHE_ENTRIES,
+            entries: RefCell::new(HashMap::new()),
+            hits: Cell::new(0),
+        }
+    }
+
+    /// Keep at most `capacity` queries, evicting the oldest first
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Cache key of a retrieval
+    pub(crate) fn key(query: &str, top_k: usize, filter: Option<&MetadataFilter>) -> QueryKey {
+        (fnv1a_64(query.as_bytes()), top_k, filter.map_or(0, filter_hash))
+    }
+
+    /// Cached results for `key`, if fresh at `now_ms` and computed against
+    /// database `version`
+    pub(crate) fn get(
+        &self,
+        key: &QueryKey,
+        version: u64,
+        now_ms: f64,
+    ) -> Option<Vec<SearchResult>> {
+        let entries = self.entries.borrow();
+        let entry = entries.get(key)?;
+        if entry.version != version || now_ms - entry.stored_at_ms >= self.ttl_ms {
+            return None;
+        }
+        self.hits.set(self.hits.get() + 1);
+        Some(entry.results.clone())
+    }
+
+    pub(crate) fn insert(
+        &self,
+        key: QueryKey,
+        results: &[SearchResult],
+        version: u64,
+        now_ms: f64,
+    ) {
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|_, entry| {
+            entry.version == version && now_ms - entry.stored_at_ms < self.ttl_ms
+        });
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by(|a, b| a.1.stored_at_ms.total_cmp(&b.1.stored_at_ms))
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CachedQuery {
+                results: results.to_vec(),
+                version,
+                stored_at_ms: now_ms,
+            },
+        );
+    }
+
+    /// Number of retrievals served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// Number of cached queries (including stale ones not yet evicted)
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Drop all cached queries
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
+
+/// Hash of a filter that does not depend on map iteration order
+fn filter_hash(filter: &MetadataFilter) -> u64 {
+    let mut document_ids: Vec<&str> = filter.document_ids.iter().map(String::as_str).collect();
+    document_ids.sort_unstable();
+    let mut extra: Vec<(&String, &String)> = filter.extra.iter().collect();
+    extra.sort_unstable();
+
+    let mut text = document_ids.join("\u{1f}");
+    for (key, value) in extra {
+        text.push_str(&format!("\u{1e}{}\u{1f}{}", key, value));
+    }
+    // Keep 0 for "no filter"
+    fnv1a_64(text.as_bytes()).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_cache_expiry_and_version() {
+        let cache = QueryCache::new(1000.0).with_capacity(2);
+        let key = QueryCache::key("query", 3, None);
+        cache.insert(key, &[], 1, 0.0);
+
+        assert!(cache.get(&key, 1, 999.0).is_some());
+        assert!(cache.get(&key, 1, 1000.0).is_none());
+        assert!(cache.get(&key, 2, 10.0).is_none());
+        assert!(cache.get(&QueryCache::key("query", 4, None), 1, 10.0).is_none());
+        assert_eq!(cache.hits(), 1);
+
+        // Filters hash the same regardless of insertion order
+        let a = MetadataFilter::default().with_extra("x", "1").with_extra("y", "2");
+        let b = MetadataFilter::default().with_extra("y", "2").with_extra("x", "1");
+        assert_eq!(QueryCache::key("q", 1, Some(&a)), QueryCache::key("q", 1, Some(&b)));
+        assert_ne!(QueryCache::key("q", 1, Some(&a)), QueryCache::key("q", 1, None));
+
+        cache.insert(QueryCache::key("a", 1, None), &[], 1, 1.0);
+        cache.insert(QueryCache::key("b", 1, None), &[], 1, 2.0);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key, 1, 3.0).is_none());
+    }
+}
@@ -1,10 +1,76 @@
 use anyhow::Result;
-use super::{EmbeddingModel, VectorDatabase, SearchResult};
+use std::collections::{HashMap, HashSet};
+use super::{Chunk, EmbeddingModel, MetadataFilter, VectorDatabase, SearchResult};
+use super::embeddings::cosine_similarity;
+use super::chunking::{parent_span, parse_span_id, span_id};
+use super::query_cache::{QueryCache, QueryKey};
+use crate::error::LlmError;
+use crate::utils::text::{split_sentences, split_words, CharOffsets};
+use crate::utils::time::{days_between, parse_timestamp};
+use crate::utils::now_ms;
+
+/// How chunk scores combine into a document score
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreAgg {
+    /// Best chunk score (favours one highly relevant passage)
+    Max,
+    /// Mean chunk score (favours consistently relevant documents)
+    Mean,
+}
+
+/// Document ranked by its aggregated chunk scores
+#[derive(Debug, Clone)]
+pub struct DocumentResult {
+    pub document_id: String,
+    pub document_name: String,
+    pub score: f32,
+    /// Highest-scoring chunk of the document
+    pub best_chunk: SearchResult,
+    /// Number of chunks that contributed to the score
+    pub num_chunks: usize,
+}
+
+/// Search result with the components of its score
+///
+/// `final_score = ((1 - w) * similarity + w * lexical_score) * boost`, where
+/// `w` is the retriever's lexical weight (0 when hybrid search is off).
+#[derive(Debug, Clone)]
+pub struct ScoredExplanation {
+    /// Result carrying `final_score` as its score
+    pub result: SearchResult,
+    /// Raw cosine similarity to the query
+    pub similarity: f32,
+    /// Per-document boost multiplier (1.0 when none applies)
+    pub boost: f32,
+    /// Fraction of query terms found in the chunk, when hybrid search is on
+    pub lexical_score: Option<f32>,
+    pub final_score: f32,
+}
+
+/// Retrieved chunk with the wider window of text around it
+#[derive(Debug, Clone)]
+pub struct ParentHit {
+    /// Matched chunk; its `parent_id` is the ID of `parent`
+    pub hit: SearchResult,
+    /// Window of the document around the hit
+    pub parent: Chunk,
+}
 
 /// Retriever for finding relevant chunks
 pub struct Retriever {
     vector_db: VectorDatabase,
     embedding_model: EmbeddingModel,
+    /// Weight of the lexical score in hybrid search (None = vector only)
+    lexical_weight: Option<f32>,
+    /// Score multipliers by document ID
+    document_boosts: HashMap<String, f32>,
+    /// Cosine similarity above which `retrieve_context` treats two chunks
+    /// as the same passage (None keeps every chunk)
+    context_dedup_threshold: Option<f32>,
+    /// Most chunks `retrieve` returns from any one document (None = no cap)
+    max_per_document: Option<usize>,
+    /// Results of recent `retrieve` and `retrieve_filtered` calls
+    query_cache: Option<QueryCache>,
 }
 
 impl Retriever {
@@ -13,27 +79,459 @@ impl Retriever {
         Self {
             vector_db,
             embedding_model,
+            lexical_weight: None,
+            document_boosts: HashMap::new(),
+            context_dedup_threshold: None,
+            max_per_document: None,
+            query_cache: None,
         }
     }
 
+    /// Enable hybrid search, blending in query-term overlap with `weight`
+    /// (clamped to 0.0..=1.0)
+    pub fn with_lexical_weight(mut self, weight: f32) -> Self {
+        self.lexical_weight = Some(weight.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Multiply the scores of chunks from the given documents
+    pub fn with_document_boosts(mut self, boosts: HashMap<String, f32>) -> Self {
+        self.document_boosts = boosts;
+        self
+    }
+
+    /// Drop near-identical chunks (such as boilerplate copied between
+    /// documents) from `retrieve_context`, keeping the best-scored copy
+    pub fn with_context_dedup(mut self, threshold: f32) -> Self {
+        self.context_dedup_threshold = Some(threshold);
+        self
+    }
+
+    /// Let `retrieve` return at most `max` chunks per document, filling
+    /// `top_k` from other documents instead
+    pub fn with_max_per_document(mut self, max: usize) -> Self {
+        self.max_per_document = Some(max);
+        self
+    }
+
+    /// Serve repeated `retrieve` and `retrieve_filtered` calls from `cache`
+    /// until its TTL passes or the vector database changes
+    pub fn with_query_cache(mut self, cache: QueryCache) -> Self {
+        self.query_cache = Some(cache);
+        self
+    }
+
+    /// Get the query cache, if enabled
+    pub fn query_cache(&self) -> Option<&QueryCache> {
+        self.query_cache.as_ref()
+    }
+
     /// Retrieve top-k relevant chunks for a query
     pub async fn retrieve(&self, query: &str, top_k: usize) -> Result<Vec<SearchResult>> {
         log::info!("Retrieving top-{} chunks for query: {}", top_k, query);
 
-        // Generate embedding for query
-        let query_embedding = self.embedding_model.embed(query).await?;
+        let key = QueryCache::key(query, top_k, None);
+        if let Some(results) = self.cached(&key) {
+            return Ok(results);
+        }
+
+        let query_embedding = self.embedding_model.embed_query(query).await?;
+        let results = self.retrieve_embedded(query, &query_embedding, top_k).await?;
+
+        self.cache(key, &results);
+        Ok(results)
+    }
+
+    /// Retrieve top-k chunks for each of several independent queries
+    ///
+    /// Returns one result list per query, in order, equal to what
+    /// `retrieve` gives. Queries not served by the query cache are embedded
+    /// together in a single `embed_batch` call.
+    pub async fn retrieve_batch(
+        &self,
+        queries: &[String],
+        top_k: usize,
+    ) -> Result<Vec<Vec<SearchResult>>> {
+        log::info!("Retrieving top-{} chunks for {} queries", top_k, queries.len());
+
+        let keys: Vec<QueryKey> = queries
+            .iter()
+            .map(|query| QueryCache::key(query, top_k, None))
+            .collect();
+        let mut results: Vec<Option<Vec<SearchResult>>> =
+            keys.iter().map(|key| self.cached(key)).collect();
+
+        let misses: Vec<usize> = (0..queries.len()).filter(|&i| results[i].is_none()).collect();
+        if misses.is_empty() {
+            return Ok(results.into_iter().flatten().collect());
+        }
+        let texts: Vec<String> = misses.iter().map(|&i| queries[i].clone()).collect();
+        let embeddings = self.embedding_model.embed_queries(&texts).await?;
+        if embeddings.len() != misses.len() {
+            anyhow::bail!(
+                "Expected {} query embeddings, got {}",
+                misses.len(),
+                embeddings.len()
+            );
+        }
+
+        for (i, query_embedding) in misses.into_iter().zip(embeddings) {
+            let found = self.retrieve_embedded(&queries[i], &query_embedding, top_k).await?;
+            self.cache(keys[i], &found);
+            results[i] = Some(found);
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Fresh cached results for `key`, if caching is enabled
+    fn cached(&self, key: &QueryKey) -> Option<Vec<SearchResult>> {
+        let results = self
+            .query_cache
+            .as_ref()?
+            .get(key, self.vector_db.version(), now_ms())?;
+        log::debug!("Query cache hit ({} results)", results.len());
+        Some(results)
+    }
+
+    fn cache(&self, key: QueryKey, results: &[SearchResult]) {
+        if let Some(cache) = &self.query_cache {
+            cache.insert(key, results, self.vector_db.version(), now_ms());
+        }
+    }
+
+    /// Keep the best results (in order) with at most `max` per document
+    pub fn cap_per_document(
+        results: Vec<SearchResult>,
+        max: usize,
+        top_k: usize,
+    ) -> Vec<SearchResult> {
+        let mut taken: HashMap<String, usize> = HashMap::new();
+        results
+            .into_iter()
+            .filter(|result| {
+                let count = taken.entry(result.chunk.metadata.document_id.clone()).or_insert(0);
+                *count += 1;
+                *count <= max
+            })
+            .take(top_k)
+            .collect()
+    }
+
+    /// `retrieve` for an already embedded query, bypassing the cache
+    async fn retrieve_embedded(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        match self.max_per_document {
+            Some(max) => {
+                let results = self
+                    .retrieve_uncapped(query, query_embedding, self.vector_db.count())
+                    .await?;
+                Ok(Self::cap_per_document(results, max, top_k))
+            }
+            None => self.retrieve_uncapped(query, query_embedding, top_k).await,
+        }
+    }
+
+    /// `retrieve_embedded` without the per-document cap
+    async fn retrieve_uncapped(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        if self.lexical_weight.is_some() {
+            let explained = self
+                .rescore_embedded(query, query_embedding, top_k, self.vector_db.count(), None)
+                .await?;
+            return Ok(explained.into_iter().map(|e| e.result).collect());
+        }
 
         // Search vector database
-        let results = self.vector_db.search(&query_embedding, top_k).await?;
+        let results = if self.document_boosts.is_empty() {
+            self.vector_db.search(query_embedding, top_k).await?
+        } else {
+            self.vector_db
+                .search_boosted(query_embedding, top_k, &self.document_boosts)
+                .await?
+        };
 
         log::info!("Retrieved {} results", results.len());
 
         Ok(results)
     }
 
+    /// Retrieve top-k chunks among those matching `filter` (e.g. "only
+    /// document X" or "only PDFs"), scored like `retrieve`
+    pub async fn retrieve_filtered(
+        &self,
+        query: &str,
+        top_k: usize,
+        filter: &MetadataFilter,
+    ) -> Result<Vec<SearchResult>> {
+        log::info!("Retrieving top-{} filtered chunks for query: {}", top_k, query);
+
+        let key = QueryCache::key(query, top_k, Some(filter));
+        if let Some(results) = self.cached(&key) {
+            return Ok(results);
+        }
+
+        let results = if self.lexical_weight.is_some() || !self.document_boosts.is_empty() {
+            let explained = self
+                .rescore(query, top_k, self.vector_db.count(), Some(filter))
+                .await?;
+            explained.into_iter().map(|e| e.result).collect()
+        } else {
+            let query_embedding = self.embedding_model.embed_query(query).await?;
+            self.vector_db
+                .search_filtered(&query_embedding, top_k, filter)
+                .await?
+        };
+
+        self.cache(key, &results);
+        Ok(results)
+    }
+
+    /// Two-stage retrieval: fetch `fetch_k` candidates by vector
+    /// similarity, then rescore them (lexical weight, boosts) and return
+    /// the best `top_k`
+    ///
+    /// Over-fetching lets the second stage promote chunks that plain vector
+    /// search would cut off. `fetch_k` below `top_k` is raised to `top_k`.
+    pub async fn retrieve_with_fetch_k(
+        &self,
+        query: &str,
+        top_k: usize,
+        fetch_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        log::info!(
+            "Retrieving top-{} of {} candidates for query: {}",
+            top_k,
+            fetch_k.max(top_k),
+            query
+        );
+
+        let explained = self.rescore(query, top_k, fetch_k.max(top_k), None).await?;
+        Ok(explained.into_iter().map(|e| e.result).collect())
+    }
+
+    /// Retrieve top-k chunks with a breakdown of how each was scored
+    pub async fn retrieve_explained(
+        &self,
+        query: &str,
+        top_k: usize,
+    ) -> Result<Vec<ScoredExplanation>> {
+        self.rescore(query, top_k, self.vector_db.count(), None).await
+    }
+
+    /// Rescore the `fetch_k` nearest chunks (matching `filter`, if given)
+    /// and keep the best `top_k`
+    async fn rescore(
+        &self,
+        query: &str,
+        top_k: usize,
+        fetch_k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<ScoredExplanation>> {
+        let query_embedding = self.embedding_model.embed_query(query).await?;
+        self.rescore_embedded(query, &query_embedding, top_k, fetch_k, filter)
+            .await
+    }
+
+    /// `rescore` for an already embedded query
+    async fn rescore_embedded(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        top_k: usize,
+        fetch_k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<ScoredExplanation>> {
+        let candidates = match filter {
+            Some(filter) => {
+                self.vector_db
+                    .search_filtered(query_embedding, fetch_k, filter)
+                    .await?
+            }
+            None => self.vector_db.search(query_embedding, fetch_k).await?,
+        };
+
+        let query_terms = terms(query);
+        let mut explained: Vec<ScoredExplanation> = candidates
+            .into_iter()
+            .map(|result| self.explain(result, &query_terms))
+            .collect();
+
+        explained.sort_by(|a, b| {
+            b.final_score
+                .partial_cmp(&a.final_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        explained.truncate(top_k);
+        Ok(explained)
+    }
+
+    /// Score an unboosted vector search result
+    fn explain(
+        &self,
+        mut result: SearchResult,
+        query_terms: &HashSet<String>,
+    ) -> ScoredExplanation {
+        let similarity = result.score;
+        let boost = self
+            .document_boosts
+            .get(&result.chunk.metadata.document_id)
+            .copied()
+            .unwrap_or(1.0);
+        let lexical_score = self
+            .lexical_weight
+            .map(|_| lexical_overlap(query_terms, &result.chunk.content));
+
+        let weight = self.lexical_weight.unwrap_or(0.0);
+        let final_score =
+            ((1.0 - weight) * similarity + weight * lexical_score.unwrap_or(0.0)) * boost;
+        result.score = final_score;
+
+        ScoredExplanation {
+            result,
+            similarity,
+            boost,
+            lexical_score,
+            final_score,
+        }
+    }
+
+    /// Small-to-big retrieval: search small chunks, return wider context
+    ///
+    /// Each hit is paired with its parent span: the one stored in its
+    /// `parent_id` at chunking time (`DocumentChunker::with_parent_chars`),
+    /// or else a window of about `parent_chars` characters centred on it.
+    /// The parent text is rebuilt from the document's stored chunks by
+    /// their character offsets (text no stored chunk covers reads as
+    /// spaces).
+    pub async fn retrieve_with_parents(
+        &self,
+        query: &str,
+        top_k: usize,
+        parent_chars: usize,
+    ) -> Result<Vec<ParentHit>> {
+        let results = self.retrieve(query, top_k).await?;
+
+        let mut documents: HashMap<String, Vec<char>> = HashMap::new();
+        Ok(results
+            .into_iter()
+            .map(|mut hit| {
+                let document_id = hit.chunk.metadata.document_id.clone();
+                let text = documents
+                    .entry(document_id.clone())
+                    .or_insert_with(|| document_text(self.vector_db.document_chunks(&document_id)));
+
+                let parent = parent_window(&hit.chunk, text, parent_chars);
+                hit.chunk.metadata.parent_id = Some(parent.id.clone());
+                ParentHit { hit, parent }
+            })
+            .collect())
+    }
+
+    /// Retrieve top-k chunks, each with `window` neighbouring sentences on
+    /// either side
+    ///
+    /// Meant for stores indexed with `ChunkingStrategy::Sentence`: matching
+    /// stays precise while the returned `parent` is readable context. The
+    /// document text is rebuilt from its stored chunks as in
+    /// `retrieve_with_parents`.
+    pub async fn retrieve_sentence_window(
+        &self,
+        query: &str,
+        top_k: usize,
+        window: usize,
+    ) -> Result<Vec<ParentHit>> {
+        let results = self.retrieve(query, top_k).await?;
+
+        // Rebuilt text and sentence spans by document ID
+        let mut documents: HashMap<String, (Vec<char>, SentenceSpans)> = HashMap::new();
+        Ok(results
+            .into_iter()
+            .map(|mut hit| {
+                let document_id = hit.chunk.metadata.document_id.clone();
+                let (text, sentences) = documents.entry(document_id.clone()).or_insert_with(|| {
+                    let text = document_text(self.vector_db.document_chunks(&document_id));
+                    let sentences = sentence_spans(&text);
+                    (text, sentences)
+                });
+
+                let parent = sentence_window(&hit.chunk, text, sentences, window);
+                hit.chunk.metadata.parent_id = Some(parent.id.clone());
+                ParentHit { hit, parent }
+            })
+            .collect())
+    }
+
+    /// Retrieve top-k chunks, favouring recent ones
+    ///
+    /// Each similarity is multiplied by `exp(-lambda * age_days)` with
+    /// `lambda = ln 2 / half_life_days`, so a chunk `half_life_days` old
+    /// keeps half its score. Chunks whose `created_at` does not parse are
+    /// not decayed.
+    pub async fn retrieve_time_decayed(
+        &self,
+        query: &str,
+        top_k: usize,
+        half_life_days: f64,
+    ) -> Result<Vec<SearchResult>> {
+        self.retrieve_decayed_at(query, top_k, half_life_days, now_ms())
+            .await
+    }
+
+    /// `retrieve_time_decayed` with ages measured at `now_ms`
+    async fn retrieve_decayed_at(
+        &self,
+        query: &str,
+        top_k: usize,
+        half_life_days: f64,
+        now_ms: f64,
+    ) -> Result<Vec<SearchResult>> {
+        if half_life_days.is_nan() || half_life_days <= 0.0 {
+            return Err(LlmError::Config("Half-life must be positive".to_string()).into());
+        }
+        let lambda = std::f64::consts::LN_2 / half_life_days;
+
+        let query_embedding = self.embedding_model.embed_query(query).await?;
+        let mut results = self
+            .vector_db
+            .search(&query_embedding, self.vector_db.count())
+            .await?;
+
+        for result in &mut results {
+            if let Some(created_ms) = parse_timestamp(&result.chunk.metadata.created_at) {
+                let age_days = days_between(created_ms, now_ms).max(0.0);
+                result.score *= (-lambda * age_days).exp() as f32;
+            }
+        }
+
+        results.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        results.truncate(top_k);
+        Ok(results)
+    }
+
     /// Retrieve and format context for LLM
     pub async fn retrieve_context(&self, query: &str, top_k: usize) -> Result<String> {
-        let results = self.retrieve(query, top_k).await?;
+        let results = match self.context_dedup_threshold {
+            Some(threshold) => {
+                // Over-fetch so dropped duplicates can be replaced
+                let results = self.retrieve(query, top_k * 2).await?;
+                let mut unique = Self::dedup_similar(results, threshold);
+                unique.truncate(top_k);
+                unique
+            }
+            None => self.retrieve(query, top_k).await?,
+        };
 
         // Format results as context
         let mut context = String::new();
@@ -51,6 +549,150 @@ impl Retriever {
         Ok(context)
     }
 
+    /// Retrieve the top-k documents, scoring each from all of its chunks
+    pub async fn retrieve_documents(
+        &self,
+        query: &str,
+        top_k: usize,
+        agg: ScoreAgg,
+    ) -> Result<Vec<DocumentResult>> {
+        let query_embedding = self.embedding_model.embed_query(query).await?;
+        let results = self
+            .vector_db
+            .search(&query_embedding, self.vector_db.count())
+            .await?;
+
+        Ok(Self::aggregate_documents(results, top_k, agg))
+    }
+
+    /// Group chunk results by document and rank documents by `agg`
+    pub fn aggregate_documents(
+        results: Vec<SearchResult>,
+        top_k: usize,
+        agg: ScoreAgg,
+    ) -> Vec<DocumentResult> {
+        // (sum of scores, best result, count) per document
+        let mut groups: HashMap<String, (f32, SearchResult, usize)> = HashMap::new();
+        for result in results {
+            match groups.get_mut(&result.chunk.metadata.document_id) {
+                Some((sum, best, count)) => {
+                    *sum += result.score;
+                    *count += 1;
+                    if result.score > best.score {
+                        *best = result;
+                    }
+                }
+                None => {
+                    groups.insert(
+                        result.chunk.metadata.document_id.clone(),
+                        (result.score, result, 1),
+                    );
+                }
+            }
+        }
+
+        let mut documents: Vec<DocumentResult> = groups
+            .into_iter()
+            .map(|(document_id, (sum, best, count))| DocumentResult {
+                document_id,
+                document_name: best.chunk.metadata.document_name.clone(),
+                score: match agg {
+                    ScoreAgg::Max => best.score,
+                    ScoreAgg::Mean => sum / count as f32,
+                },
+                best_chunk: best,
+                num_chunks: count,
+            })
+            .collect();
+
+        documents.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.document_id.cmp(&b.document_id))
+        });
+        documents.truncate(top_k);
+        documents
+    }
+
+    /// Drop results whose embedding has cosine similarity of at least
+    /// `threshold` with a better-scored result, from any document
+    ///
+    /// Results without an embedding are always kept. Returned best first.
+    pub fn dedup_similar(mut results: Vec<SearchResult>, threshold: f32) -> Vec<SearchResult> {
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        let mut kept: Vec<SearchResult> = Vec::with_capacity(results.len());
+        for result in results {
+            let duplicate = result.chunk.embedding.as_deref().is_some_and(|embedding| {
+                kept.iter().any(|k| {
+                    k.chunk.embedding.as_deref().is_some_and(|other| {
+                        other.len() == embedding.len()
+                            && cosine_similarity(embedding, other) >= threshold
+                    })
+                })
+            });
+            if duplicate {
+                log::debug!("Dropping near-duplicate chunk {} from context", result.chunk.id);
+            } else {
+                kept.push(result);
+            }
+        }
+        kept
+    }
+
+    /// Merge contiguous or overlapping chunks of the same document
+    ///
+    /// Each merged result spans from the first chunk's `start_char` to the
+    /// last chunk's `end_char`, its content is rebuilt from the chunk
+    /// contents without repeating the overlap, and it keeps the highest
+    /// score. Results are returned best first.
+    pub fn merge_adjacent(results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let mut by_document: HashMap<String, Vec<SearchResult>> = HashMap::new();
+        for result in results {
+            by_document
+                .entry(result.chunk.metadata.document_id.clone())
+                .or_default()
+                .push(result);
+        }
+
+        let mut merged: Vec<SearchResult> = Vec::new();
+        for (_, mut group) in by_document {
+            group.sort_by_key(|r| r.chunk.metadata.start_char);
+
+            let mut iter = group.into_iter();
+            let Some(mut current) = iter.next() else {
+                continue;
+            };
+
+            for next in iter {
+                let current_end = current.chunk.metadata.end_char;
+                let next_meta = &next.chunk.metadata;
+
+                if next_meta.start_char > current_end {
+                    merged.push(std::mem::replace(&mut current, next));
+                    continue;
+                }
+
+                if next_meta.end_char > current_end {
+                    let overlap = current_end - next_meta.start_char;
+                    let tail: String = next.chunk.content.chars().skip(overlap).collect();
+                    current.chunk.content.push_str(&tail);
+                    current.chunk.metadata.end_char = next_meta.end_char;
+                }
+                current.score = current.score.max(next.score);
+            }
+            merged.push(current);
+        }
+
+        merged.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        merged
+    }
+
     /// Get reference to vector database
     pub fn vector_db(&self) -> &VectorDatabase {
         &self.vector_db
@@ -66,3 +708,499 @@ impl Retriever {
         &self.embedding_model
     }
 }
+
+/// Document text reassembled from its chunks' character offsets
+fn document_text<'a>(chunks: impl Iterator<Item = &'a Chunk>) -> Vec<char> {
+    let mut text = Vec::new();
+    for chunk in chunks {
+        let (start, end) = (chunk.metadata.start_char, chunk.metadata.end_char);
+        if text.len() < end {
+            text.resize(end, ' ');
+        }
+        for (slot, c) in text[start..end].iter_mut().zip(chunk.content.chars()) {
+            *slot = c;
+        }
+    }
+    text
+}
+
+/// Parent span of `chunk` in `text`: the one stored in its `parent_id`, or
+/// else a window of about `parent_chars` characters centred on it
+fn parent_window(chunk: &Chunk, text: &[char], parent_chars: usize) -> Chunk {
+    let metadata = &chunk.metadata;
+    let stored = metadata
+        .parent_id
+        .as_deref()
+        .and_then(|id| parse_span_id(id, &metadata.document_id));
+    let (window_start, window_end) = match stored {
+        Some((start, end)) => (start.min(text.len()), end.min(text.len())),
+        None => parent_span(metadata.start_char, metadata.end_char, text.len(), parent_chars),
+    };
+
+    window_chunk(chunk, text, window_start, window_end)
+}
+
+/// Character spans of a document's sentences
+type SentenceSpans = Vec<(usize, usize)>;
+
+/// Sentence spans of a rebuilt document text
+fn sentence_spans(text: &[char]) -> SentenceSpans {
+    let text: String = text.iter().collect();
+    let offsets = CharOffsets::new(&text);
+    split_sentences(&text)
+        .into_iter()
+        .map(|(start, end, _)| (offsets.to_char(start), offsets.to_char(end)))
+        .collect()
+}
+
+/// The sentences overlapping `chunk` plus `window` sentences on each side
+fn sentence_window(
+    chunk: &Chunk,
+    text: &[char],
+    sentences: &[(usize, usize)],
+    window: usize,
+) -> Chunk {
+    let (start, end) = (chunk.metadata.start_char, chunk.metadata.end_char);
+    let first = sentences.iter().position(|&(_, e)| e > start);
+    let last = sentences.iter().rposition(|&(s, _)| s < end);
+
+    match (first, last) {
+        (Some(first), Some(last)) if first <= last => {
+            let window_start = sentences[first.saturating_sub(window)].0.min(start);
+            let window_end = sentences[(last + window).min(sentences.len() - 1)].1.max(end);
+            window_chunk(chunk, text, window_start, window_end)
+        }
+        _ => window_chunk(chunk, text, start, end),
+    }
+}
+
+/// Chunk covering characters `start..end` of the document `chunk` is from
+fn window_chunk(chunk: &Chunk, text: &[char], window_start: usize, window_end: usize) -> Chunk {
+    let metadata = &chunk.metadata;
+    Chunk {
+        id: span_id(&metadata.document_id, window_start, window_end),
+        content: text[window_start..window_end].iter().collect(),
+        embedding: None,
+        metadata: super::ChunkMetadata {
+            start_char: window_start,
+            end_char: window_end,
+            token_count: None,
+            parent_id: None,
+            ..metadata.clone()
+        },
+    }
+}
+
+/// Lowercased distinct words of `text`
+fn terms(text: &str) -> HashSet<String> {
+    split_words(text)
+        .into_iter()
+        .map(|(_, _, word)| word.to_lowercase())
+        .collect()
+}
+
+/// Fraction of query terms that occur in `content`
+fn lexical_overlap(query_terms: &HashSet<String>, content: &str) -> f32 {
+    if query_terms.is_empty() {
+        return 0.0;
+    }
+    let content_terms = terms(content);
+    let matched = query_terms.intersection(&content_terms).count();
+    matched as f32 / query_terms.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::chunking::document;
+    use crate::rag::{Chunk, ChunkMetadata};
+
+    fn result(document_id: &str, text: &str, start: usize, end: usize, score: f32) -> SearchResult {
+        SearchResult {
+            chunk: Chunk {
+                id: format!("{}_{}", document_id, start),
+                content: text[start..end].to_string(),
+                embedding: None,
+                metadata: ChunkMetadata {
+                    document_id: document_id.to_string(),
+                    document_name: document_id.to_string(),
+                    chunk_index: 0,
+                    start_char: start,
+                    end_char: end,
+                    token_count: None,
+                    created_at: "2025-01-01".to_string(),
+                    parent_id: None,
+                    extra: Default::default(),
+                },
+            },
+            score,
+        }
+    }
+
+    /// `result(..).chunk` embedded as `query_embedding` shifted by `offset`
+    /// (a larger offset is less similar to the query)
+    fn embedded(
+        query_embedding: &[f32],
+        document_id: &str,
+        text: &str,
+        start: usize,
+        end: usize,
+        offset: f32,
+    ) -> Chunk {
+        let mut chunk = result(document_id, text, start, end, 0.0).chunk;
+        let mut embedding = query_embedding.to_vec();
+        embedding[1] += offset;
+        chunk.embedding = Some(embedding);
+        chunk
+    }
+
+    /// Database of `(document_id, text, start, end, offset)` chunks embedded
+    /// relative to the embedding of "query"
+    async fn db_with(
+        model: &EmbeddingModel,
+        chunks: &[(&str, &str, usize, usize, f32)],
+    ) -> VectorDatabase {
+        let query_embedding = model.embed("query").await.unwrap();
+        let mut db = VectorDatabase::new();
+        for &(document_id, text, start, end, offset) in chunks {
+            let chunk = embedded(&query_embedding, document_id, text, start, end, offset);
+            db.add_chunk(chunk).await.unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_merge_adjacent_overlapping_chunks() {
+        let text = "The quick brown fox jumps over the lazy dog";
+
+        let merged = Retriever::merge_adjacent(vec![
+            result("doc", text, 16, 30, 0.6),
+            result("other", text, 0, 9, 0.5),
+            result("doc", text, 4, 19, 0.9),
+        ]);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].chunk.content, "quick brown fox jumps over");
+        assert_eq!(merged[0].chunk.metadata.start_char, 4);
+        assert_eq!(merged[0].chunk.metadata.end_char, 30);
+        assert_eq!(merged[0].score, 0.9);
+        assert_eq!(merged[1].chunk.metadata.document_id, "other");
+    }
+
+    #[test]
+    fn test_document_score_aggregation() {
+        let text = "0123456789";
+        let results = vec![
+            // One excellent chunk among poor ones
+            result("spiky", text, 0, 1, 0.95),
+            result("spiky", text, 1, 2, 0.1),
+            result("spiky", text, 2, 3, 0.1),
+            // Consistently relevant
+            result("steady", text, 0, 1, 0.7),
+            result("steady", text, 1, 2, 0.65),
+            result("steady", text, 2, 3, 0.6),
+        ];
+
+        let by_max = Retriever::aggregate_documents(results.clone(), 2, ScoreAgg::Max);
+        assert_eq!(by_max[0].document_id, "spiky");
+        assert_eq!(by_max[0].score, 0.95);
+        assert_eq!(by_max[0].best_chunk.score, 0.95);
+        assert_eq!(by_max[0].num_chunks, 3);
+
+        let by_mean = Retriever::aggregate_documents(results, 1, ScoreAgg::Mean);
+        assert_eq!(by_mean.len(), 1);
+        assert_eq!(by_mean[0].document_id, "steady");
+        assert!((by_mean[0].score - 0.65).abs() < 1e-6);
+        assert_eq!(by_mean[0].best_chunk.score, 0.7);
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_explained_reconstructs_hybrid_score() {
+        let model = EmbeddingModel::new("test".to_string());
+        let text = "rust borrow checker explained in plain words";
+        // Vary similarity by perturbing the query embedding
+        let chunks = [("a", text, 0, 11, 0.0), ("b", text, 0, 44, 1.0), ("c", text, 0, 4, 2.0)];
+        let db = db_with(&model, &chunks).await;
+
+        let retriever = Retriever::new(db, model)
+            .with_lexical_weight(0.3)
+            .with_document_boosts(HashMap::from([("b".to_string(), 1.5)]));
+        let explained = retriever.retrieve_explained("borrow checker", 3).await.unwrap();
+
+        assert_eq!(explained.len(), 3);
+        for e in &explained {
+            let lexical = e.lexical_score.unwrap();
+            let expected = (0.7 * e.similarity + 0.3 * lexical) * e.boost;
+            assert!((e.final_score - expected).abs() < 1e-6);
+            assert_eq!(e.result.score, e.final_score);
+        }
+
+        let b = explained.iter().find(|e| e.result.chunk.metadata.document_id == "b").unwrap();
+        assert_eq!(b.boost, 1.5);
+        assert_eq!(b.lexical_score, Some(1.0));
+        let c = explained.iter().find(|e| e.result.chunk.metadata.document_id == "c").unwrap();
+        assert_eq!(c.lexical_score, Some(0.0));
+
+        // Plain retrieval ranks the same way
+        let plain = retriever.retrieve("borrow checker", 3).await.unwrap();
+        for (r, e) in plain.iter().zip(&explained) {
+            assert_eq!(r.chunk.id, e.result.chunk.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_k_overfetches_candidates() {
+        let model = EmbeddingModel::new("test".to_string());
+        let text = "unrelated words here; sourdough starter feeding";
+        // Later chunks are further from the query
+        let chunks = [
+            ("a", text, 0, 9, 0.0),
+            ("b", text, 10, 15, 1.0),
+            ("c", text, 16, 20, 2.0),
+            ("d", text, 22, 47, 3.0),
+        ];
+        let db = db_with(&model, &chunks).await;
+
+        let retriever = Retriever::new(db, model).with_lexical_weight(0.9);
+        let query = "sourdough starter";
+
+        // Only the nearest candidate is scored, so the lexical match is missed
+        let narrow = retriever.retrieve_with_fetch_k(query, 1, 1).await.unwrap();
+        assert_eq!(narrow.len(), 1);
+        assert_eq!(narrow[0].chunk.metadata.document_id, "a");
+
+        // Scoring every candidate lets the second stage promote it
+        let wide = retriever.retrieve_with_fetch_k(query, 1, 4).await.unwrap();
+        assert_eq!(wide.len(), 1);
+        assert_eq!(wide[0].chunk.metadata.document_id, "d");
+
+        // fetch_k never returns fewer than top_k
+        assert_eq!(retriever.retrieve_with_fetch_k(query, 3, 1).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_with_parents_widens_hits() {
+        let model = EmbeddingModel::new("test".to_string());
+        let text = "Alpha intro. The key fact is here. Beta outro.";
+        // Only the middle chunk matches the query exactly
+        let chunks = [
+            ("doc", text, 0, 12, 1.0),
+            ("doc", text, 13, 34, 0.0),
+            ("doc", text, 35, 46, 1.0),
+        ];
+        let db = db_with(&model, &chunks).await;
+        let retriever = Retriever::new(db, model);
+
+        let hits = retriever.retrieve_with_parents("query", 1, 31).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        let ParentHit { hit, parent } = &hits[0];
+        assert_eq!(hit.chunk.content, "The key fact is here.");
+        assert_eq!(parent.content, "tro. The key fact is here. Beta");
+        assert_eq!(
+            &text[parent.metadata.start_char..parent.metadata.end_char],
+            parent.content
+        );
+        assert_eq!(hit.chunk.metadata.parent_id.as_deref(), Some(parent.id.as_str()));
+
+        // Windows near the document edges shift inward instead of shrinking
+        let wide = retriever.retrieve_with_parents("query", 1, 100).await.unwrap();
+        assert_eq!(wide[0].parent.content, text);
+    }
+
+    #[tokio::test]
+    async fn test_parent_span_stored_at_chunking() {
+        use crate::rag::{ChunkingStrategy, DocumentChunker};
+
+        let model = EmbeddingModel::new("test".to_string());
+        let query_embedding = model.embed("query").await.unwrap();
+        let content = "Alpha intro. The key fact is here. Beta outro.";
+        let document = document("doc", content);
+        let chunks = DocumentChunker::new(ChunkingStrategy::FixedSize { size: 12, overlap: 0 })
+            .with_parent_chars(24)
+            .chunk(&document)
+            .unwrap();
+        assert_eq!(chunks[1].metadata.parent_id.as_deref(), Some("doc:6-30"));
+
+        let mut db = VectorDatabase::new();
+        for (i, mut chunk) in chunks.into_iter().enumerate() {
+            let mut embedding = query_embedding.clone();
+            embedding[0] += if i == 1 { 0.0 } else { 1.0 };
+            chunk.embedding = Some(embedding);
+            db.add_chunk(chunk).await.unwrap();
+        }
+
+        // The stored parent wins over the query-time size
+        let hits = Retriever::new(db, model)
+            .retrieve_with_parents("query", 1, 1000)
+            .await
+            .unwrap();
+        assert_eq!(hits[0].hit.chunk.content, " The key fac");
+        assert_eq!(hits[0].parent.id, "doc:6-30");
+        assert_eq!(hits[0].parent.content, &content[6..30]);
+    }
+
+    #[tokio::test]
+    async fn test_time_decay_prefers_recent_chunks() {
+        let model = EmbeddingModel::new("test".to_string());
+        let query_embedding = model.embed("query").await.unwrap();
+
+        let mut db = VectorDatabase::new();
+        for (start, created_at) in [(0, "2025-01-01T00:00:00Z"), (5, "2025-01-10T00:00:00Z")] {
+            let mut chunk = embedded(&query_embedding, "doc", "Old. New.", start, start + 4, 0.0);
+            chunk.metadata.created_at = created_at.to_string();
+            db.add_chunk(chunk).await.unwrap();
+        }
+        let retriever = Retriever::new(db, model);
+
+        // Equal similarity: plain retrieval keeps insertion order
+        assert_eq!(retriever.retrieve("query", 1).await.unwrap()[0].chunk.content, "Old.");
+
+        let now = parse_timestamp("2025-01-11T00:00:00Z").unwrap();
+        let results = retriever.retrieve_decayed_at("query", 2, 10.0, now).await.unwrap();
+        assert_eq!(results[0].chunk.content, "New.");
+        assert!((results[0].score - 0.5f32.powf(0.1)).abs() < 1e-4);
+        assert!((results[1].score - 0.5).abs() < 1e-4);
+
+        assert!(retriever.retrieve_time_decayed("query", 1, 0.0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sentence_window_returns_neighbours_in_order() {
+        use crate::rag::{ChunkingStrategy, DocumentChunker};
+
+        let model = EmbeddingModel::new("test".to_string());
+        let query_embedding = model.embed("query").await.unwrap();
+
+        let content = "One is first. Two follows. Three matches! Four is next. Five ends.";
+        let document = document("doc", content);
+        let chunks = DocumentChunker::new(ChunkingStrategy::Sentence).chunk(&document).unwrap();
+        assert_eq!(chunks.len(), 5);
+
+        let mut db = VectorDatabase::new();
+        for mut chunk in chunks {
+            let mut embedding = query_embedding.clone();
+            // Only the middle sentence matches the query exactly
+            embedding[0] += if chunk.content == "Three matches!" { 0.0 } else { 1.0 };
+            chunk.embedding = Some(embedding);
+            db.add_chunk(chunk).await.unwrap();
+        }
+        let retriever = Retriever::new(db, model);
+
+        let hits = retriever.retrieve_sentence_window("query", 1, 1).await.unwrap();
+        let ParentHit { hit, parent } = &hits[0];
+        assert_eq!(hit.chunk.content, "Three matches!");
+        assert_eq!(parent.content, "Two follows. Three matches! Four is next.");
+        assert_eq!(hit.chunk.metadata.parent_id.as_deref(), Some(parent.id.as_str()));
+
+        // Windows stop at the document edges
+        let hits = retriever.retrieve_sentence_window("query", 1, 5).await.unwrap();
+        assert_eq!(hits[0].parent.content, content);
+        let hits = retriever.retrieve_sentence_window("query", 1, 0).await.unwrap();
+        assert_eq!(hits[0].parent.content, "Three matches!");
+    }
+
+    #[tokio::test]
+    async fn test_context_dedup_across_documents() {
+        let model = EmbeddingModel::new("test".to_string());
+        let boilerplate = "All rights reserved by the publisher";
+        let other = "Sourdough needs a lively starter";
+        let chunks = [
+            ("a", boilerplate, 0, boilerplate.len(), 0.0),
+            ("b", boilerplate, 0, boilerplate.len(), 0.01),
+            ("c", other, 0, other.len(), 2.0),
+        ];
+        let db = db_with(&model, &chunks).await;
+
+        let retriever = Retriever::new(db, model);
+        let context = retriever.retrieve_context("query", 3).await.unwrap();
+        assert_eq!(context.matches(boilerplate).count(), 2);
+
+        let retriever = retriever.with_context_dedup(0.99);
+        let context = retriever.retrieve_context("query", 3).await.unwrap();
+        assert_eq!(context.matches(boilerplate).count(), 1);
+        assert!(context.contains("Document 1: a"));
+        assert!(context.contains(other));
+    }
+
+    #[tokio::test]
+    async fn test_max_per_document() {
+        let model = EmbeddingModel::new("test".to_string());
+        let text = "0123456789";
+        let chunks = [
+            ("big", text, 0, 1, 0.0),
+            ("big", text, 1, 2, 0.1),
+            ("big", text, 2, 3, 0.2),
+            ("b", text, 0, 1, 1.0),
+            ("c", text, 0, 1, 2.0),
+        ];
+        let db = db_with(&model, &chunks).await;
+
+        let documents = |results: Vec<SearchResult>| -> Vec<String> {
+            results.into_iter().map(|r| r.chunk.metadata.document_id).collect()
+        };
+
+        let retriever = Retriever::new(db, model);
+        let results = retriever.retrieve("query", 3).await.unwrap();
+        assert_eq!(documents(results), vec!["big", "big", "big"]);
+
+        let retriever = retriever.with_max_per_document(1);
+        let results = retriever.retrieve("query", 3).await.unwrap();
+        assert_eq!(documents(results), vec!["big", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_query_cache_invalidated_by_new_chunk() {
+        let model = EmbeddingModel::new("test".to_string());
+        let query_embedding = model.embed("query").await.unwrap();
+        let text = "0123456789";
+
+        let db = db_with(&model, &[("a", text, 0, 1, 0.0)]).await;
+
+        let mut retriever = Retriever::new(db, model).with_query_cache(QueryCache::new(60_000.0));
+        let first = retriever.retrieve("query", 2).await.unwrap();
+        let second = retriever.retrieve("query", 2).await.unwrap();
+        assert_eq!(retriever.query_cache().unwrap().hits(), 1);
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+
+        // A different top_k or filter is a different entry
+        retriever.retrieve("query", 3).await.unwrap();
+        let filter = MetadataFilter::documents(&["a"]);
+        retriever.retrieve_filtered("query", 2, &filter).await.unwrap();
+        assert_eq!(retriever.query_cache().unwrap().hits(), 1);
+
+        let chunk = embedded(&query_embedding, "b", text, 0, 1, 0.0);
+        retriever.vector_db_mut().add_chunk(chunk).await.unwrap();
+
+        let third = retriever.retrieve("query", 2).await.unwrap();
+        assert_eq!(retriever.query_cache().unwrap().hits(), 1);
+        assert_eq!(third.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_batch_matches_retrieve() {
+        let model = EmbeddingModel::new("test".to_string());
+        let text = "apple pie banana bread cherry tart";
+        let chunks = [("a", text, 0, 9, 0.0), ("b", text, 10, 22, 0.0), ("c", text, 23, 34, 0.0)];
+        let db = db_with(&model, &chunks).await;
+
+        let retriever = Retriever::new(db, model).with_lexical_weight(0.5);
+        let queries: Vec<String> = ["banana bread", "cherry", "apple tart"]
+            .iter()
+            .map(|q| q.to_string())
+            .collect();
+        let ids = |results: &[SearchResult]| -> Vec<(String, f32)> {
+            results.iter().map(|r| (r.chunk.id.clone(), r.score)).collect()
+        };
+
+        let batch = retriever.retrieve_batch(&queries, 2).await.unwrap();
+        assert_eq!(batch.len(), queries.len());
+        assert_eq!(batch[0][0].chunk.metadata.document_id, "b");
+        assert_eq!(batch[1][0].chunk.metadata.document_id, "c");
+
+        for (query, batched) in queries.iter().zip(&batch) {
+            let single = retriever.retrieve(query, 2).await.unwrap();
+            assert_eq!(ids(&single), ids(batched));
+        }
+    }
+}
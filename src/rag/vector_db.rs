@@ -1,11 +1,139 @@
 use anyhow::Result;
-use super::{Chunk, SearchResult, embeddings::cosine_similarity};
+use async_trait::async_trait;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use super::{Chunk, EmbeddingModel, SearchResult, SimilarityMetric};
+use super::embeddings::{cosine_similarity, reconcile_dimension};
+use crate::utils::Quantizer;
+
+/// Number of chunks re-embedded per batch in `rebuild_embeddings`
+const REBUILD_BATCH_SIZE: usize = 32;
+
+/// When buffered chunk writes reach persistent storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Write every chunk as soon as it is added
+    #[default]
+    Immediate,
+    /// Write once `every_n` chunks are dirty
+    Batched { every_n: usize },
+    /// Write only when `flush` is called
+    Manual,
+}
+
+/// In-memory format of stored embeddings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmbeddingStorage {
+    /// Full precision, kept in each chunk's `embedding`
+    #[default]
+    F32,
+    /// IEEE-754 half precision (half the memory, ~3 significant digits)
+    F16,
+}
+
+/// What `add_chunk` does with embeddings that are not unit length
+///
+/// Mixing normalized and unnormalized vectors skews cosine rankings, so a
+/// store meant for normalized embeddings can check them on insert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalizationCheck {
+    /// Accept any embedding
+    #[default]
+    Off,
+    /// Log a warning and add the chunk anyway
+    Warn,
+    /// Fail `add_chunk` without adding the chunk
+    Reject,
+}
+
+/// Restricts a search to chunks whose metadata matches
+///
+/// Deserializes from JS as `{ document_ids: [...], extra: { key: value } }`;
+/// both fields are optional and an empty filter matches every chunk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetadataFilter {
+    /// Allowed document IDs (empty allows any document)
+    #[serde(default)]
+    pub document_ids: Vec<String>,
+    /// Values `ChunkMetadata::extra` must contain (e.g. `file_type: pdf`)
+    #[serde(default)]
+    pub extra: HashMap<String, String>,
+}
+
+impl MetadataFilter {
+    /// Filter matching only the given documents
+    pub fn documents(document_ids: &[&str]) -> Self {
+        Self {
+            document_ids: document_ids.iter().map(|id| id.to_string()).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Also require `extra[key] == value`
+    pub fn with_extra(mut self, key: &str, value: &str) -> Self {
+        self.extra.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Whether `chunk` passes the filter
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        let metadata = &chunk.metadata;
+        (self.document_ids.is_empty() || self.document_ids.contains(&metadata.document_id))
+            && self
+                .extra
+                .iter()
+                .all(|(key, value)| metadata.extra.get(key) == Some(value))
+    }
+}
+
+/// Persistent storage for chunks (e.g. `IndexedDbStorage`)
+#[async_trait(?Send)]
+pub trait ChunkPersistence {
+    /// Write (insert or overwrite) a batch of chunks
+    async fn write_chunks(&self, chunks: &[Chunk]) -> Result<()>;
+
+    /// Remove chunks by ID; IDs that are not stored are ignored
+    async fn delete_chunks(&self, ids: &[String]) -> Result<()>;
+}
 
 /// Simple in-memory vector database
 /// TODO: Integrate with Voy or custom IndexedDB implementation
 #[derive(Clone)]
 pub struct VectorDatabase {
     chunks: Vec<Chunk>,
+    /// Optional Hamming-distance pre-filter for large stores
+    binary_prefilter: Option<BinaryPrefilter>,
+    /// Scoring function used by `search`
+    metric: SimilarityMetric,
+    /// Map search scores into `[0, 1]`
+    normalize_scores: bool,
+    /// Storage that added chunks are written to
+    persistence: Option<Rc<dyn ChunkPersistence>>,
+    flush_policy: FlushPolicy,
+    /// IDs of chunks changed since the last flush
+    dirty: HashSet<String>,
+    embedding_storage: EmbeddingStorage,
+    /// Half-precision embeddings aligned with `chunks` (F16 storage only;
+    /// those chunks keep `embedding: None`)
+    half_embeddings: Vec<Option<Vec<u16>>>,
+    normalization_check: NormalizationCheck,
+    /// Allowed distance of an embedding's L2 norm from 1.0
+    norm_tolerance: f32,
+    /// Truncate or zero-pad queries to the dimension of each stored
+    /// embedding instead of requiring them to match
+    reconcile_dimensions: bool,
+    /// Bumped on every change to the stored chunks or embeddings
+    version: u64,
+}
+
+/// 1-bit signatures used to shortlist candidates before cosine reranking
+#[derive(Clone)]
+struct BinaryPrefilter {
+    shortlist_size: usize,
+    /// Signature per chunk, aligned with `chunks`
+    signatures: Vec<Option<Vec<u8>>>,
 }
 
 impl VectorDatabase {
@@ -13,21 +141,280 @@ impl VectorDatabase {
     pub fn new() -> Self {
         Self {
             chunks: Vec::new(),
+            binary_prefilter: None,
+            metric: SimilarityMetric::default(),
+            normalize_scores: false,
+            persistence: None,
+            flush_policy: FlushPolicy::default(),
+            dirty: HashSet::new(),
+            embedding_storage: EmbeddingStorage::default(),
+            half_embeddings: Vec::new(),
+            normalization_check: NormalizationCheck::default(),
+            norm_tolerance: 1e-3,
+            reconcile_dimensions: false,
+            version: 0,
+        }
+    }
+
+    /// Expect unit-length embeddings, handling ones whose norm differs from
+    /// 1.0 by more than `tolerance` according to `check`
+    pub fn with_normalization_check(mut self, check: NormalizationCheck, tolerance: f32) -> Self {
+        self.normalization_check = check;
+        self.norm_tolerance = tolerance;
+        self
+    }
+
+    /// Keep embeddings in `storage` format, converting any already stored
+    pub fn with_embedding_storage(mut self, storage: EmbeddingStorage) -> Self {
+        let embeddings: Vec<Option<Vec<f32>>> = (0..self.chunks.len())
+            .map(|i| self.embedding_at(i).map(Cow::into_owned))
+            .collect();
+
+        self.embedding_storage = storage;
+        self.half_embeddings.clear();
+        for (i, embedding) in embeddings.into_iter().enumerate() {
+            self.chunks[i].embedding = None;
+            if storage == EmbeddingStorage::F16 {
+                self.half_embeddings.push(None);
+            }
+            self.set_embedding(i, embedding);
+        }
+        self
+    }
+
+    /// Embedding of the chunk at `index`, decoded if stored as f16
+    fn embedding_at(&self, index: usize) -> Option<Cow<'_, [f32]>> {
+        match self.embedding_storage {
+            EmbeddingStorage::F32 => self.chunks[index].embedding.as_deref().map(Cow::Borrowed),
+            EmbeddingStorage::F16 => self.half_embeddings[index]
+                .as_deref()
+                .map(|half| Cow::Owned(Quantizer::dequantize_f16(half))),
+        }
+    }
+
+    /// Store the embedding of the chunk at `index` in the configured format
+    fn set_embedding(&mut self, index: usize, embedding: Option<Vec<f32>>) {
+        self.version += 1;
+        match self.embedding_storage {
+            EmbeddingStorage::F32 => self.chunks[index].embedding = embedding,
+            EmbeddingStorage::F16 => {
+                self.half_embeddings[index] = embedding.as_deref().map(Quantizer::quantize_f16)
+            }
+        }
+    }
+
+    /// Copy of the chunk at `index` with its embedding in f32
+    fn chunk_with_embedding(&self, index: usize) -> Chunk {
+        let mut chunk = self.chunks[index].clone();
+        if self.embedding_storage == EmbeddingStorage::F16 {
+            chunk.embedding = self.embedding_at(index).map(Cow::into_owned);
+        }
+        chunk
+    }
+
+    /// Write added chunks to `persistence` according to `policy`
+    ///
+    /// Deletions (`delete_by_document`, `clear`) are written immediately,
+    /// whatever the policy.
+    pub fn with_persistence(
+        mut self,
+        persistence: Rc<dyn ChunkPersistence>,
+        policy: FlushPolicy,
+    ) -> Self {
+        self.persistence = Some(persistence);
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Number of chunks changed since the last flush
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Write all dirty chunks to persistent storage
+    ///
+    /// Returns the number of chunks written (0 without persistence).
+    pub async fn flush(&mut self) -> Result<usize> {
+        let Some(persistence) = self.persistence.clone() else {
+            return Ok(0);
+        };
+        if self.dirty.is_empty() {
+            return Ok(0);
+        }
+
+        let chunks: Vec<Chunk> = (0..self.chunks.len())
+            .filter(|&i| self.dirty.contains(&self.chunks[i].id))
+            .map(|i| self.chunk_with_embedding(i))
+            .collect();
+        persistence.write_chunks(&chunks).await?;
+        self.dirty.clear();
+
+        log::debug!("Flushed {} chunks to persistent storage", chunks.len());
+        Ok(chunks.len())
+    }
+
+    /// Remove deleted chunks from persistent storage
+    async fn persist_deletes(&mut self, ids: Vec<String>) -> Result<()> {
+        let Some(persistence) = self.persistence.clone() else {
+            return Ok(());
+        };
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.dirty.retain(|id| !ids.contains(id));
+        persistence.delete_chunks(&ids).await?;
+        log::debug!("Deleted {} chunks from persistent storage", ids.len());
+        Ok(())
+    }
+
+    /// Record changed chunks and flush if the policy calls for it
+    async fn mark_dirty(&mut self, ids: impl IntoIterator<Item = String>) -> Result<()> {
+        if self.persistence.is_none() {
+            return Ok(());
+        }
+        self.dirty.extend(ids);
+
+        let due = match self.flush_policy {
+            FlushPolicy::Immediate => true,
+            FlushPolicy::Batched { every_n } => self.dirty.len() >= every_n,
+            FlushPolicy::Manual => false,
+        };
+        if due {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Score search results with `metric` instead of cosine similarity
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Report search scores in `[0, 1]` instead of the metric's raw range
+    ///
+    /// Ranking is unchanged; see `SimilarityMetric::normalize`.
+    pub fn with_normalized_scores(mut self, normalize: bool) -> Self {
+        self.normalize_scores = normalize;
+        self
+    }
+
+    /// Let searches score chunks whose embedding dimension differs from the
+    /// query's, by truncating or zero-padding the query to match
+    ///
+    /// A fallback for keeping search usable while migrating to another
+    /// embedding model (see `rebuild_embeddings`); the scores are rough and
+    /// every affected search logs a warning. The binary pre-filter still
+    /// skips mismatched chunks.
+    pub fn with_dimension_reconciliation(mut self, enabled: bool) -> Self {
+        self.reconcile_dimensions = enabled;
+        self
+    }
+
+    /// The query in the dimension of a stored embedding (unchanged unless
+    /// reconciliation is on and the dimensions differ)
+    fn query_for<'a>(&self, query_embedding: &'a [f32], dimension: usize) -> Cow<'a, [f32]> {
+        if self.reconcile_dimensions && query_embedding.len() != dimension {
+            Cow::Owned(reconcile_dimension(query_embedding, dimension))
+        } else {
+            Cow::Borrowed(query_embedding)
+        }
+    }
+
+    fn warn_reconciled(&self, query_embedding: &[f32], reconciled: usize) {
+        if reconciled > 0 {
+            log::warn!(
+                "Reconciled a {}-d query to the dimension of {} chunks; results are approximate \
+                 until embeddings are rebuilt",
+                query_embedding.len(),
+                reconciled
+            );
+        }
+    }
+
+    /// Enable two-stage search: shortlist `shortlist_size` candidates by
+    /// Hamming distance between binary signatures, then rerank them with
+    /// full cosine similarity
+    pub fn with_binary_prefilter(mut self, shortlist_size: usize) -> Self {
+        self.binary_prefilter = Some(BinaryPrefilter {
+            shortlist_size,
+            signatures: Vec::new(),
+        });
+        self.refresh_signatures();
+        self
+    }
+
+    /// Recompute all binary signatures (after bulk changes)
+    fn refresh_signatures(&mut self) {
+        if self.binary_prefilter.is_none() {
+            return;
+        }
+        let signatures = (0..self.chunks.len())
+            .map(|i| self.embedding_at(i).map(|e| Quantizer::quantize_binary(&e)))
+            .collect();
+        if let Some(prefilter) = self.binary_prefilter.as_mut() {
+            prefilter.signatures = signatures;
         }
     }
 
     /// Add a chunk to the database
-    pub async fn add_chunk(&mut self, chunk: Chunk) -> Result<()> {
+    ///
+    /// If a flush triggered by the persistence policy fails, the error is
+    /// returned but the chunk has still been added; it stays dirty and is
+    /// written by the next successful flush.
+    pub async fn add_chunk(&mut self, mut chunk: Chunk) -> Result<()> {
         if chunk.embedding.is_none() {
             log::warn!("Adding chunk without embedding: {}", chunk.id);
         }
+        self.check_normalized(&chunk)?;
+
+        if let Some(prefilter) = self.binary_prefilter.as_mut() {
+            prefilter
+                .signatures
+                .push(chunk.embedding.as_deref().map(Quantizer::quantize_binary));
+        }
 
+        if self.embedding_storage == EmbeddingStorage::F16 {
+            self.half_embeddings
+                .push(chunk.embedding.take().as_deref().map(Quantizer::quantize_f16));
+        }
+
+        let id = chunk.id.clone();
         self.chunks.push(chunk);
+        self.version += 1;
         log::debug!("Added chunk to vector database. Total: {}", self.chunks.len());
 
+        self.mark_dirty([id]).await?;
+
         Ok(())
     }
 
+    /// Apply the normalization check to a chunk about to be added
+    fn check_normalized(&self, chunk: &Chunk) -> Result<()> {
+        if self.normalization_check == NormalizationCheck::Off {
+            return Ok(());
+        }
+        let Some(embedding) = chunk.embedding.as_deref() else {
+            return Ok(());
+        };
+
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if (norm - 1.0).abs() <= self.norm_tolerance {
+            return Ok(());
+        }
+        match self.normalization_check {
+            NormalizationCheck::Reject => anyhow::bail!(
+                "Embedding of chunk {} is not normalized (norm {:.4})",
+                chunk.id,
+                norm
+            ),
+            _ => {
+                log::warn!("Embedding of chunk {} is not normalized (norm {:.4})", chunk.id, norm);
+                Ok(())
+            }
+        }
+    }
+
     /// Add multiple chunks
     pub async fn add_chunks(&mut self, chunks: Vec<Chunk>) -> Result<()> {
         for chunk in chunks {
@@ -36,28 +423,266 @@ impl VectorDatabase {
         Ok(())
     }
 
-    /// Search for similar chunks using cosine similarity
+    /// Append the chunks of another store (e.g. one built in a worker)
+    ///
+    /// Chunks whose ID is already present are skipped, keeping the existing
+    /// chunk. Search indexes are updated as chunks are added. Returns the
+    /// number of chunks added.
+    pub async fn merge(&mut self, other: VectorDatabase) -> Result<usize> {
+        let mut ids: HashSet<String> = self.chunks.iter().map(|c| c.id.clone()).collect();
+        let mut added = 0;
+
+        for i in 0..other.chunks.len() {
+            let chunk = other.chunk_with_embedding(i);
+            if !ids.insert(chunk.id.clone()) {
+                log::warn!("Merge skipped chunk {}: ID already present", chunk.id);
+                continue;
+            }
+            self.add_chunk(chunk).await?;
+            added += 1;
+        }
+
+        log::info!("Merged {} chunks, total {}", added, self.chunks.len());
+        Ok(added)
+    }
+
+    /// Add a chunk unless a near-duplicate is already stored
+    ///
+    /// Returns `true` if the chunk was inserted, `false` if it was skipped
+    /// because an existing chunk's similarity exceeds `threshold`.
+    pub async fn add_chunk_dedup(&mut self, chunk: Chunk, threshold: f32) -> Result<bool> {
+        if let Some(embedding) = chunk.embedding.as_ref() {
+            let duplicates = self.find_duplicates(embedding, threshold);
+            if let Some(existing) = duplicates.first() {
+                log::debug!(
+                    "Skipping chunk {}: near-duplicate of {} (score {:.3})",
+                    chunk.id,
+                    existing.chunk.id,
+                    existing.score
+                );
+                return Ok(false);
+            }
+        }
+
+        self.add_chunk(chunk).await?;
+        Ok(true)
+    }
+
+    /// Find stored chunks whose similarity to `embedding` exceeds `threshold`
+    ///
+    /// Results are sorted by score (descending).
+    pub fn find_duplicates(&self, embedding: &[f32], threshold: f32) -> Vec<SearchResult> {
+        let mut results: Vec<SearchResult> = (0..self.chunks.len())
+            .filter_map(|i| {
+                let emb = self.embedding_at(i)?;
+                if emb.len() != embedding.len() {
+                    return None;
+                }
+                let score = cosine_similarity(embedding, &emb);
+                (score > threshold).then(|| SearchResult {
+                    chunk: self.chunk_with_embedding(i),
+                    score,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results
+    }
+
+    /// Search for similar chunks using the configured metric
     pub async fn search(
         &self,
         query_embedding: &[f32],
         top_k: usize,
     ) -> Result<Vec<SearchResult>> {
-        let mut results: Vec<SearchResult> = self
-            .chunks
-            .iter()
-            .filter_map(|chunk| {
-                chunk.embedding.as_ref().map(|emb| {
-                    let score = cosine_similarity(query_embedding, emb);
+        Ok(self.rank(query_embedding, top_k, |_| 1.0, None))
+    }
+
+    /// Search only the chunks matching `filter`
+    ///
+    /// Skips the binary pre-filter, so every matching chunk is scored.
+    pub async fn search_filtered(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        filter: &MetadataFilter,
+    ) -> Result<Vec<SearchResult>> {
+        Ok(self.rank(query_embedding, top_k, |_| 1.0, Some(filter)))
+    }
+
+    /// Chunks most similar to the stored chunk `chunk_id`, excluding itself
+    ///
+    /// Uses the chunk's own embedding as the query, for "related passages"
+    /// lookups without a text query.
+    pub async fn knn_for_chunk(&self, chunk_id: &str, top_k: usize) -> Result<Vec<SearchResult>> {
+        let Some(index) = self.chunks.iter().position(|c| c.id == chunk_id) else {
+            anyhow::bail!("Chunk {} not found", chunk_id);
+        };
+        let Some(embedding) = self.embedding_at(index) else {
+            anyhow::bail!("Chunk {} has no embedding", chunk_id);
+        };
+
+        let mut results = self.rank(&embedding, top_k + 1, |_| 1.0, None);
+        results.retain(|r| r.chunk.id != chunk_id);
+        results.truncate(top_k);
+        Ok(results)
+    }
+
+    /// Search with per-document score multipliers
+    ///
+    /// Each chunk's similarity is multiplied by the boost for its
+    /// `document_id` (default 1.0) before ranking. Boosts must be finite and
+    /// non-negative.
+    pub async fn search_boosted(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        boosts: &HashMap<String, f32>,
+    ) -> Result<Vec<SearchResult>> {
+        if let Some((document_id, boost)) =
+            boosts.iter().find(|(_, b)| !b.is_finite() || **b < 0.0)
+        {
+            anyhow::bail!("Invalid boost {} for document {}", boost, document_id);
+        }
+
+        let boost = |chunk: &Chunk| {
+            boosts
+                .get(&chunk.metadata.document_id)
+                .copied()
+                .unwrap_or(1.0)
+        };
+        Ok(self.rank(query_embedding, top_k, boost, None))
+    }
+
+    /// Search that stops scanning once `top_k` results score at least
+    /// `high_confidence`
+    ///
+    /// At least `min_scanned` chunks are always scored. Faster than `search`
+    /// when a near-exact match exists, but a better chunk later in the store
+    /// can be missed.
+    pub async fn search_early_exit(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        high_confidence: f32,
+        min_scanned: usize,
+    ) -> Result<Vec<SearchResult>> {
+        Ok(self
+            .scan_early_exit(query_embedding, top_k, high_confidence, min_scanned)
+            .0)
+    }
+
+    /// `search_early_exit` results and the number of chunks scored
+    fn scan_early_exit(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        high_confidence: f32,
+        min_scanned: usize,
+    ) -> (Vec<SearchResult>, usize) {
+        let mut scored: Vec<(f32, usize)> = Vec::new();
+        let mut confident = 0;
+        let mut scanned = 0;
+        let mut reconciled = 0;
+
+        for i in 0..self.chunks.len() {
+            if scanned >= min_scanned && top_k > 0 && confident >= top_k {
+                break;
+            }
+            scanned += 1;
+
+            let Some(emb) = self.embedding_at(i) else {
+                continue;
+            };
+            let query = self.query_for(query_embedding, emb.len());
+            if query.len() != query_embedding.len() {
+                reconciled += 1;
+            }
+            let mut score = self.metric.score(&query, &emb);
+            if self.normalize_scores {
+                score = self.metric.normalize(score);
+            }
+            // A NaN score (e.g. from a NaN embedding) matches nothing
+            if score.is_nan() {
+                continue;
+            }
+            if score >= high_confidence {
+                confident += 1;
+            }
+            scored.push((score, i));
+        }
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(top_k);
+        self.warn_reconciled(query_embedding, reconciled);
+
+        log::debug!(
+            "Early-exit search scanned {} of {} chunks",
+            scanned,
+            self.chunks.len()
+        );
+
+        let results = scored
+            .into_iter()
+            .map(|(score, i)| SearchResult {
+                chunk: self.chunk_with_embedding(i),
+                score,
+            })
+            .collect();
+        (results, scanned)
+    }
+
+    /// Score all chunks (those matching `filter`, if given) against the
+    /// query and keep the top k
+    fn rank<F>(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        boost: F,
+        filter: Option<&MetadataFilter>,
+    ) -> Vec<SearchResult>
+    where
+        F: Fn(&Chunk) -> f32,
+    {
+        let candidates: Vec<usize> = match (&self.binary_prefilter, filter) {
+            (_, Some(filter)) => (0..self.chunks.len())
+                .filter(|&i| filter.matches(&self.chunks[i]))
+                .collect(),
+            (Some(prefilter), None) if self.chunks.len() > prefilter.shortlist_size.max(top_k) => {
+                self.shortlist(prefilter, query_embedding, prefilter.shortlist_size.max(top_k))
+            }
+            _ => (0..self.chunks.len()).collect(),
+        };
+
+        let mut reconciled = 0;
+        let mut results: Vec<SearchResult> = candidates
+            .into_iter()
+            .filter_map(|i| {
+                self.embedding_at(i).map(|emb| {
+                    let query = self.query_for(query_embedding, emb.len());
+                    if query.len() != query_embedding.len() {
+                        reconciled += 1;
+                    }
+                    let mut score = self.metric.score(&query, &emb);
+                    if self.normalize_scores {
+                        score = self.metric.normalize(score);
+                    }
+                    score *= boost(&self.chunks[i]);
                     SearchResult {
-                        chunk: chunk.clone(),
+                        chunk: self.chunk_with_embedding(i),
                         score,
                     }
                 })
             })
             .collect();
+        self.warn_reconciled(query_embedding, reconciled);
+
+        // A NaN score (e.g. from a NaN embedding) matches nothing
+        results.retain(|r| !r.score.is_nan());
 
         // Sort by score (descending)
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
 
         // Take top k
         results.truncate(top_k);
@@ -68,28 +693,230 @@ impl VectorDatabase {
             self.chunks.len()
         );
 
-        Ok(results)
+        results
+    }
+
+    /// Indices of the chunks with the smallest Hamming distance to the
+    /// binarized query
+    fn shortlist(
+        &self,
+        prefilter: &BinaryPrefilter,
+        query_embedding: &[f32],
+        size: usize,
+    ) -> Vec<usize> {
+        let query_signature = Quantizer::quantize_binary(query_embedding);
+
+        let mut distances: Vec<(u32, usize)> = prefilter
+            .signatures
+            .iter()
+            .enumerate()
+            .filter_map(|(i, signature)| {
+                let signature = signature.as_ref()?;
+                (signature.len() == query_signature.len())
+                    .then(|| (hamming_distance(signature, &query_signature), i))
+            })
+            .collect();
+
+        distances.sort_unstable();
+        distances.truncate(size);
+
+        distances.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// Re-embed every chunk with a new embedding model
+    ///
+    /// Use after switching embedding models, since old vectors live in a
+    /// different space (and often have a different dimension).
+    /// `on_progress(done, total)` is called after each batch. Returns the
+    /// number of chunks re-embedded.
+    pub async fn rebuild_embeddings<F>(
+        &mut self,
+        model: &EmbeddingModel,
+        mut on_progress: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(usize, usize),
+    {
+        let total = self.chunks.len();
+        log::info!("Rebuilding embeddings for {} chunks", total);
+
+        let mut done = 0;
+        while done < total {
+            let batch = done..(done + REBUILD_BATCH_SIZE).min(total);
+            let texts: Vec<String> =
+                self.chunks[batch.clone()].iter().map(|c| c.content.clone()).collect();
+            let embeddings = model.embed_documents(&texts).await?;
+
+            for (i, embedding) in batch.clone().zip(embeddings) {
+                self.set_embedding(i, Some(embedding));
+            }
+
+            done = batch.end;
+            on_progress(done, total);
+        }
+
+        self.refresh_signatures();
+        let ids: Vec<String> = self.chunks.iter().map(|c| c.id.clone()).collect();
+        self.mark_dirty(ids).await?;
+
+        log::info!("Rebuilt {} embeddings (dimension {})", done, model.dimension());
+
+        Ok(done)
     }
 
     /// Delete chunks by document ID
+    ///
+    /// With persistence, the chunks are also removed from storage; if that
+    /// fails the error is returned, but they are already gone from memory.
     pub async fn delete_by_document(&mut self, document_id: &str) -> Result<usize> {
-        let initial_count = self.chunks.len();
+        let deleted_ids: Vec<String> = self
+            .document_chunks(document_id)
+            .map(|chunk| chunk.id.clone())
+            .collect();
+        if self.embedding_storage == EmbeddingStorage::F16 {
+            let mut kept = self.chunks.iter().map(|c| c.metadata.document_id != document_id);
+            self.half_embeddings.retain(|_| kept.next().unwrap_or(true));
+        }
         self.chunks.retain(|chunk| chunk.metadata.document_id != document_id);
-        let deleted = initial_count - self.chunks.len();
+        let deleted = deleted_ids.len();
+        if deleted > 0 {
+            self.version += 1;
+            self.refresh_signatures();
+            self.persist_deletes(deleted_ids).await?;
+        }
 
         log::info!("Deleted {} chunks for document {}", deleted, document_id);
 
         Ok(deleted)
     }
 
+    /// Look up a chunk by ID
+    pub fn get_chunk(&self, id: &str) -> Option<Chunk> {
+        let index = self.chunks.iter().position(|c| c.id == id)?;
+        Some(self.chunk_with_embedding(index))
+    }
+
+    /// Counter that changes whenever chunks or embeddings change, for
+    /// invalidating caches of search results
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Get total number of chunks
     pub fn count(&self) -> usize {
         self.chunks.len()
     }
 
-    /// Clear all chunks
+    /// Approximate heap memory held by the database, including spare capacity
+    pub fn count_bytes(&self) -> usize {
+        let chunk_bytes: usize = self
+            .chunks
+            .iter()
+            .map(|c| {
+                c.id.capacity()
+                    + c.content.capacity()
+                    + c.embedding.as_ref().map_or(0, |e| e.capacity() * size_of::<f32>())
+                    + c.metadata.document_id.capacity()
+                    + c.metadata.document_name.capacity()
+                    + c.metadata.created_at.capacity()
+            })
+            .sum();
+
+        let half_bytes = self.half_embeddings.capacity() * size_of::<Option<Vec<u16>>>()
+            + self
+                .half_embeddings
+                .iter()
+                .flatten()
+                .map(|e| e.capacity() * size_of::<u16>())
+                .sum::<usize>();
+
+        let signature_bytes = self.binary_prefilter.as_ref().map_or(0, |prefilter| {
+            prefilter.signatures.capacity() * size_of::<Option<Vec<u8>>>()
+                + prefilter
+                    .signatures
+                    .iter()
+                    .flatten()
+                    .map(Vec::capacity)
+                    .sum::<usize>()
+        });
+
+        self.chunks.capacity() * size_of::<Chunk>() + chunk_bytes + half_bytes + signature_bytes
+    }
+
+    /// Release spare capacity left by deletions and rebuild the binary
+    /// signatures; returns the number of bytes reclaimed
+    pub fn compact(&mut self) -> usize {
+        let before = self.count_bytes();
+
+        self.chunks.shrink_to_fit();
+        for chunk in &mut self.chunks {
+            chunk.id.shrink_to_fit();
+            chunk.content.shrink_to_fit();
+            if let Some(embedding) = chunk.embedding.as_mut() {
+                embedding.shrink_to_fit();
+            }
+        }
+        self.half_embeddings.shrink_to_fit();
+        for embedding in self.half_embeddings.iter_mut().flatten() {
+            embedding.shrink_to_fit();
+        }
+        self.refresh_signatures();
+
+        let reclaimed = before.saturating_sub(self.count_bytes());
+        log::info!("Compacted vector database, reclaimed {} bytes", reclaimed);
+        reclaimed
+    }
+
+    /// Aggregate diagnostics about stored chunks and embeddings
+    pub fn stats(&self) -> VectorDbStats {
+        let mut dimension_counts: HashMap<usize, usize> = HashMap::new();
+        let mut norm_sum = 0.0f64;
+        let mut num_embedded = 0;
+
+        for embedding in (0..self.chunks.len()).filter_map(|i| self.embedding_at(i)) {
+            *dimension_counts.entry(embedding.len()).or_insert(0) += 1;
+            norm_sum += embedding.iter().map(|v| (v * v) as f64).sum::<f64>().sqrt();
+            num_embedded += 1;
+        }
+
+        // The most common dimension is taken as the expected one
+        let embedding_dim = dimension_counts
+            .iter()
+            .max_by_key(|&(dim, count)| (*count, std::cmp::Reverse(*dim)))
+            .map(|(&dim, _)| dim);
+        let num_mismatched_dimensions = embedding_dim
+            .map_or(0, |dim| num_embedded - dimension_counts[&dim]);
+
+        if num_mismatched_dimensions > 0 {
+            log::warn!(
+                "{} chunks have embeddings with a dimension other than {:?} (mixed embedding models?)",
+                num_mismatched_dimensions,
+                embedding_dim
+            );
+        }
+
+        VectorDbStats {
+            num_chunks: self.chunks.len(),
+            num_documents: self.get_document_ids().len(),
+            embedding_dim,
+            num_mismatched_dimensions,
+            num_missing_embeddings: self.chunks.len() - num_embedded,
+            mean_norm: if num_embedded > 0 {
+                (norm_sum / num_embedded as f64) as f32
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Clear all chunks, removing them from persistent storage too
     pub async fn clear(&mut self) -> Result<()> {
-        self.chunks.clear();
+        let ids: Vec<String> = self.chunks.drain(..).map(|chunk| chunk.id).collect();
+        self.half_embeddings.clear();
+        self.dirty.clear();
+        self.refresh_signatures();
+        self.version += 1;
+        self.persist_deletes(ids).await?;
         log::info!("Cleared vector database");
         Ok(())
     }
@@ -106,6 +933,36 @@ impl VectorDatabase {
         ids
     }
 
+    /// Chunks belonging to a document, in insertion order
+    ///
+    /// With `EmbeddingStorage::F16` the yielded chunks have no `embedding`.
+    pub fn document_chunks<'a>(&'a self, document_id: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.metadata.document_id == document_id)
+    }
+
+    /// All stored chunks, in insertion order
+    ///
+    /// With `EmbeddingStorage::F16` the yielded chunks have no `embedding`;
+    /// use `get_chunk` for a copy with the embedding decoded.
+    pub fn iter_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter()
+    }
+
+    /// Chunks grouped by document, ordered by document ID (as in
+    /// `get_document_ids`) and within a document by insertion
+    pub fn iter_documents(&self) -> impl Iterator<Item = (&str, Vec<&Chunk>)> {
+        let mut documents: BTreeMap<&str, Vec<&Chunk>> = BTreeMap::new();
+        for chunk in &self.chunks {
+            documents
+                .entry(chunk.metadata.document_id.as_str())
+                .or_default()
+                .push(chunk);
+        }
+        documents.into_iter()
+    }
+
     /// Get chunk count for a specific document
     pub fn count_by_document(&self, document_id: &str) -> usize {
         self.chunks
@@ -129,6 +986,32 @@ impl VectorDatabase {
     }
 }
 
+/// Number of differing bits between two binary signatures
+fn hamming_distance(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+/// Diagnostics returned by `VectorDatabase::stats`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct VectorDbStats {
+    pub num_chunks: usize,
+    pub num_documents: usize,
+    /// Most common embedding dimension (`None` if nothing is embedded)
+    pub embedding_dim: Option<usize>,
+    /// Embedded chunks whose dimension differs from `embedding_dim`
+    pub num_mismatched_dimensions: usize,
+    pub num_missing_embeddings: usize,
+    /// Mean L2 norm of the stored embeddings
+    pub mean_norm: f32,
+}
+
+impl VectorDbStats {
+    /// Whether embeddings of different dimensions are mixed
+    pub fn has_dimension_mismatch(&self) -> bool {
+        self.num_mismatched_dimensions > 0
+    }
+}
+
 impl Default for VectorDatabase {
     fn default() -> Self {
         Self::new()
@@ -154,7 +1037,10 @@ mod tests {
                 chunk_index: 0,
                 start_char: 0,
                 end_char: 11,
+                token_count: None,
                 created_at: "2025-01-01".to_string(),
+                parent_id: None,
+                extra: Default::default(),
             },
         };
 
@@ -168,7 +1054,10 @@ mod tests {
                 chunk_index: 1,
                 start_char: 12,
                 end_char: 25,
+                token_count: None,
                 created_at: "2025-01-01".to_string(),
+                parent_id: None,
+                extra: Default::default(),
             },
         };
 
@@ -183,4 +1072,450 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].chunk.id, "1");
     }
+
+    fn test_chunk(id: &str, document_id: &str, embedding: Vec<f32>) -> Chunk {
+        Chunk {
+            id: id.to_string(),
+            content: format!("Content of {}", id),
+            embedding: Some(embedding),
+            metadata: ChunkMetadata {
+                document_id: document_id.to_string(),
+                document_name: document_id.to_string(),
+                chunk_index: 0,
+                start_char: 0,
+                end_char: 0,
+                token_count: None,
+                created_at: "2025-01-01".to_string(),
+                parent_id: None,
+                extra: Default::default(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_chunk_dedup() {
+        let mut db = VectorDatabase::new();
+        db.add_chunk(test_chunk("1", "doc1", vec![1.0, 0.0, 0.0])).await.unwrap();
+
+        let duplicate = test_chunk("2", "doc2", vec![1.0, 0.0, 0.0]);
+        assert!(!db.add_chunk_dedup(duplicate, 0.99).await.unwrap());
+
+        let dissimilar = test_chunk("3", "doc2", vec![0.0, 1.0, 0.0]);
+        assert!(db.add_chunk_dedup(dissimilar, 0.99).await.unwrap());
+
+        assert_eq!(db.count(), 2);
+        assert_eq!(db.find_duplicates(&[1.0, 0.0, 0.0], 0.99).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_boosted() {
+        let mut db = VectorDatabase::new();
+        db.add_chunk(test_chunk("close", "doc1", vec![1.0, 0.1, 0.0])).await.unwrap();
+        db.add_chunk(test_chunk("farther", "doc2", vec![1.0, 0.5, 0.0])).await.unwrap();
+
+        let query = vec![1.0, 0.0, 0.0];
+        let results = db.search(&query, 1).await.unwrap();
+        assert_eq!(results[0].chunk.id, "close");
+
+        let boosts = HashMap::from([("doc2".to_string(), 1.5)]);
+        let results = db.search_boosted(&query, 2, &boosts).await.unwrap();
+        assert_eq!(results[0].chunk.id, "farther");
+        assert_eq!(results[1].chunk.id, "close");
+
+        for invalid in [f32::NAN, f32::INFINITY, -1.0] {
+            let boosts = HashMap::from([("doc2".to_string(), invalid)]);
+            assert!(db.search_boosted(&query, 2, &boosts).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_embeddings() {
+        let mut db = VectorDatabase::new();
+        for i in 0..40 {
+            db.add_chunk(test_chunk(&i.to_string(), "doc1", vec![1.0, 0.0, 0.0])).await.unwrap();
+        }
+
+        let model = EmbeddingModel::new("new-model".to_string());
+        let mut progress = Vec::new();
+        let rebuilt = db
+            .rebuild_embeddings(&model, |done, total| progress.push((done, total)))
+            .await
+            .unwrap();
+
+        assert_eq!(rebuilt, 40);
+        assert_eq!(progress, vec![(32, 40), (40, 40)]);
+        assert!(db
+            .chunks
+            .iter()
+            .all(|c| c.embedding.as_ref().unwrap().len() == model.dimension()));
+
+        let query = model.embed("query").await.unwrap();
+        let results = db.search(&query, 5).await.unwrap();
+        assert_eq!(results.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_binary_prefilter_recall() {
+        // Four clusters, each in its own orthant of a 16-dim space
+        let centers: Vec<Vec<f32>> = (0..4)
+            .map(|c| (0..16).map(|d| if (d / 4) == c { 1.0 } else { -1.0 }).collect())
+            .collect();
+
+        let mut seed: u32 = 42;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 16) as f32 / 65536.0 * 0.4 - 0.2
+        };
+
+        let mut brute = VectorDatabase::new();
+        let mut two_stage = VectorDatabase::new().with_binary_prefilter(60);
+        for i in 0..200 {
+            let embedding: Vec<f32> = centers[i % 4].iter().map(|v| v + noise()).collect();
+            let chunk = test_chunk(&i.to_string(), "doc1", embedding);
+            brute.add_chunk(chunk.clone()).await.unwrap();
+            two_stage.add_chunk(chunk).await.unwrap();
+        }
+
+        let query: Vec<f32> = centers[2].iter().map(|v| v * 0.9).collect();
+        let expected = brute.search(&query, 5).await.unwrap();
+        let actual = two_stage.search(&query, 5).await.unwrap();
+
+        let ids = |results: &[SearchResult]| -> Vec<String> {
+            results.iter().map(|r| r.chunk.id.clone()).collect()
+        };
+        assert_eq!(ids(&actual), ids(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_stats_flags_mismatched_dimensions() {
+        let mut db = VectorDatabase::new();
+        db.add_chunk(test_chunk("1", "doc1", vec![3.0, 4.0, 0.0])).await.unwrap();
+        db.add_chunk(test_chunk("2", "doc1", vec![0.0, 0.0, 5.0])).await.unwrap();
+        db.add_chunk(test_chunk("3", "doc2", vec![1.0, 0.0])).await.unwrap();
+
+        let mut missing = test_chunk("4", "doc3", vec![]);
+        missing.embedding = None;
+        db.add_chunk(missing).await.unwrap();
+
+        let stats = db.stats();
+        assert_eq!(stats.num_chunks, 4);
+        assert_eq!(stats.num_documents, 3);
+        assert_eq!(stats.embedding_dim, Some(3));
+        assert_eq!(stats.num_mismatched_dimensions, 1);
+        assert!(stats.has_dimension_mismatch());
+        assert_eq!(stats.num_missing_embeddings, 1);
+        assert!((stats.mean_norm - 11.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_compact_after_deletions() {
+        let mut db = VectorDatabase::new().with_binary_prefilter(4);
+        for i in 0..200 {
+            let document = if i < 190 { "stale" } else { "kept" };
+            let embedding = vec![(i % 7) as f32, 1.0, (i % 3) as f32];
+            db.add_chunk(test_chunk(&format!("c{}", i), document, embedding)).await.unwrap();
+        }
+
+        let query = vec![6.0, 1.0, 0.0];
+        db.delete_by_document("stale").await.unwrap();
+        let expected = db.search(&query, 3).await.unwrap();
+        let before = db.count_bytes();
+
+        let reclaimed = db.compact();
+
+        assert!(reclaimed > 0);
+        assert_eq!(db.count_bytes(), before - reclaimed);
+        assert!(db.count_bytes() < before / 4);
+
+        let results = db.search(&query, 3).await.unwrap();
+        let ids = |r: &[SearchResult]| r.iter().map(|r| r.chunk.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&results), ids(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_normalized_scores_per_metric() {
+        let query = vec![1.0, 0.5];
+        let embeddings = [vec![1.0, 0.4], vec![0.2, 1.0], vec![-3.0, -1.0], vec![4.0, 2.5]];
+
+        for metric in [
+            SimilarityMetric::Cosine,
+            SimilarityMetric::Euclidean,
+            SimilarityMetric::DotProduct,
+        ] {
+            let mut raw_db = VectorDatabase::new().with_metric(metric);
+            for (i, embedding) in embeddings.iter().enumerate() {
+                raw_db
+                    .add_chunk(test_chunk(&i.to_string(), "doc1", embedding.clone()))
+                    .await
+                    .unwrap();
+            }
+            let normalized_db = raw_db.clone().with_normalized_scores(true);
+
+            let raw = raw_db.search(&query, 4).await.unwrap();
+            let normalized = normalized_db.search(&query, 4).await.unwrap();
+
+            let ids = |results: &[SearchResult]| -> Vec<String> {
+                results.iter().map(|r| r.chunk.id.clone()).collect()
+            };
+            assert_eq!(ids(&raw), ids(&normalized), "{:?}", metric);
+            for result in &normalized {
+                assert!((0.0..=1.0).contains(&result.score), "{:?}: {}", metric, result.score);
+            }
+        }
+
+        assert!(SimilarityMetric::Euclidean.score(&query, &[4.0, 2.5]) < 0.0);
+        assert_eq!(SimilarityMetric::Euclidean.normalize(0.0), 1.0);
+        assert_eq!(SimilarityMetric::Cosine.normalize(-1.0), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_batched_flush_policy() {
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct RecordingStore {
+            batches: RefCell<Vec<Vec<String>>>,
+            deleted: RefCell<Vec<String>>,
+        }
+
+        #[async_trait(?Send)]
+        impl ChunkPersistence for RecordingStore {
+            async fn write_chunks(&self, chunks: &[Chunk]) -> Result<()> {
+                let ids = chunks.iter().map(|c| c.id.clone()).collect();
+                self.batches.borrow_mut().push(ids);
+                Ok(())
+            }
+
+            async fn delete_chunks(&self, ids: &[String]) -> Result<()> {
+                self.deleted.borrow_mut().extend_from_slice(ids);
+                Ok(())
+            }
+        }
+
+        let store = Rc::new(RecordingStore::default());
+        let mut db = VectorDatabase::new()
+            .with_persistence(store.clone(), FlushPolicy::Batched { every_n: 10 });
+
+        for i in 0..25 {
+            db.add_chunk(test_chunk(&i.to_string(), "doc1", vec![1.0, 0.0]))
+                .await
+                .unwrap();
+        }
+        assert_eq!(store.batches.borrow().len(), 2);
+        assert_eq!(db.dirty_count(), 5);
+
+        assert_eq!(db.flush().await.unwrap(), 5);
+        assert_eq!(db.dirty_count(), 0);
+        assert_eq!(db.flush().await.unwrap(), 0);
+
+        {
+            let batches = store.batches.borrow();
+            assert_eq!(batches.len(), 3);
+            let written: HashSet<&String> = batches.iter().flatten().collect();
+            assert_eq!(written.len(), 25);
+        }
+
+        // Deletes reach storage right away, even under a batched policy
+        db.add_chunk(test_chunk("other", "doc2", vec![0.0, 1.0])).await.unwrap();
+        assert_eq!(db.delete_by_document("doc2").await.unwrap(), 1);
+        assert_eq!(*store.deleted.borrow(), vec!["other".to_string()]);
+        assert_eq!(db.dirty_count(), 0);
+
+        db.clear().await.unwrap();
+        assert_eq!(store.deleted.borrow().len(), 26);
+    }
+
+    #[tokio::test]
+    async fn test_f16_embedding_storage() {
+        let embeddings: Vec<Vec<f32>> = (0..20)
+            .map(|i| (0..64).map(|j| (((i * 7 + j * 13) % 17) as f32 - 8.0) / 8.0).collect())
+            .collect();
+        let query = embeddings[3].clone();
+
+        let mut full = VectorDatabase::new();
+        let mut half = VectorDatabase::new().with_embedding_storage(EmbeddingStorage::F16);
+        for (i, embedding) in embeddings.iter().enumerate() {
+            let chunk = test_chunk(&i.to_string(), "doc1", embedding.clone());
+            full.add_chunk(chunk.clone()).await.unwrap();
+            half.add_chunk(chunk).await.unwrap();
+        }
+
+        let expected = full.search(&query, 5).await.unwrap();
+        let results = half.search(&query, 5).await.unwrap();
+        for (a, b) in expected.iter().zip(&results) {
+            assert_eq!(a.chunk.id, b.chunk.id);
+            assert!((a.score - b.score).abs() < 1e-3);
+        }
+        // Results carry the decoded embedding
+        assert_eq!(results[0].chunk.embedding.as_deref(), Some(&query[..]));
+        assert_eq!(half.stats().embedding_dim, Some(64));
+        assert!(half.count_bytes() < full.count_bytes());
+
+        // Converting an existing store keeps it searchable
+        let converted = full.with_embedding_storage(EmbeddingStorage::F16);
+        assert_eq!(converted.search(&query, 1).await.unwrap()[0].chunk.id, "3");
+        assert_eq!(half.delete_by_document("doc1").await.unwrap(), 20);
+        assert_eq!(half.stats().num_chunks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_early_exit() {
+        let mut db = VectorDatabase::new();
+        for i in 0..100 {
+            let angle = i as f32 * 0.01;
+            db.add_chunk(test_chunk(&i.to_string(), "doc1", vec![angle.cos(), angle.sin(), 0.0]))
+                .await
+                .unwrap();
+        }
+        // Near-identical to the query, planted early in the store
+        db.chunks[10].embedding = Some(vec![0.0, 0.001, 1.0]);
+
+        let query = [0.0, 0.0, 1.0];
+        let (results, scanned) = db.scan_early_exit(&query, 1, 0.99, 5);
+        assert_eq!(scanned, 11);
+        assert_eq!(results[0].chunk.id, "10");
+        assert_eq!(db.search(&query, 1).await.unwrap()[0].chunk.id, "10");
+
+        // `min_scanned` is always honoured; an unreachable threshold scans all
+        assert_eq!(db.scan_early_exit(&query, 1, 0.99, 50).1, 50);
+        let (results, scanned) = db.scan_early_exit(&query, 3, 1.5, 0);
+        assert_eq!(scanned, 100);
+        assert_eq!(results.len(), 3);
+
+        // NaN embeddings are skipped instead of panicking the sort
+        db.chunks[20].embedding = Some(vec![f32::NAN, 0.0, 1.0]);
+        let (results, _) = db.scan_early_exit(&query, 100, 1.5, 0);
+        assert_eq!(results.len(), 99);
+        assert_eq!(results[0].chunk.id, "10");
+    }
+
+    #[tokio::test]
+    async fn test_merge_skips_colliding_ids() {
+        let mut db = VectorDatabase::new().with_binary_prefilter(1);
+        db.add_chunk(test_chunk("a", "doc1", vec![1.0, 0.0])).await.unwrap();
+        db.add_chunk(test_chunk("shared", "doc1", vec![0.0, 1.0])).await.unwrap();
+
+        let mut other = VectorDatabase::new().with_embedding_storage(EmbeddingStorage::F16);
+        other.add_chunk(test_chunk("shared", "doc2", vec![1.0, 1.0])).await.unwrap();
+        other.add_chunk(test_chunk("b", "doc2", vec![-1.0, 0.0])).await.unwrap();
+
+        assert_eq!(db.merge(other).await.unwrap(), 1);
+        assert_eq!(db.count(), 3);
+
+        // The existing chunk wins the collision
+        assert_eq!(db.document_chunks("doc1").count(), 2);
+        assert_eq!(db.document_chunks("doc2").count(), 1);
+
+        // Merged chunks are searchable through the prefilter
+        let results = db.search(&[-1.0, 0.0], 1).await.unwrap();
+        assert_eq!(results[0].chunk.id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_normalization_check() {
+        let unit = test_chunk("1", "doc", vec![0.6, 0.8, 0.0]);
+        let unnormalized = test_chunk("2", "doc", vec![3.0, 4.0, 0.0]);
+
+        let mut db =
+            VectorDatabase::new().with_normalization_check(NormalizationCheck::Reject, 1e-3);
+        db.add_chunk(unit.clone()).await.unwrap();
+        let err = db.add_chunk(unnormalized.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("not normalized"));
+        assert_eq!(db.count(), 1);
+
+        let mut db = VectorDatabase::new().with_normalization_check(NormalizationCheck::Warn, 1e-3);
+        db.add_chunk(unnormalized.clone()).await.unwrap();
+        assert_eq!(db.count(), 1);
+
+        let mut db = VectorDatabase::new();
+        db.add_chunk(unnormalized).await.unwrap();
+        assert_eq!(db.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_filtered() {
+        let mut db = VectorDatabase::new().with_binary_prefilter(1);
+        let mut pdf = test_chunk("1", "manual", vec![1.0, 0.0, 0.0]);
+        pdf.metadata.extra.insert("file_type".to_string(), "pdf".to_string());
+        db.add_chunk(pdf).await.unwrap();
+        db.add_chunk(test_chunk("2", "notes", vec![0.9, 0.1, 0.0])).await.unwrap();
+        db.add_chunk(test_chunk("3", "notes", vec![0.0, 1.0, 0.0])).await.unwrap();
+
+        let query = [1.0, 0.0, 0.0];
+        let notes = db
+            .search_filtered(&query, 5, &MetadataFilter::documents(&["notes"]))
+            .await
+            .unwrap();
+        let ids: Vec<&str> = notes.iter().map(|r| r.chunk.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "3"]);
+
+        let pdfs = MetadataFilter::default().with_extra("file_type", "pdf");
+        let results = db.search_filtered(&query, 5, &pdfs).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunk.id, "1");
+
+        let none = MetadataFilter::documents(&["manual"]).with_extra("file_type", "txt");
+        assert!(db.search_filtered(&query, 5, &none).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_knn_for_chunk() {
+        let mut db = VectorDatabase::new();
+        db.add_chunk(test_chunk("a", "doc1", vec![1.0, 0.0, 0.0])).await.unwrap();
+        db.add_chunk(test_chunk("far", "doc1", vec![0.0, 0.0, 1.0])).await.unwrap();
+        db.add_chunk(test_chunk("near", "doc2", vec![0.9, 0.1, 0.0])).await.unwrap();
+        let mut empty = test_chunk("empty", "doc2", vec![]);
+        empty.embedding = None;
+        db.add_chunk(empty).await.unwrap();
+
+        let results = db.knn_for_chunk("a", 2).await.unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.chunk.id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "far"]);
+
+        assert!(db.knn_for_chunk("empty", 2).await.is_err());
+        assert!(db.knn_for_chunk("missing", 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dimension_reconciliation() {
+        let mut db = VectorDatabase::new().with_dimension_reconciliation(true);
+        let mut near = vec![0.0; 384];
+        near[0] = 1.0;
+        let mut far = vec![0.0; 384];
+        far[1] = 1.0;
+        db.add_chunk(test_chunk("near", "doc1", near)).await.unwrap();
+        db.add_chunk(test_chunk("far", "doc1", far)).await.unwrap();
+
+        // A query from a 512-d model; its extra dimensions are dropped
+        let mut query = vec![0.0; 512];
+        query[0] = 1.0;
+        query[400] = 1.0;
+        let results = db.search(&query, 2).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].chunk.id, "near");
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+
+        assert_eq!(reconcile_dimension(&[1.0, 2.0, 3.0], 2), vec![1.0, 2.0]);
+        assert_eq!(reconcile_dimension(&[1.0], 3), vec![1.0, 0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_iter_chunks_and_documents() {
+        let mut db = VectorDatabase::new();
+        for (id, document_id) in [("1", "b"), ("2", "a"), ("3", "b"), ("4", "c")] {
+            db.add_chunk(test_chunk(id, document_id, vec![1.0, 0.0])).await.unwrap();
+        }
+
+        let ids: Vec<&str> = db.iter_chunks().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3", "4"]);
+
+        let documents: Vec<(&str, Vec<&str>)> = db
+            .iter_documents()
+            .map(|(id, chunks)| (id, chunks.iter().map(|c| c.id.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            documents,
+            vec![("a", vec!["2"]), ("b", vec!["1", "3"]), ("c", vec!["4"])]
+        );
+    }
 }
@@ -1,6 +1,12 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+use crate::rag::{Chunk, ChunkPersistence};
+
+/// Object store holding vector database chunks, keyed by chunk ID
+pub const CHUNKS_STORE: &str = "chunks";
+
 /// IndexedDB storage wrapper using Rexie
 pub struct IndexedDbStorage {
     db_name: String,
@@ -94,6 +100,23 @@ impl IndexedDbStorage {
     }
 }
 
+#[async_trait(?Send)]
+impl ChunkPersistence for IndexedDbStorage {
+    async fn write_chunks(&self, chunks: &[Chunk]) -> Result<()> {
+        for chunk in chunks {
+            self.set(CHUNKS_STORE, &chunk.id, chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_chunks(&self, ids: &[String]) -> Result<()> {
+        for id in ids {
+            self.delete(CHUNKS_STORE, id).await?;
+        }
+        Ok(())
+    }
+}
+
 /// Storage quota information
 #[derive(Debug, Clone)]
 pub struct StorageQuota {
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, Request, RequestCredentials, RequestInit, RequestMode, Response};
+
+/// Error from a single fetch attempt
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("Fetch failed: {0}")]
+    Network(String),
+    #[error("HTTP error: {0}")]
+    Http(u16),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl FetchError {
+    /// Whether retrying the request may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Network(_) => true,
+            FetchError::Http(status) => *status >= 500 || *status == 408 || *status == 429,
+            FetchError::Other(_) => false,
+        }
+    }
+}
+
+/// Retry configuration for network requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled on each subsequent retry
+    pub backoff_ms: u32,
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (0-based)
+    pub fn delay_ms(&self, retry: u32) -> u32 {
+        self.backoff_ms.saturating_mul(1u32 << retry.min(16))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff_ms: 500,
+        }
+    }
+}
+
+/// Request mode (`RequestInit.mode`) for model file fetches
+///
+/// `no-cors` is not offered: its opaque responses cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FetchMode {
+    /// Allow cross-origin requests to servers that send CORS headers
+    #[default]
+    Cors,
+    /// Fail any request that leaves the page's origin
+    SameOrigin,
+}
+
+/// When cookies and HTTP auth are sent (`RequestInit.credentials`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FetchCredentials {
+    /// Never
+    Omit,
+    /// Only to the page's own origin (the browser default)
+    #[default]
+    SameOrigin,
+    /// Always, including cross-origin (the server must allow credentials)
+    Include,
+}
+
+/// Options for fetching model files
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchOptions {
+    /// Retry policy for transient failures
+    pub retry: RetryPolicy,
+    /// Extra request headers (e.g. `Authorization` for gated models)
+    pub headers: HashMap<String, String>,
+    /// Request mode
+    pub mode: FetchMode,
+    /// Credentials policy
+    pub credentials: FetchCredentials,
+}
+
+/// Destination for request mode and credentials
+///
+/// Implemented for `web_sys::RequestInit`; the counterpart of `HeaderSink`.
+pub trait RequestInitSink {
+    fn set_fetch_mode(&mut self, mode: FetchMode);
+    fn set_fetch_credentials(&mut self, credentials: FetchCredentials);
+}
+
+impl RequestInitSink for RequestInit {
+    fn set_fetch_mode(&mut self, mode: FetchMode) {
+        self.set_mode(match mode {
+            FetchMode::Cors => RequestMode::Cors,
+            FetchMode::SameOrigin => RequestMode::SameOrigin,
+        });
+    }
+
+    fn set_fetch_credentials(&mut self, credentials: FetchCredentials) {
+        self.set_credentials(match credentials {
+            FetchCredentials::Omit => RequestCredentials::Omit,
+            FetchCredentials::SameOrigin => RequestCredentials::SameOrigin,
+            FetchCredentials::Include => RequestCredentials::Include,
+        });
+    }
+}
+
+/// Apply the mode and credentials of `options` to `sink`
+pub fn apply_request_options(options: &FetchOptions, sink: &mut impl RequestInitSink) {
+    sink.set_fetch_mode(options.mode);
+    sink.set_fetch_credentials(options.credentials);
+}
+
+/// Destination for request headers
+///
+/// Implemented for `web_sys::Headers`; lets header handling be tested
+/// without a browser.
+pub trait HeaderSink {
+    fn set_header(&mut self, name: &str, value: &str) -> Result<(), FetchError>;
+}
+
+impl HeaderSink for Headers {
+    fn set_header(&mut self, name: &str, value: &str) -> Result<(), FetchError> {
+        self.set(name, value)
+            .map_err(|e| FetchError::Other(format!("Invalid header '{}': {:?}", name, e)))
+    }
+}
+
+/// Copy headers into `sink` in name order
+pub fn apply_headers(
+    headers: &HashMap<String, String>,
+    sink: &mut impl HeaderSink,
+) -> Result<(), FetchError> {
+    let mut names: Vec<&String> = headers.keys().collect();
+    names.sort();
+    for name in names {
+        sink.set_header(name, &headers[name])?;
+    }
+    Ok(())
+}
+
+/// Fetch a URL as bytes, retrying transient failures with exponential backoff
+pub async fn fetch_bytes(url: &str, options: &FetchOptions) -> Result<Vec<u8>, FetchError> {
+    retry_with_backoff(
+        &options.retry,
+        || fetch_bytes_once(url, options),
+        sleep_ms,
+    )
+    .await
+}
+
+/// Run `op` until it succeeds, fails permanently, or retries are exhausted
+///
+/// `sleep` is awaited between attempts with the backoff delay in
+/// milliseconds. Returns the last error when giving up.
+pub async fn retry_with_backoff<T, Op, OpFut, Sleep, SleepFut>(
+    policy: &RetryPolicy,
+    mut op: Op,
+    mut sleep: Sleep,
+) -> Result<T, FetchError>
+where
+    Op: FnMut() -> OpFut,
+    OpFut: Future<Output = Result<T, FetchError>>,
+    Sleep: FnMut(u32) -> SleepFut,
+    SleepFut: Future<Output = ()>,
+{
+    let mut retry = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if e.is_transient() && retry < policy.max_retries => {
+                let delay = policy.delay_ms(retry);
+                log::warn!(
+                    "{} - retrying in {}ms ({}/{})",
+                    e,
+                    delay,
+                    retry + 1,
+                    policy.max_retries
+                );
+                sleep(delay).await;
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Wait for the given number of milliseconds
+pub async fn sleep_ms(ms: u32) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let promise =
+            js_sys::Promise::new(&mut |resolve, _reject| super::set_timeout(&resolve, ms));
+        let _ = JsFuture::from(promise).await;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64));
+    }
+}
+
+/// Single fetch attempt
+async fn fetch_bytes_once(url: &str, options: &FetchOptions) -> Result<Vec<u8>, FetchError> {
+    let window =
+        web_sys::window().ok_or_else(|| FetchError::Other("No window object available".into()))?;
+
+    let mut opts = RequestInit::new();
+    opts.method("GET");
+    apply_request_options(options, &mut opts);
+
+    if !options.headers.is_empty() {
+        let mut request_headers = Headers::new()
+            .map_err(|e| FetchError::Other(format!("Failed to create headers: {:?}", e)))?;
+        apply_headers(&options.headers, &mut request_headers)?;
+        opts.set_headers(&request_headers);
+    }
+
+    let request = Request::new_with_str_and_init(url, &opts)
+        .map_err(|e| FetchError::Other(format!("Failed to create request: {:?}", e)))?;
+
+    let resp_value = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|e| FetchError::Network(format!("{:?}", e)))?;
+
+    let resp: Response = resp_value
+        .dyn_into()
+        .map_err(|e| FetchError::Other(format!("Response conversion failed: {:?}", e)))?;
+
+    if !resp.ok() {
+        return Err(FetchError::Http(resp.status()));
+    }
+
+    let array_buffer = JsFuture::from(
+        resp.array_buffer()
+            .map_err(|e| FetchError::Other(format!("array_buffer() failed: {:?}", e)))?,
+    )
+    .await
+    .map_err(|e| FetchError::Network(format!("array_buffer await failed: {:?}", e)))?;
+
+    Ok(js_sys::Uint8Array::new(&array_buffer).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[tokio::test]
+    async fn test_retry_succeeds_after_transient_failures() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff_ms: 100,
+        };
+        let attempts = RefCell::new(0);
+        let delays = RefCell::new(Vec::new());
+
+        let result = retry_with_backoff(
+            &policy,
+            || async {
+                *attempts.borrow_mut() += 1;
+                match *attempts.borrow() {
+                    1 => Err(FetchError::Network("connection reset".into())),
+                    2 => Err(FetchError::Http(503)),
+                    _ => Ok(vec![1u8, 2, 3]),
+                }
+            },
+            |ms| {
+                delays.borrow_mut().push(ms);
+                async {}
+            },
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), vec![1, 2, 3]);
+        assert_eq!(*attempts.borrow(), 3);
+        assert_eq!(*delays.borrow(), vec![100, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff_ms: 10,
+        };
+        let attempts = RefCell::new(0);
+
+        let result: Result<(), FetchError> = retry_with_backoff(
+            &policy,
+            || async {
+                *attempts.borrow_mut() += 1;
+                Err(FetchError::Http(500))
+            },
+            |_| async {},
+        )
+        .await;
+
+        assert!(matches!(result, Err(FetchError::Http(500))));
+        assert_eq!(*attempts.borrow(), 3);
+
+        // Permanent errors are not retried
+        *attempts.borrow_mut() = 0;
+        let result: Result<(), FetchError> = retry_with_backoff(
+            &policy,
+            || async {
+                *attempts.borrow_mut() += 1;
+                Err(FetchError::Http(404))
+            },
+            |_| async {},
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(*attempts.borrow(), 1);
+    }
+
+    #[test]
+    fn test_model_config_headers_applied() {
+        use crate::llm::ModelConfig;
+
+        struct RecordingHeaders(Vec<(String, String)>);
+
+        impl HeaderSink for RecordingHeaders {
+            fn set_header(&mut self, name: &str, value: &str) -> Result<(), FetchError> {
+                self.0.push((name.to_string(), value.to_string()));
+                Ok(())
+            }
+        }
+
+        let mut config = ModelConfig::default().with_auth_token("hf_secret");
+        config
+            .headers
+            .insert("X-Client".to_string(), "rust-wasm-llm".to_string());
+
+        let mut sink = RecordingHeaders(Vec::new());
+        apply_headers(&config.fetch_options().headers, &mut sink).unwrap();
+
+        assert_eq!(
+            sink.0,
+            vec![
+                ("Authorization".to_string(), "Bearer hf_secret".to_string()),
+                ("X-Client".to_string(), "rust-wasm-llm".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_model_config_mode_and_credentials_applied() {
+        use crate::llm::ModelConfig;
+
+        #[derive(Default)]
+        struct RecordingInit {
+            mode: Option<FetchMode>,
+            credentials: Option<FetchCredentials>,
+        }
+
+        impl RequestInitSink for RecordingInit {
+            fn set_fetch_mode(&mut self, mode: FetchMode) {
+                self.mode = Some(mode);
+            }
+
+            fn set_fetch_credentials(&mut self, credentials: FetchCredentials) {
+                self.credentials = Some(credentials);
+            }
+        }
+
+        let mut sink = RecordingInit::default();
+        apply_request_options(&ModelConfig::default().fetch_options(), &mut sink);
+        assert_eq!(sink.mode, Some(FetchMode::Cors));
+        assert_eq!(sink.credentials, Some(FetchCredentials::SameOrigin));
+
+        let config: ModelConfig = serde_json::from_value(serde_json::json!({
+            "model_url": "/models/phi.gguf",
+            "tokenizer_url": "/models/tokenizer.json",
+            "model_id": "phi",
+            "use_webgpu": false,
+            "quantization": "Q4",
+            "fetch_mode": "same-origin",
+            "credentials": "include"
+        }))
+        .unwrap();
+        apply_request_options(&config.fetch_options(), &mut sink);
+        assert_eq!(sink.mode, Some(FetchMode::SameOrigin));
+        assert_eq!(sink.credentials, Some(FetchCredentials::Include));
+    }
+}
@@ -1,34 +1,133 @@
+use std::io::BufRead;
+
 use anyhow::Result;
 
+use crate::error::LlmError;
+
+/// How text files with invalid UTF-8 are decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Decoding {
+    /// Replace invalid sequences with U+FFFD (`�`)
+    #[default]
+    Lossy,
+    /// Fail on the first invalid sequence
+    Strict,
+}
+
 /// File parser for different document types
 pub struct FileParser;
 
 impl FileParser {
     /// Parse a file based on its type
-    pub async fn parse(file_name: &str, content: &[u8]) -> Result<String> {
-        let extension = Self::get_extension(file_name);
+    ///
+    /// The type comes from the extension; unknown or missing extensions fall
+    /// back to `detect_type`, and content that clearly contradicts the
+    /// extension (e.g. a `.txt` starting with `%PDF`) wins over it. The
+    /// extracted text is sanitized (control characters removed, line endings
+    /// normalized to `\n`). `decoding` applies to text and HTML files.
+    pub async fn parse(
+        file_name: &str,
+        content: &[u8],
+        decoding: Utf8Decoding,
+    ) -> Result<String> {
+        let file_type = Self::resolve_type(file_name, content);
 
-        match extension.as_str() {
-            "txt" | "md" => Self::parse_text(content),
+        let text = match file_type.as_str() {
+            "txt" | "md" => Self::parse_text(content, decoding),
             "pdf" => Self::parse_pdf(content).await,
             "docx" => Self::parse_docx(content).await,
-            "html" | "htm" => Self::parse_html(content),
-            _ => Err(anyhow::anyhow!("Unsupported file type: {}", extension)),
+            "html" | "htm" => Self::parse_html(content, decoding),
+            _ => Err(LlmError::Parse(format!("Unsupported file type: {}", file_type)).into()),
+        }?;
+
+        Ok(super::text::sanitize(&text))
+    }
+
+    /// Choose the parser type from the extension and the content
+    pub fn resolve_type(file_name: &str, content: &[u8]) -> String {
+        let extension = Self::get_extension(file_name);
+        let detected = Self::detect_type(content);
+
+        let declared = match extension.as_str() {
+            "txt" | "md" => "txt",
+            "htm" | "html" => "html",
+            "pdf" | "docx" => extension.as_str(),
+            _ => return detected,
+        };
+
+        // Plain text is the detection fallback, so it never contradicts
+        if detected != "txt" && detected != declared {
+            log::warn!(
+                "{} has a .{} extension but its content looks like {}; parsing by content",
+                file_name,
+                extension,
+                detected
+            );
+            return detected;
         }
+        extension
     }
 
-    /// Get file extension
+    /// Get file extension (empty when there is none)
     fn get_extension(file_name: &str) -> String {
         file_name
-            .rsplit('.')
-            .next()
-            .unwrap_or("")
-            .to_lowercase()
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default()
     }
 
     /// Parse plain text
-    fn parse_text(content: &[u8]) -> Result<String> {
-        Ok(String::from_utf8(content.to_vec())?)
+    fn parse_text(content: &[u8], decoding: Utf8Decoding) -> Result<String> {
+        match decoding {
+            Utf8Decoding::Lossy => Ok(String::from_utf8_lossy(content).into_owned()),
+            Utf8Decoding::Strict => String::from_utf8(content.to_vec())
+                .map_err(|e| LlmError::Parse(format!("Invalid UTF-8 text: {}", e)).into()),
+        }
+    }
+
+    /// Read plain text in groups of `lines_per_group` lines
+    ///
+    /// Lines inside a group are joined with `\n` and the trailing `\n` of
+    /// the group is dropped, so joining the groups with `\n` gives the same
+    /// text as a full lossy parse; the groups can be fed straight to
+    /// `RagPipeline::index_stream`. Only one group is held in memory.
+    pub fn parse_text_stream<R: BufRead>(
+        mut reader: R,
+        lines_per_group: usize,
+    ) -> impl Iterator<Item = Result<String>> {
+        let lines_per_group = lines_per_group.max(1);
+        let mut line = Vec::new();
+        let mut done = false;
+
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+
+            let mut group = String::new();
+            for i in 0..lines_per_group {
+                line.clear();
+                if let Err(e) = reader.read_until(b'\n', &mut line) {
+                    done = true;
+                    return Some(Err(LlmError::Parse(format!("Failed to read text: {}", e)).into()));
+                }
+
+                // A line without `\n` is the last one
+                let complete = line.last() == Some(&b'\n');
+                if complete {
+                    line.pop();
+                }
+                if i > 0 {
+                    group.push('\n');
+                }
+                group.push_str(&String::from_utf8_lossy(&line));
+                if !complete {
+                    done = true;
+                    break;
+                }
+            }
+            Some(Ok(group))
+        })
     }
 
     /// Parse PDF (TODO: integrate pdf.js or similar)
@@ -44,8 +143,8 @@ impl FileParser {
     }
 
     /// Parse HTML (basic text extraction)
-    fn parse_html(content: &[u8]) -> Result<String> {
-        let html = String::from_utf8(content.to_vec())?;
+    fn parse_html(content: &[u8], decoding: Utf8Decoding) -> Result<String> {
+        let html = Self::parse_text(content, decoding)?;
 
         // TODO: Implement proper HTML parsing
         // For now, just remove tags
@@ -66,12 +165,20 @@ impl FileParser {
             "pdf".to_string()
         } else if content.starts_with(b"PK") {
             "docx".to_string() // DOCX is a zip file
-        } else if content.starts_with(b"<html") || content.starts_with(b"<!DOCTYPE") {
+        } else if Self::looks_like_html(content) {
             "html".to_string()
         } else {
             "txt".to_string()
         }
     }
+
+    /// Whether content starts (after whitespace) with an HTML root or doctype
+    fn looks_like_html(content: &[u8]) -> bool {
+        let start = content.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(0);
+        let head = &content[start..content.len().min(start + 14)];
+        let head = head.to_ascii_lowercase();
+        head.starts_with(b"<html") || head.starts_with(b"<!doctype html")
+    }
 }
 
 #[cfg(test)]
@@ -81,7 +188,7 @@ mod tests {
     #[test]
     fn test_parse_text() {
         let content = b"Hello, world!";
-        let result = FileParser::parse_text(content).unwrap();
+        let result = FileParser::parse_text(content, Utf8Decoding::Strict).unwrap();
         assert_eq!(result, "Hello, world!");
     }
 
@@ -99,4 +206,57 @@ mod tests {
         assert_eq!(FileParser::detect_type(b"<html>"), "html");
         assert_eq!(FileParser::detect_type(b"Plain text"), "txt");
     }
+
+    #[test]
+    fn test_resolve_type_prefers_content() {
+        assert_eq!(FileParser::get_extension("README"), "");
+        assert_eq!(FileParser::resolve_type("upload.bin", b"%PDF-1.7"), "pdf");
+        assert_eq!(FileParser::resolve_type("notes.txt", b"%PDF-1.7"), "pdf");
+        assert_eq!(FileParser::resolve_type("notes.md", b"# Title"), "md");
+        assert_eq!(FileParser::resolve_type("page.htm", b"<html>"), "htm");
+    }
+
+    #[tokio::test]
+    async fn test_parse_sniffs_unknown_extensions() {
+        let err = FileParser::parse("upload.bin", b"%PDF-1.7 binary", Utf8Decoding::Lossy)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("PDF parsing"));
+
+        let html = b"  <!DOCTYPE html><html><body>Hello</body><script>track()</script>";
+        let text = FileParser::parse("blob", html, Utf8Decoding::Lossy).await.unwrap();
+        assert!(text.contains("Hello"));
+        assert!(!text.contains("track()"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_decoding_modes() {
+        let content = b"caf\xe9 au lait";
+
+        let text = FileParser::parse("notes.txt", content, Utf8Decoding::Lossy).await.unwrap();
+        assert_eq!(text, "caf\u{FFFD} au lait");
+
+        let err = FileParser::parse("notes.txt", content, Utf8Decoding::Strict)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid UTF-8"));
+    }
+
+    #[test]
+    fn test_parse_text_stream_matches_full_parse() {
+        let content = "first line\nsecond\n\nthird: café\r\nfourth\nfifth\n".as_bytes();
+        let full = FileParser::parse_text(content, Utf8Decoding::Lossy).unwrap();
+
+        for lines_per_group in [1, 2, 3, 100] {
+            let groups: Vec<String> = FileParser::parse_text_stream(content, lines_per_group)
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(groups.join("\n"), full);
+        }
+
+        let groups: Vec<String> = FileParser::parse_text_stream(&b"a\nb"[..], 2)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(groups, vec!["a\nb"]);
+    }
 }
@@ -0,0 +1,36 @@
+// Stable content hashing shared by chunk IDs, deduplication and caches
+
+/// 64-bit FNV-1a hash (stable across platforms and Rust versions)
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// Content address of `bytes` as 16 lowercase hex digits
+///
+/// Not cryptographic: use it to recognize identical content, not to
+/// protect against deliberate collisions.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:016x}", fnv1a_64(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash_is_stable() {
+        // Published FNV-1a 64 test vectors
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_eq!(content_hash(b"foobar"), "85944171f73967e8");
+
+        assert_eq!(content_hash(b"same text"), content_hash(b"same text"));
+        assert_ne!(content_hash(b"same text"), content_hash(b"same text."));
+        assert_eq!(content_hash(b"x").len(), 16);
+    }
+}
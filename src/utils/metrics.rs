@@ -0,0 +1,150 @@
+// Timing and throughput telemetry for a single generation
+
+use serde::Serialize;
+
+use super::now_ms;
+
+/// Accumulates timing for one generation
+///
+/// Starts timing on creation (or `reset`); the generation loop calls
+/// `record_token` for each token and `finish` when done.
+pub struct GenerationMetrics {
+    clock: Box<dyn Fn() -> f64>,
+    start_ms: f64,
+    first_token_ms: Option<f64>,
+    end_ms: Option<f64>,
+    tokens: usize,
+}
+
+/// Serializable summary of `GenerationMetrics`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    /// Milliseconds until the first token (None if none was generated)
+    pub time_to_first_token_ms: Option<f64>,
+    pub total_time_ms: f64,
+    pub tokens_generated: usize,
+    pub tokens_per_sec: f64,
+}
+
+impl GenerationMetrics {
+    /// Metrics timed with `Date.now()`
+    pub fn new() -> Self {
+        Self::with_clock(now_ms)
+    }
+
+    /// Metrics using a custom millisecond clock
+    pub fn with_clock(clock: impl Fn() -> f64 + 'static) -> Self {
+        let start_ms = clock();
+        Self {
+            clock: Box::new(clock),
+            start_ms,
+            first_token_ms: None,
+            end_ms: None,
+            tokens: 0,
+        }
+    }
+
+    /// Clear all measurements and restart timing now
+    pub fn reset(&mut self) {
+        self.start_ms = (self.clock)();
+        self.first_token_ms = None;
+        self.end_ms = None;
+        self.tokens = 0;
+    }
+
+    /// Record one generated token
+    pub fn record_token(&mut self) {
+        let now = (self.clock)();
+        self.first_token_ms.get_or_insert(now);
+        self.tokens += 1;
+    }
+
+    /// Stop timing; later calls keep the first end time
+    pub fn finish(&mut self) {
+        if self.end_ms.is_none() {
+            self.end_ms = Some((self.clock)());
+        }
+    }
+
+    /// Milliseconds from the start to the first token
+    pub fn time_to_first_token_ms(&self) -> Option<f64> {
+        self.first_token_ms.map(|first| first - self.start_ms)
+    }
+
+    /// Milliseconds from the start to `finish` (or to now while running)
+    pub fn total_time_ms(&self) -> f64 {
+        self.end_ms.unwrap_or_else(|| (self.clock)()) - self.start_ms
+    }
+
+    /// Number of tokens recorded
+    pub fn tokens(&self) -> usize {
+        self.tokens
+    }
+
+    /// Tokens per second over the total time
+    pub fn tokens_per_sec(&self) -> f64 {
+        let total_ms = self.total_time_ms();
+        if total_ms > 0.0 {
+            self.tokens as f64 * 1000.0 / total_ms
+        } else {
+            0.0
+        }
+    }
+
+    /// Current measurements in serializable form
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            time_to_first_token_ms: self.time_to_first_token_ms(),
+            total_time_ms: self.total_time_ms(),
+            tokens_generated: self.tokens,
+            tokens_per_sec: self.tokens_per_sec(),
+        }
+    }
+}
+
+impl Default for GenerationMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_ttft_and_throughput() {
+        let clock = Rc::new(Cell::new(1000.0));
+        let time = Rc::clone(&clock);
+        let mut metrics = GenerationMetrics::with_clock(move || time.get());
+
+        // 300ms to the first token, then 50ms per token
+        clock.set(1300.0);
+        metrics.record_token();
+        for _ in 0..9 {
+            clock.set(clock.get() + 50.0);
+            metrics.record_token();
+        }
+        metrics.finish();
+        clock.set(9000.0);
+
+        assert_eq!(
+            metrics.snapshot(),
+            MetricsSnapshot {
+                time_to_first_token_ms: Some(300.0),
+                total_time_ms: 750.0,
+                tokens_generated: 10,
+                tokens_per_sec: 10.0 * 1000.0 / 750.0,
+            }
+        );
+
+        metrics.reset();
+        assert_eq!(metrics.tokens(), 0);
+        assert_eq!(metrics.time_to_first_token_ms(), None);
+        clock.set(9100.0);
+        assert_eq!(metrics.total_time_ms(), 100.0);
+        assert_eq!(metrics.tokens_per_sec(), 0.0);
+    }
+}
@@ -1,10 +1,18 @@
 // Utility functions and helpers
 
+pub mod fetch;
 pub mod file_parser;
+pub mod hash;
+pub mod metrics;
 pub mod quantization;
+pub mod text;
+pub mod time;
 
-pub use file_parser::FileParser;
+pub use file_parser::{FileParser, Utf8Decoding};
+pub use hash::content_hash;
+pub use metrics::{GenerationMetrics, MetricsSnapshot};
 pub use quantization::Quantizer;
+pub use time::{current_timestamp, parse_timestamp};
 
 /// Generate a unique ID
 pub fn generate_id() -> String {
@@ -18,11 +26,54 @@ pub fn generate_id() -> String {
     format!("id_{}", timestamp)
 }
 
-/// Get current ISO timestamp
-pub fn current_timestamp() -> String {
-    // TODO: Use proper timestamp
-    // For now, use a placeholder
-    "2025-01-01T00:00:00Z".to_string()
+/// Milliseconds since the Unix epoch (`Date.now()` in the browser)
+pub fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .unwrap_or(0.0)
+    }
+}
+
+/// Let the browser event loop run (zero-delay `setTimeout`; no-op natively)
+pub async fn yield_now() {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let promise = js_sys::Promise::new(&mut |resolve, _reject| set_timeout(&resolve, 0));
+        let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+    }
+}
+
+/// Call `callback` after `ms` milliseconds using the global `setTimeout`
+///
+/// Works on the main thread and in workers (which have no `window`); if no
+/// `setTimeout` is available the callback runs immediately, so awaiting
+/// code never hangs.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn set_timeout(callback: &js_sys::Function, ms: u32) {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    let global = js_sys::global();
+    let scheduled = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))
+        .ok()
+        .and_then(|f| f.dyn_into::<js_sys::Function>().ok())
+        .is_some_and(|set_timeout| {
+            set_timeout
+                .call2(&global, callback, &JsValue::from(ms))
+                .is_ok()
+        });
+    if !scheduled {
+        let _ = callback.call0(&JsValue::NULL);
+    }
 }
 
 /// Format file size in human-readable format
@@ -1,3 +1,9 @@
+/// Values per block of `Quantizer::quantize_int4` (as in GGUF's Q4_0)
+pub const INT4_BLOCK_SIZE: usize = 32;
+
+/// Bytes per int4 block: an f16 scale plus two values per byte
+const INT4_BLOCK_BYTES: usize = 2 + INT4_BLOCK_SIZE / 2;
+
 /// Quantization utilities for reducing memory usage
 pub struct Quantizer;
 
@@ -14,6 +20,80 @@ impl Quantizer {
         data.iter().map(|&v| v as f32 / 127.0).collect()
     }
 
+    /// Quantize to int8 with one absmax scale per block of `block_size`
+    /// values (as in GGUF's Q8_0)
+    ///
+    /// Returns the quantized values and the per-block scales. Small-magnitude
+    /// blocks keep their precision instead of sharing one global range.
+    pub fn quantize_blocks(data: &[f32], block_size: usize) -> (Vec<i8>, Vec<f32>) {
+        let mut values = Vec::with_capacity(data.len());
+        let mut scales = Vec::with_capacity(data.len().div_ceil(block_size.max(1)));
+
+        for block in data.chunks(block_size.max(1)) {
+            let absmax = block.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+            let scale = absmax / 127.0;
+            let inverse = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+            values.extend(block.iter().map(|&v| (v * inverse).round().clamp(-127.0, 127.0) as i8));
+            scales.push(scale);
+        }
+
+        (values, scales)
+    }
+
+    /// Reverse `quantize_blocks` with the same `block_size`
+    pub fn dequantize_blocks(data: &[i8], scales: &[f32], block_size: usize) -> Vec<f32> {
+        data.chunks(block_size.max(1))
+            .zip(scales)
+            .flat_map(|(block, &scale)| block.iter().map(move |&v| v as f32 * scale))
+            .collect()
+    }
+
+    /// Quantize to 4 bits per value, packed two per byte
+    ///
+    /// Each block of `INT4_BLOCK_SIZE` values is stored as its f16 scale
+    /// (little-endian) followed by the values as nibbles offset by 8, the
+    /// earlier value in the low nibble. The last block is zero-padded.
+    pub fn quantize_int4(data: &[f32]) -> Vec<u8> {
+        let num_blocks = data.len().div_ceil(INT4_BLOCK_SIZE);
+        let mut packed = Vec::with_capacity(num_blocks * INT4_BLOCK_BYTES);
+
+        for block in data.chunks(INT4_BLOCK_SIZE) {
+            let absmax = block.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+            // Round the scale through f16 so encoding uses the stored value
+            let scale = f16_to_f32(f32_to_f16(absmax / 7.0));
+            let inverse = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+
+            packed.extend_from_slice(&f32_to_f16(scale).to_le_bytes());
+            let nibble = |v: Option<&f32>| {
+                let v = v.copied().unwrap_or(0.0);
+                ((v * inverse).round().clamp(-8.0, 7.0) as i8 + 8) as u8
+            };
+            for i in (0..INT4_BLOCK_SIZE).step_by(2) {
+                packed.push(nibble(block.get(i)) | (nibble(block.get(i + 1)) << 4));
+            }
+        }
+
+        packed
+    }
+
+    /// Unpack the first `original_length` values of `quantize_int4` output
+    pub fn dequantize_int4(data: &[u8], original_length: usize) -> Vec<f32> {
+        let mut result = Vec::with_capacity(original_length);
+
+        for block in data.chunks_exact(INT4_BLOCK_BYTES) {
+            let scale = f16_to_f32(u16::from_le_bytes([block[0], block[1]]));
+            for &byte in &block[2..] {
+                for nibble in [byte & 0x0f, byte >> 4] {
+                    if result.len() < original_length {
+                        result.push((nibble as i8 - 8) as f32 * scale);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
     /// Quantize f32 vector to uint8 (0-255)
     pub fn quantize_uint8(data: &[f32]) -> Vec<u8> {
         // Assume data is normalized to [-1, 1]
@@ -77,6 +157,16 @@ impl Quantizer {
         result
     }
 
+    /// Convert f32 values to IEEE-754 half precision bits
+    pub fn quantize_f16(data: &[f32]) -> Vec<u16> {
+        data.iter().map(|&v| f32_to_f16(v)).collect()
+    }
+
+    /// Convert IEEE-754 half precision bits back to f32
+    pub fn dequantize_f16(data: &[u16]) -> Vec<f32> {
+        data.iter().map(|&h| f16_to_f32(h)).collect()
+    }
+
     /// Calculate compression ratio
     pub fn compression_ratio(original_size: usize, compressed_size: usize) -> f64 {
         original_size as f64 / compressed_size as f64
@@ -88,6 +178,72 @@ impl Quantizer {
     }
 }
 
+/// Round `value` to the nearest f16 (ties to even)
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinite; NaN keeps a quiet payload bit
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Subnormal half (or zero): shift the implicit bit into the mantissa
+        if half_exponent < -10 {
+            return sign;
+        }
+        let shift = (14 - half_exponent) as u32;
+        return sign | round_shift(mantissa | 0x0080_0000, shift) as u16;
+    }
+
+    // A mantissa that rounds up carries into the exponent, as it should
+    sign | (((half_exponent as u32) << 10) + round_shift(mantissa, 13)) as u16
+}
+
+/// `value >> shift`, rounded to nearest with ties to even
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let truncated = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let midpoint = 1 << (shift - 1);
+    if remainder > midpoint || (remainder == midpoint && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+/// Widen f16 bits to f32 (exact)
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x03ff) as u32;
+
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        0 => {
+            // Subnormal: normalize so the leading bit becomes implicit
+            let mut exponent = 127 - 14;
+            let mut mantissa = mantissa;
+            while mantissa & 0x0400 == 0 {
+                mantissa <<= 1;
+                exponent -= 1;
+            }
+            sign | (exponent << 23) | ((mantissa & 0x03ff) << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -125,4 +281,96 @@ mod tests {
         assert_eq!(Quantizer::compression_ratio(original, compressed), 4.0);
         assert_eq!(Quantizer::size_reduction(original, compressed), 75.0);
     }
+
+    #[test]
+    fn test_f16_round_trip() {
+        let bits = Quantizer::quantize_f16(&[1.0, -2.0, 65504.0, 1e6, 6.0e-8, 1e-9]);
+        assert_eq!(bits, vec![0x3c00, 0xc000, 0x7bff, 0x7c00, 0x0001, 0x0000]);
+        assert_eq!(Quantizer::dequantize_f16(&bits[..3]), vec![1.0, -2.0, 65504.0]);
+        assert!(Quantizer::dequantize_f16(&[0x7e00])[0].is_nan());
+
+        // A unit-norm embedding: f16 error is far below int8's
+        let dim = 384;
+        let raw: Vec<f32> = (0..dim).map(|i| ((i * 37 % 101) as f32 - 50.0) / 50.0).collect();
+        let norm = raw.iter().map(|v| v * v).sum::<f32>().sqrt();
+        let embedding: Vec<f32> = raw.iter().map(|v| v / norm).collect();
+
+        let max_error = |decoded: Vec<f32>| {
+            embedding
+                .iter()
+                .zip(decoded)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let f16_error = max_error(Quantizer::dequantize_f16(&Quantizer::quantize_f16(&embedding)));
+        let int8_error =
+            max_error(Quantizer::dequantize_int8(&Quantizer::quantize_int8(&embedding)));
+        assert!(f16_error * 10.0 < int8_error, "f16 {} vs int8 {}", f16_error, int8_error);
+    }
+
+    #[test]
+    fn test_block_quantization() {
+        // Blocks whose magnitudes range from 0.001 to 10
+        let data: Vec<f32> = (0..128)
+            .map(|i| {
+                let magnitude = [0.001, 0.05, 1.0, 10.0][i / 32];
+                magnitude * ((i * 37 % 29) as f32 - 14.0) / 14.0
+            })
+            .collect();
+
+        let error = |block_size: usize| {
+            let (values, scales) = Quantizer::quantize_blocks(&data, block_size);
+            assert_eq!(scales.len(), data.len().div_ceil(block_size));
+            let decoded = Quantizer::dequantize_blocks(&values, &scales, block_size);
+            assert_eq!(decoded.len(), data.len());
+            data.iter().zip(decoded).map(|(a, b)| (a - b).powi(2)).sum::<f32>()
+        };
+
+        // One block spanning the whole vector is a single global scale
+        let global = error(data.len());
+        let blocked = error(32);
+        assert!(blocked * 2.0 < global, "blocked {} vs global {}", blocked, global);
+
+        // Small blocks keep their relative precision
+        let (values, scales) = Quantizer::quantize_blocks(&data, 32);
+        let decoded = Quantizer::dequantize_blocks(&values, &scales, 32);
+        for (a, b) in data[..32].iter().zip(&decoded) {
+            assert!((a - b).abs() <= 0.001 / 127.0);
+        }
+
+        let (values, scales) = Quantizer::quantize_blocks(&[0.0; 5], 4);
+        assert_eq!(values, vec![0; 5]);
+        assert_eq!(scales, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_int4_round_trip() {
+        // Multiples of the scale (7 / 7 = 1) survive exactly
+        let exact: Vec<f32> = (-7..=7).map(|v| v as f32).collect();
+        let packed = Quantizer::quantize_int4(&exact);
+        assert_eq!(packed.len(), INT4_BLOCK_BYTES);
+        assert_eq!(u16::from_le_bytes([packed[0], packed[1]]), 0x3c00);
+        // -7 and -6 offset by 8, low nibble first; padding encodes 0 as 8
+        assert_eq!(packed[2], 0x21);
+        assert_eq!(packed[INT4_BLOCK_BYTES - 1], 0x88);
+        assert_eq!(Quantizer::dequantize_int4(&packed, exact.len()), exact);
+
+        // Error stays within half a step of each block's own scale
+        let data: Vec<f32> = (0..100)
+            .map(|i| {
+                let magnitude = if i < 64 { 0.01 } else { 3.0 };
+                magnitude * ((i * 37 % 29) as f32 - 14.0) / 14.0
+            })
+            .collect();
+        let packed = Quantizer::quantize_int4(&data);
+        assert_eq!(packed.len(), 4 * INT4_BLOCK_BYTES);
+        let decoded = Quantizer::dequantize_int4(&packed, data.len());
+        assert_eq!(decoded.len(), data.len());
+        for (block, decoded) in data.chunks(INT4_BLOCK_SIZE).zip(decoded.chunks(INT4_BLOCK_SIZE)) {
+            let absmax = block.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+            for (a, b) in block.iter().zip(decoded) {
+                assert!((a - b).abs() <= absmax / 14.0 * 1.01, "{} vs {}", a, b);
+            }
+        }
+    }
 }
@@ -0,0 +1,251 @@
+// Sentence and word segmentation shared by chunking and retrieval
+
+/// Abbreviations whose trailing period does not end a sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "vs", "etc", "e.g", "i.e", "cf",
+    "al", "approx", "fig", "no", "vol", "inc", "ltd", "co", "corp", "jan", "feb", "mar", "apr",
+    "jun", "jul", "aug", "sep", "sept", "oct", "nov", "dec",
+];
+
+/// Split text into sentences
+///
+/// Returns `(start, end, sentence)` with byte offsets into `text`, so
+/// `&text[start..end] == sentence`. Surrounding whitespace is excluded.
+/// A sentence ends at `.`, `!` or `?` followed by whitespace (or the end of
+/// the text), and at blank lines. Periods inside numbers ("3.50"), after
+/// common abbreviations ("Dr.", "e.g.") and after single-letter initials
+/// do not end a sentence.
+pub fn split_sentences(text: &str) -> Vec<(usize, usize, &str)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+
+        let boundary = match c {
+            '.' | '!' | '?' => {
+                // Absorb runs like "?!" or "..." and closing quotes/brackets
+                let mut j = i + 1;
+                while j < chars.len()
+                    && matches!(chars[j].1, '.' | '!' | '?' | '"' | '\'' | ')' | ']' | '\u{201d}')
+                {
+                    j += 1;
+                }
+                let followed_by_space = j == chars.len() || chars[j].1.is_whitespace();
+                if followed_by_space && (c != '.' || !is_abbreviation(&text[..pos])) {
+                    i = j;
+                    Some(chars.get(j).map_or(text.len(), |&(p, _)| p))
+                } else {
+                    None
+                }
+            }
+            '\n' if matches!(chars.get(i + 1), Some((_, '\n'))) => Some(pos),
+            _ => None,
+        };
+
+        if let Some(end) = boundary {
+            push_trimmed(text, start, end, &mut sentences);
+            start = end;
+        }
+        i += 1;
+    }
+
+    push_trimmed(text, start, text.len(), &mut sentences);
+    sentences
+}
+
+/// Split text into words
+///
+/// Returns `(start, end, word)` with byte offsets into `text`. Words are
+/// runs of alphanumeric characters; apostrophes, hyphens and periods are
+/// kept when they join two alphanumerics ("don't", "well-known", "3.50").
+pub fn split_words(text: &str) -> Vec<(usize, usize, &str)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut words = Vec::new();
+    let mut word_start: Option<usize> = None;
+
+    for (i, &(pos, c)) in chars.iter().enumerate() {
+        let joiner = matches!(c, '\'' | '\u{2019}' | '-' | '.')
+            && word_start.is_some()
+            && chars.get(i + 1).is_some_and(|(_, next)| next.is_alphanumeric());
+
+        if c.is_alphanumeric() || joiner {
+            word_start.get_or_insert(pos);
+        } else if let Some(start) = word_start.take() {
+            words.push((start, pos, &text[start..pos]));
+        }
+    }
+
+    if let Some(start) = word_start {
+        words.push((start, text.len(), &text[start..]));
+    }
+
+    words
+}
+
+/// Remove control characters and normalize line endings
+///
+/// `\r\n` and lone `\r` become `\n`; other control characters except
+/// `\n`, `\t` and form feed (the page separator used by
+/// `strip_repeated_lines`) are dropped, as is a leading byte-order mark.
+pub fn sanitize(text: &str) -> String {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut sanitized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                sanitized.push('\n');
+            }
+            '\n' | '\t' | '\x0c' => sanitized.push(c),
+            c if c.is_control() => {}
+            c => sanitized.push(c),
+        }
+    }
+
+    sanitized
+}
+
+/// Converts between byte and character offsets of a string
+pub struct CharOffsets {
+    /// Byte offset of each character, followed by the text length
+    bounds: Vec<usize>,
+}
+
+impl CharOffsets {
+    /// Index the character boundaries of `text`
+    pub fn new(text: &str) -> Self {
+        let bounds = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        Self { bounds }
+    }
+
+    /// Number of characters
+    pub fn char_len(&self) -> usize {
+        self.bounds.len() - 1
+    }
+
+    /// Byte offset of character `char_idx` (clamped to the text length)
+    pub fn to_byte(&self, char_idx: usize) -> usize {
+        self.bounds[char_idx.min(self.char_len())]
+    }
+
+    /// Character index containing byte offset `byte_idx`
+    pub fn to_char(&self, byte_idx: usize) -> usize {
+        match self.bounds.binary_search(&byte_idx) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+    }
+}
+
+/// Whether the word ending at `prefix` (just before a period) is an
+/// abbreviation or initial
+fn is_abbreviation(prefix: &str) -> bool {
+    let word = prefix
+        .rsplit(|c: char| c.is_whitespace())
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(|c: char| !c.is_alphanumeric());
+
+    if word.chars().count() == 1 && word.chars().all(char::is_alphabetic) {
+        return true;
+    }
+
+    let word = word.to_lowercase();
+    ABBREVIATIONS.contains(&word.as_str())
+}
+
+fn push_trimmed<'a>(
+    text: &'a str,
+    start: usize,
+    end: usize,
+    sentences: &mut Vec<(usize, usize, &'a str)>,
+) {
+    let slice = &text[start..end];
+    let trimmed = slice.trim_start();
+    let start = start + (slice.len() - trimmed.len());
+    let trimmed = trimmed.trim_end();
+    if !trimmed.is_empty() {
+        sentences.push((start, start + trimmed.len(), trimmed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence_texts(text: &str) -> Vec<&str> {
+        split_sentences(text).into_iter().map(|(_, _, s)| s).collect()
+    }
+
+    #[test]
+    fn test_abbreviations_and_decimals() {
+        assert_eq!(
+            sentence_texts("Dr. Smith paid $3.50. Next."),
+            vec!["Dr. Smith paid $3.50.", "Next."]
+        );
+        assert_eq!(
+            sentence_texts("Use a fruit, e.g. an apple. J. R. R. Tolkien agreed!"),
+            vec!["Use a fruit, e.g. an apple.", "J. R. R. Tolkien agreed!"]
+        );
+    }
+
+    #[test]
+    fn test_sentence_offsets() {
+        let text = "  Really?! Yes.\n\nNew paragraph without period\nstill going";
+        let sentences = split_sentences(text);
+
+        for &(start, end, sentence) in &sentences {
+            assert_eq!(&text[start..end], sentence);
+        }
+        assert_eq!(
+            sentence_texts(text),
+            vec![
+                "Really?!",
+                "Yes.",
+                "New paragraph without period\nstill going"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_words() {
+        let text = "Don't over-think it: 3.50 café.";
+        let words: Vec<&str> = split_words(text).into_iter().map(|(_, _, w)| w).collect();
+        assert_eq!(words, vec!["Don't", "over-think", "it", "3.50", "café"]);
+
+        for (start, end, word) in split_words(text) {
+            assert_eq!(&text[start..end], word);
+        }
+    }
+
+    #[test]
+    fn test_char_offsets() {
+        let text = "a日本b";
+        let offsets = CharOffsets::new(text);
+
+        assert_eq!(offsets.char_len(), 4);
+        assert_eq!(offsets.to_byte(2), 4);
+        assert_eq!(offsets.to_byte(10), text.len());
+        assert_eq!(offsets.to_char(4), 2);
+        assert_eq!(offsets.to_char(text.len()), 4);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize("\u{feff}line one\0\r\nline\ttwo\rthree\x07\x0cpage"),
+            "line one\nline\ttwo\nthree\x0cpage"
+        );
+    }
+}
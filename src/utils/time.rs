@@ -0,0 +1,128 @@
+// ISO 8601 timestamps for chunk and document metadata
+
+use super::now_ms;
+
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+/// Current time as an ISO 8601 UTC string (e.g. `2025-01-01T12:30:00Z`)
+pub fn current_timestamp() -> String {
+    format_timestamp(now_ms())
+}
+
+/// Format milliseconds since the Unix epoch as an ISO 8601 UTC string
+pub fn format_timestamp(ms: f64) -> String {
+    let secs = (ms / 1000.0).floor() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parse an ISO 8601 date or date-time into milliseconds since the epoch
+///
+/// Accepts `YYYY-MM-DD` and `YYYY-MM-DDTHH:MM[:SS[.fff]]` with an optional
+/// `Z` or `±HH:MM` offset (no offset means UTC).
+pub fn parse_timestamp(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut ms = days_from_civil(year, month, day) as f64 * MS_PER_DAY;
+    if let Some(time) = time {
+        let (clock, offset_minutes) = split_offset(time)?;
+        let mut fields = clock.splitn(3, ':');
+        let hours: f64 = fields.next()?.parse().ok()?;
+        let minutes: f64 = fields.next()?.parse().ok()?;
+        let seconds: f64 = fields.next().map_or(Some(0.0), |s| s.parse().ok())?;
+        ms += ((hours * 60.0 + minutes - offset_minutes) * 60.0 + seconds) * 1000.0;
+    }
+    Some(ms)
+}
+
+/// Days (fractional) between two millisecond timestamps
+pub fn days_between(earlier_ms: f64, later_ms: f64) -> f64 {
+    (later_ms - earlier_ms) / MS_PER_DAY
+}
+
+/// Split a time into the clock part and its UTC offset in minutes
+fn split_offset(time: &str) -> Option<(&str, f64)> {
+    if let Some(clock) = time.strip_suffix('Z') {
+        return Some((clock, 0.0));
+    }
+    match time.rfind(['+', '-']) {
+        Some(i) => {
+            let sign = if time[i..].starts_with('-') { -1.0 } else { 1.0 };
+            let (hours, minutes) = time[i + 1..].split_once(':').unwrap_or((&time[i + 1..], "0"));
+            let offset = hours.parse::<f64>().ok()? * 60.0 + minutes.parse::<f64>().ok()?;
+            Some((&time[..i], sign * offset))
+        }
+        None => Some((time, 0.0)),
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(format_timestamp(0.0), "1970-01-01T00:00:00Z");
+
+        let ms = parse_timestamp("2024-02-29T13:45:30Z").unwrap();
+        assert_eq!(ms, 1_709_214_330_000.0);
+        assert_eq!(format_timestamp(ms), "2024-02-29T13:45:30Z");
+
+        // Offsets, fractional seconds and bare dates
+        assert_eq!(parse_timestamp("2024-02-29T15:45:30.5+02:00"), Some(ms + 500.0));
+        assert_eq!(parse_timestamp("2024-02-29"), Some(1_709_164_800_000.0));
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(parse_timestamp("2024-13-01"), None);
+
+        assert!(parse_timestamp(&current_timestamp()).is_some());
+    }
+}